@@ -23,6 +23,8 @@ layout (location = 2) in vec3 vNormal;
 out vec4 FragColor;
 
 layout (location = 5) uniform sampler2D uTexture;
+// 1.0 is opaque, less lets the scene shine through, e.g. for a ghost car.
+layout (location = 6) uniform float uAlpha;
 
 void main() {
     vec3 normal = normalize(vNormal);
@@ -39,5 +41,5 @@ void main() {
 
     // Specular lighting will come soon, maybe :D
     // I like the comic style
-    FragColor = vec4((ambient + diffuse) * texture(uTexture, vUV).rgb, 1.0);
+    FragColor = vec4((ambient + diffuse) * texture(uTexture, vUV).rgb, uAlpha);
 }
\ No newline at end of file
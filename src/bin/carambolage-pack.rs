@@ -0,0 +1,95 @@
+// This file is part of Carambolage.
+
+// Carambolage is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Carambolage is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Carambolage.  If not, see <http://www.gnu.org/licenses/>.
+use carambolage::util::Pack;
+use getopts::{Matches, Options};
+use std::env;
+use std::path::PathBuf;
+use std::process;
+
+/// What the pack tool should do.
+#[derive(Debug, PartialEq)]
+enum Command {
+    /// Pack all files below the directory into the pack file.
+    Create(PathBuf, PathBuf),
+    /// Print the files in a pack.
+    List(PathBuf),
+}
+
+/// Build a pack file from a resource directory, e.g. `res.pack` from `res`.
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let opts = get_options();
+    let matches = match opts.parse(&args[1..]) {
+        Ok(m) => m,
+        Err(f) => panic!(f.to_string()),
+    };
+    if matches.opt_present("help") {
+        print!("{}", opts.usage(&format!("Usage: {} [options] [DIR]", args[0])));
+        return;
+    }
+
+    let result = match match_options(&matches) {
+        Command::Create(dir, output) => Pack::create(&dir, &output).map(|count| {
+            println!("Packed {} files from {} into {}", count, dir.display(), output.display());
+        }),
+        Command::List(file) => Pack::open(&file).map(|pack| {
+            for file in pack.files() {
+                println!("{}", file);
+            }
+        }),
+    };
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
+
+fn get_options() -> Options {
+    let mut opts = Options::new();
+    opts.optopt("o", "output", "write the pack to FILE [default: DIR.pack]", "FILE");
+    opts.optopt("l", "list", "print the files in the pack FILE", "FILE");
+    opts.optflag("", "help", "print this help");
+    opts
+}
+
+fn match_options(matches: &Matches) -> Command {
+    if let Some(file) = matches.opt_str("l") {
+        return Command::List(PathBuf::from(file));
+    }
+    let dir = PathBuf::from(matches.free.first().map_or("res", String::as_str));
+    let output = matches.opt_str("o").map_or_else(|| dir.with_extension("pack"), PathBuf::from);
+    Command::Create(dir, output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{get_options, match_options, Command};
+    use std::path::PathBuf;
+
+    fn command(args: &[&str]) -> Command {
+        let matches = get_options().parse(args).unwrap();
+        match_options(&matches)
+    }
+
+    #[test]
+    fn arguments() {
+        assert_eq!(command(&[]), Command::Create(PathBuf::from("res"), PathBuf::from("res.pack")));
+        assert_eq!(
+            command(&["mods", "-o", "dist/mods.pack"]),
+            Command::Create(PathBuf::from("mods"), PathBuf::from("dist/mods.pack"))
+        );
+        assert_eq!(command(&["--list", "res.pack"]), Command::List(PathBuf::from("res.pack")));
+    }
+}
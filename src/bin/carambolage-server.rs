@@ -0,0 +1,160 @@
+// This file is part of Carambolage.
+
+// Carambolage is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Carambolage is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Carambolage.  If not, see <http://www.gnu.org/licenses/>.
+use simplelog::*;
+
+use carambolage::net;
+use carambolage::net::dedicated::{self, ServerSettings};
+use carambolage::util::RES_ENV;
+use getopts::{Matches, Options};
+use std::env;
+use std::fs::File;
+
+/// Dedicated server for network games, without window, graphics or local players.
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let opts = get_options();
+    let matches = match opts.parse(&args[1..]) {
+        Ok(m) => m,
+        Err(f) => panic!(f.to_string()),
+    };
+    if matches.opt_present("help") {
+        print!("{}", opts.usage(&format!("Usage: {} [options]", args[0])));
+        return;
+    }
+    if let Some(res) = matches.opt_str("res") {
+        env::set_var(RES_ENV, res);
+    }
+    let settings = match_options(&matches);
+
+    let terminal_log_config = Config {
+        time: Some(Level::Error),
+        target: Some(Level::Debug),
+        ..Default::default()
+    };
+    let write_log_config = Config {
+        time: Some(Level::Error),
+        target: Some(Level::Debug),
+        ..Default::default()
+    };
+    CombinedLogger::init(vec![
+        TermLogger::new(LevelFilter::Info, terminal_log_config).unwrap(),
+        WriteLogger::new(
+            LevelFilter::Debug,
+            write_log_config,
+            File::create("carambolage-server.log").unwrap(),
+        ),
+    ])
+    .unwrap();
+
+    dedicated::run(settings);
+}
+
+fn get_options() -> Options {
+    let mut opts = Options::new();
+    opts.optopt(
+        "a",
+        "address",
+        "listen on ADDRESS or port [default: all interfaces, port 7878]",
+        "ADDRESS",
+    );
+    opts.optopt("m", "maps", "play these maps in turn, e.g. race_track_1,race_track_2", "MAPS");
+    opts.optopt("t", "tick-rate", "set simulation steps per second", "HZ");
+    opts.optopt("", "min-players", "start a race once this many players joined", "PLAYERS");
+    opts.optopt("", "lobby-time", "give players SECONDS in the lobby before a race", "SECONDS");
+    opts.optopt(
+        "",
+        "time-limit",
+        "end a race after SECONDS even if not every car finished",
+        "SECONDS",
+    );
+    opts.optopt("", "races", "stop after this many races [default: run forever]", "RACES");
+    opts.optopt("r", "results", "append the standings of every race to FILE", "FILE");
+    opts.optopt("", "res", "load resources from DIR, e.g. /usr/share/carambolage/res", "DIR");
+    opts.optflag("", "help", "print this help");
+    opts
+}
+
+fn match_options(matches: &Matches) -> ServerSettings {
+    let mut settings: ServerSettings = Default::default();
+    if let Some(address) = matches.opt_str("a") {
+        settings.address = net::host_address(&address);
+    }
+    if let Some(maps) = matches.opt_str("m") {
+        settings.maps = maps.split(',').map(|map| map.trim().to_string()).collect();
+    }
+    if matches.opt_str("t").is_some() {
+        settings.tick_rate = matches.opt_str("t").unwrap().parse().unwrap();
+    }
+    if matches.opt_str("min-players").is_some() {
+        settings.min_players = matches.opt_str("min-players").unwrap().parse().unwrap();
+    }
+    if matches.opt_str("lobby-time").is_some() {
+        settings.lobby_time = matches.opt_str("lobby-time").unwrap().parse().unwrap();
+    }
+    if matches.opt_str("time-limit").is_some() {
+        settings.time_limit = matches.opt_str("time-limit").unwrap().parse().unwrap();
+    }
+    if matches.opt_str("races").is_some() {
+        settings.races = Some(matches.opt_str("races").unwrap().parse().unwrap());
+    }
+    if let Some(results) = matches.opt_str("r") {
+        settings.results = results;
+    }
+    settings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{get_options, match_options};
+
+    #[test]
+    fn arguments() {
+        let args: Vec<String> = vec![
+            String::from("./carambolage-server"),
+            String::from("-a"),
+            String::from("9000"),
+            String::from("-m"),
+            String::from("race_track_2, race_track_1"),
+            String::from("-t"),
+            String::from("120"),
+            String::from("--min-players"),
+            String::from("2"),
+            String::from("--lobby-time"),
+            String::from("30"),
+            String::from("--time-limit"),
+            String::from("600"),
+            String::from("--races"),
+            String::from("8"),
+            String::from("-r"),
+            String::from("tournament.log"),
+        ];
+        let opts = get_options();
+        let matches = match opts.parse(&args[1..]) {
+            Ok(m) => m,
+            Err(f) => panic!(f.to_string()),
+        };
+
+        let settings = match_options(&matches);
+
+        assert_eq!(settings.address, "0.0.0.0:9000");
+        assert_eq!(settings.maps, vec!["race_track_2", "race_track_1"]);
+        assert_eq!(settings.tick_rate, 120);
+        assert_eq!(settings.min_players, 2);
+        assert_eq!(settings.lobby_time, 30.0);
+        assert_eq!(settings.time_limit, 600.0);
+        assert_eq!(settings.races, Some(8));
+        assert_eq!(settings.results, "tournament.log");
+    }
+}
@@ -0,0 +1,84 @@
+// This file is part of Carambolage.
+
+// Carambolage is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Carambolage is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Carambolage.  If not, see <http://www.gnu.org/licenses/>.
+use image::ImageError;
+use std::error;
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+/// Why a file of the game could not be loaded.
+#[derive(Debug)]
+pub enum Error {
+    /// The file could not be opened or read.
+    Io(PathBuf, io::Error),
+    /// The image could not be decoded.
+    Image(PathBuf, ImageError),
+    /// The obj file could not be parsed.
+    Obj(PathBuf, tobj::LoadError),
+    /// The gltf or glb file could not be parsed, or its buffers or images could not be loaded.
+    Gltf(PathBuf, gltf::Error),
+    /// The shader program called `name` did not compile or link, with the log of the driver.
+    Shader(String, String),
+    /// The file was read, but its content can not be used, e.g. a model without any mesh.
+    Invalid(PathBuf, String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(path, e) => write!(f, "Failed to read {}: {}", path.display(), e),
+            Error::Image(path, e) => write!(f, "Failed to load image {}: {}", path.display(), e),
+            Error::Obj(path, e) => write!(f, "Failed to load model {}: {:?}", path.display(), e),
+            Error::Gltf(path, e) => write!(f, "Failed to load model {}: {}", path.display(), e),
+            Error::Shader(name, log) => write!(f, "Failed to compile shader {}: {}", name, log),
+            Error::Invalid(path, reason) => write!(f, "Invalid file {}: {}", path.display(), reason),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::Io(_, e) => Some(e),
+            Error::Image(_, e) => Some(e),
+            Error::Gltf(_, e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// Most of the game reports errors as text, so an asset error can be passed on with `?`.
+impl From<Error> for String {
+    fn from(error: Error) -> String {
+        error.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Error;
+    use std::io;
+    use std::path::PathBuf;
+
+    #[test]
+    fn message_names_file() {
+        let error = Error::Io(
+            PathBuf::from("res/textures/missing.png"),
+            io::Error::new(io::ErrorKind::NotFound, "not found"),
+        );
+        let message: String = error.into();
+        assert_eq!(message, "Failed to read res/textures/missing.png: not found");
+    }
+}
@@ -0,0 +1,258 @@
+// This file is part of Carambolage.
+
+// Carambolage is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Carambolage is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Carambolage.  If not, see <http://www.gnu.org/licenses/>.
+use super::car::Car;
+use super::controller::Controller;
+use nalgebra::{clamp, Vector3};
+
+/// Waypoints closer than this in [m] count as reached.
+const WAYPOINT_RADIUS: f32 = 2.5;
+/// Other cars in a corridor of this length and half width in [m] ahead are avoided.
+const AVOID_DISTANCE: f32 = 8.0;
+const AVOID_HALF_WIDTH: f32 = 2.0;
+/// Slower cars straight ahead closer than this in [m] make the driver brake.
+const BRAKE_DISTANCE: f32 = 3.0;
+/// A driver slower than this in [m/s] at full throttle is stuck.
+const STUCK_SPEED: f32 = 1.0;
+/// Seconds a driver has to be stuck before backing up.
+const STUCK_TIME: f32 = 1.0;
+/// Seconds a stuck driver backs up.
+const REVERSE_TIME: f32 = 1.2;
+
+/// How well a computer driver drives.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Difficulty {
+    Easy,
+    Medium,
+    Hard,
+}
+
+impl Difficulty {
+    pub const ALL: [Difficulty; 3] = [Difficulty::Easy, Difficulty::Medium, Difficulty::Hard];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Difficulty::Easy => "easy",
+            Difficulty::Medium => "medium",
+            Difficulty::Hard => "hard",
+        }
+    }
+
+    /// Parse the `name()` of a difficulty.
+    pub fn parse(name: &str) -> Result<Difficulty, String> {
+        Self::ALL.iter().cloned().find(|d| d.name() == name).ok_or_else(|| {
+            let names: Vec<&str> = Self::ALL.iter().map(|d| d.name()).collect();
+            format!("Unknown difficulty '{}', expected one of {}", name, names.join(", "))
+        })
+    }
+
+    /// Fraction of the top speed driven on straights.
+    fn pace(self) -> f32 {
+        match self {
+            Difficulty::Easy => 0.65,
+            Difficulty::Medium => 0.8,
+            Difficulty::Hard => 1.0,
+        }
+    }
+
+    /// Fraction of the top speed driven through a hairpin.
+    fn corner_pace(self) -> f32 {
+        match self {
+            Difficulty::Easy => 0.3,
+            Difficulty::Medium => 0.4,
+            Difficulty::Hard => 0.5,
+        }
+    }
+
+    /// Fraction of the boost tank kept as reserve, `None` never boosts.
+    fn boost_reserve(self) -> Option<f32> {
+        match self {
+            Difficulty::Easy => None,
+            Difficulty::Medium => Some(0.6),
+            Difficulty::Hard => Some(0.2),
+        }
+    }
+}
+
+/// Computer driver of a single car.
+///
+/// The driver follows the racing line of the level from waypoint to waypoint and produces the
+/// same inputs a player would with a `Controller`. It slows down before corners, steers around
+/// cars in front of it, boosts on straights and backs up when it got stuck at a wall.
+#[derive(Debug, Copy, Clone)]
+pub struct AiDriver {
+    pub difficulty: Difficulty,
+    /// Index of the next waypoint, `None` until the driver looked for the closest one.
+    waypoint: Option<usize>,
+    stuck_time: f32,
+    reverse_time: f32,
+}
+
+impl AiDriver {
+    pub fn new(difficulty: Difficulty) -> AiDriver {
+        AiDriver {
+            difficulty,
+            waypoint: None,
+            stuck_time: 0.0,
+            reverse_time: 0.0,
+        }
+    }
+
+    /// Forget the driving state, e.g. when the car is put back on the start grid.
+    pub fn reset(&mut self) {
+        *self = AiDriver::new(self.difficulty);
+    }
+
+    /// Input for car `id` of `cars` along the waypoints of `line` for the next step of `dt`.
+    pub fn drive(&mut self, id: usize, cars: &[Car], line: &[Vector3<f32>], dt: f32) -> Controller {
+        let car = &cars[id];
+        if line.is_empty() {
+            return Controller::with_state(0., 0., false);
+        }
+        let n = line.len();
+        let position = planar(car.body.position);
+        let forward = car.body.forward();
+        let right = car.body.right();
+        let speed = car.body.velocity.dot(&forward);
+
+        // Skip all waypoints that are reached or already behind us.
+        let mut waypoint = self.waypoint.unwrap_or_else(|| closest(line, &position));
+        for _ in 0..n {
+            let to_target = line[waypoint] - position;
+            let segment = line[waypoint] - line[(waypoint + n - 1) % n];
+            if to_target.norm() < WAYPOINT_RADIUS || to_target.dot(&segment) < 0.0 {
+                waypoint = (waypoint + 1) % n;
+            } else {
+                break;
+            }
+        }
+        self.waypoint = Some(waypoint);
+        let target = line[waypoint];
+        let next = line[(waypoint + 1) % n];
+        let distance = (target - position).norm();
+
+        // Steer towards the waypoint, right is positive.
+        let to_target = target - position;
+        let angle = to_target.dot(&right).atan2(to_target.dot(&forward));
+        let mut steer = clamp(angle * 2.0, -1.0, 1.0);
+
+        // 0.0 for a straight, 1.0 for a hairpin at the waypoint.
+        let sharpness = match ((target - position).try_normalize(1e-3), (next - target).try_normalize(1e-3)) {
+            (Some(into), Some(out)) => (1.0 - into.dot(&out)) * 0.5,
+            _ => 0.0,
+        };
+        let stats = car.stats;
+        let difficulty = self.difficulty;
+        let straight_speed = stats.top_speed * difficulty.pace();
+        let corner_speed = stats.top_speed * (difficulty.pace() - (difficulty.pace() - difficulty.corner_pace()) * sharpness);
+        // Brake early enough to get from the current speed down to the corner speed.
+        let braking_distance = WAYPOINT_RADIUS + (speed * speed - corner_speed * corner_speed).max(0.0) / stats.acceleration;
+        let mut goal_speed = if distance < braking_distance {
+            corner_speed
+        } else {
+            straight_speed
+        };
+        // Turning around needs a slow car.
+        goal_speed *= clamp(1.0 - angle.abs() / std::f32::consts::PI, 0.3, 1.0);
+
+        let boost = match difficulty.boost_reserve() {
+            Some(reserve) => car.boost_fraction() > reserve && angle.abs() < 0.15 && sharpness < 0.1 && distance > braking_distance + speed,
+            None => false,
+        };
+        if boost {
+            goal_speed = stats.boost_speed;
+        }
+        let mut accel = clamp((goal_speed - speed) * 2.0, -1.0, 1.0);
+
+        // Steer around cars in front and do not ram slower ones.
+        for (other_id, other) in cars.iter().enumerate() {
+            if other_id == id {
+                continue;
+            }
+            let offset = planar(other.body.position) - position;
+            let (ahead, side) = (offset.dot(&forward), offset.dot(&right));
+            if ahead > 0.0 && ahead < AVOID_DISTANCE && side.abs() < AVOID_HALF_WIDTH {
+                steer -= side.signum() * (1.0 - ahead / AVOID_DISTANCE) * 0.4;
+                if ahead < BRAKE_DISTANCE && side.abs() < AVOID_HALF_WIDTH * 0.5 && other.body.velocity.dot(&forward) < speed {
+                    accel = accel.min(0.0);
+                }
+            }
+        }
+        let steer = clamp(steer, -1.0, 1.0);
+
+        // Back up with the steering reversed to get away from a wall.
+        if self.reverse_time > 0.0 {
+            self.reverse_time -= dt;
+            return Controller::with_state(-steer, -1.0, false);
+        }
+        if accel > 0.5 && speed < STUCK_SPEED {
+            self.stuck_time += dt;
+            if self.stuck_time > STUCK_TIME {
+                self.stuck_time = 0.0;
+                self.reverse_time = REVERSE_TIME;
+            }
+        } else {
+            self.stuck_time = 0.0;
+        }
+
+        Controller::with_state(steer, accel, boost)
+    }
+}
+
+/// Project `v` onto the ground plane.
+fn planar(v: Vector3<f32>) -> Vector3<f32> {
+    Vector3::new(v[0], v[1], 0.)
+}
+
+/// Index of the waypoint of `line` closest to `position`.
+fn closest(line: &[Vector3<f32>], position: &Vector3<f32>) -> usize {
+    let distance = |i: &usize| (line[*i] - position).norm();
+    (0..line.len())
+        .min_by(|a, b| distance(a).partial_cmp(&distance(b)).unwrap_or(std::cmp::Ordering::Equal))
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Difficulty;
+    use crate::game::car_definition::CarSelection;
+    use crate::game::scene::Scene;
+
+    #[test]
+    fn parse_difficulty() {
+        for &difficulty in Difficulty::ALL.iter() {
+            assert_eq!(Difficulty::parse(difficulty.name()), Ok(difficulty));
+        }
+        assert!(Difficulty::parse("impossible").is_err());
+    }
+
+    #[test]
+    fn drive_a_lap() {
+        let cars = [CarSelection::new("kart", None), CarSelection::new("formula", None)];
+        let mut scene = Scene::new("race_track_1", &cars).unwrap();
+        for id in 0..scene.cars.len() {
+            scene.set_driver(id, Some(Difficulty::Hard));
+        }
+
+        let dt = 1.0 / 60.0;
+        for _ in 0..75 * 60 {
+            let input = scene.input(dt, &[]);
+            scene.update(dt, &input);
+        }
+        for id in 0..scene.cars.len() {
+            assert!(scene.race.lap(id) >= 2, "car {} is still in lap {}", id, scene.race.lap(id));
+            assert!(!scene.race.progress(id).wrong_way);
+        }
+    }
+}
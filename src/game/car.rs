@@ -12,129 +12,352 @@
 
 // You should have received a copy of the GNU General Public License
 // along with Foobar.  If not, see <http://www.gnu.org/licenses/>.
+use super::car_definition::{CarDefinition, CarStats};
 use super::controller::Controller;
-use crate::grphx::Model;
-use log::debug;
-use ncollide3d::shape::Cuboid;
+use crate::grphx::{get_min_max, load_model, ModelData};
+use crate::physx::RigidBody;
+use crate::util::Lerp;
+use log::{debug, warn};
 
-use nalgebra::{clamp, zero, Matrix4, Vector3};
+use nalgebra::{clamp, Matrix4, Vector3};
+
+/// Hits changing the speed of a car by less than this in [m/s] do no damage, e.g. scratching
+/// along the border.
+const DAMAGE_THRESHOLD: f32 = 2.0;
+/// Damage per [m/s] of speed change above the threshold.
+const DAMAGE_PER_SPEED: f32 = 4.0;
+/// Fraction of top speed and steering left on a totally damaged side.
+const MIN_PERFORMANCE: f32 = 0.5;
+/// Mass factor of a car with the `Mass` power-up.
+const HEAVY_MASS: f32 = 2.5;
+/// Fraction of tire grip left on oil.
+const OIL_GRIP: f32 = 0.15;
+
+/// Sides of a car that are damaged separately.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Side {
+    Front = 0,
+    Rear = 1,
+    Left = 2,
+    Right = 3,
+}
+
+impl Side {
+    pub const ALL: [Side; 4] = [Side::Front, Side::Rear, Side::Left, Side::Right];
+}
+
+/// Power-ups active on a car, each the time left in [s], see `Pickups`.
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub struct Effects {
+    /// Collisions do no damage.
+    pub shield: f32,
+    /// The car is heavier and pushes others around.
+    pub heavy: f32,
+    /// The tires slip on oil.
+    pub oil: f32,
+}
+
+impl Effects {
+    /// Count down all effects by a time step `dt`.
+    fn update(&mut self, dt: f32) {
+        self.shield = (self.shield - dt).max(0.0);
+        self.heavy = (self.heavy - dt).max(0.0);
+        self.oil = (self.oil - dt).max(0.0);
+    }
+}
 
 /// A GameObject controlled by a player.
 pub struct Car {
-    pub position: Vector3<f32>, // position in world space
-    pub rotation: Vector3<f32>, // rotation in radians per axis
-    _velocity: Vector3<f32>,
-    _force: Vector3<f32>,
-    _mass: f32,
+    pub body: RigidBody,
     pub boost: f32,
+    /// Health of every side, indexed by `Side`, from `stats.durability` down to 0.0.
+    pub health: [f32; 4],
+    pub effects: Effects,
+    /// Handling from the car definition.
+    pub stats: CarStats,
+
+    /// Model and color palette file names, used to create the render model.
+    pub model: String,
+    pub palette: String,
 
-    pub model: Model,
-    pub cuboid: Cuboid<f32>,
+    // Body state before the last simulation step for render interpolation.
+    previous_position: Vector3<f32>,
+    previous_rotation: Vector3<f32>,
 }
 
 impl Car {
-    /// Create a new `Car`.
-    ///
-    /// For `model` and `color_palette` see `model_from_id()` and `color_from_id()`.
-    /// `mass` is the mass of the car in [kg]
-    pub fn new(model: &str, color_palette: &str, mass: f32) -> Car {
-        debug!("New({}, {}, {})", model, color_palette, mass);
+    /// Create a new `Car` from its `definition` painted with `palette`.
+    pub fn new(definition: &CarDefinition, palette: &str) -> Car {
+        debug!("New({}, {})", definition.name, palette);
+        let stats = definition.stats;
 
-        let model = Model::new(model, color_palette);
-        let (min, max) = model.get_min_max();
-        let cuboid = Cuboid::new((max - min) * 0.25);
+        // Only the vertices are needed for the collision box, no need for an OpenGL context. A
+        // broken model gets the box of the placeholder it is drawn with.
+        let model = load_model(&definition.model).unwrap_or_else(|e| {
+            warn!("{}, using a placeholder", e);
+            ModelData::placeholder()
+        });
+        let (min, max) = get_min_max(model.vertices());
+        let body = RigidBody::new(stats.mass, (max - min) * 0.25);
 
         Car {
-            position: zero(),
-            rotation: zero(),
-            _velocity: zero(),
-            _force: zero(),
-            _mass: mass,
-            boost: 100.0,
-            model,
-            cuboid,
+            body,
+            boost: stats.boost_capacity,
+            health: [stats.durability; 4],
+            effects: Default::default(),
+            stats,
+            model: definition.model.clone(),
+            palette: palette.to_string(),
+            previous_position: body.position,
+            previous_rotation: body.rotation,
         }
     }
 
-    /// Update the car position and velocity based on the internal car state for
-    /// a given time step.
+    /// Apply engine, tire and steering forces to the car body for a given time step.
+    ///
+    /// The body itself is integrated by the `Scene`. A car without a controller keeps
+    /// rolling and slows down due to rolling resistance and tire grip, so does a wreck.
+    /// Nothing happens without time passing, `dt` has to be positive.
     pub(super) fn update(&mut self, dt: f32, controller: Option<Controller>) {
-        if controller.is_some() {
-            let ct = controller.unwrap();
-
-            // accel:  0.0 - None
-            //         1.0 - Pedal to the metal
-            //        -1.0 - Emergency brake
-            let accel = ct.get_y_axis();
-            // steer:  0.0 - Forward
-            //         1.0 - Full right
-            //        -1.0 - Full left
-            // * accel to prevent steering a non moving car.
-            let steer = ct.get_x_axis() * accel;
-
-            // x,y-axis rotation are fixed to 0. No rollovers!
-            self.rotation[2] -= steer * dt * 3.5;
-
-            let rot_mat = Matrix4::new_rotation(self.rotation);
-            let mut forward = Vector3::new(0f32, 1., 0.).to_homogeneous();
-            forward = rot_mat * forward;
-            // Set homogeneous coordinate to 0 or unwrap() will panic.
-            forward[3] = 0.;
-
-            let booster = if ct.get_boost() {
-                self.boost = clamp(self.boost - dt * 30.0, 0.0, 100.0);
-                if self.boost > 0.1 {
-                    14.0
-                } else {
-                    10.0
-                }
-            } else {
-                self.boost = clamp(self.boost + dt * 14.0, 0.0, 100.0);
-                10.0
-            };
+        // The grip forces divide by dt.
+        if dt <= 0.0 {
+            return;
+        }
 
-            self.position += Vector3::from_homogeneous(forward).unwrap() * accel * booster * dt;
+        // accel:  0.0 - None
+        //         1.0 - Pedal to the metal
+        //        -1.0 - Emergency brake
+        // steer:  0.0 - Forward
+        //         1.0 - Full right
+        //        -1.0 - Full left
+        let (accel, steer, is_boost) = match controller {
+            Some(ct) if !self.is_wrecked() => (ct.get_y_axis(), ct.get_x_axis(), ct.get_boost()),
+            _ => (0.0, 0.0, false),
+        };
+
+        self.effects.update(dt);
+        let mass = if self.effects.heavy > 0.0 {
+            self.stats.mass * HEAVY_MASS
+        } else {
+            self.stats.mass
+        };
+        if (self.body.mass() - mass).abs() > 1e-6 {
+            let half_extents = self.body.half_extents;
+            self.body.set_mass(mass, half_extents);
         }
-    }
 
-    /// Draw the car to the currently bound framebuffer.
-    pub(super) fn draw(&self, view: &Matrix4<f32>, projection: &Matrix4<f32>) {
+        // A damaged engine lowers the top speed, damaged wheels make steering sluggish.
+        let mut stats = self.stats;
+        let engine = self.health_fraction(Side::Front).min(self.health_fraction(Side::Rear));
+        let wheels = (self.health_fraction(Side::Left) + self.health_fraction(Side::Right)) * 0.5;
+        stats.top_speed *= f32::lerp(&MIN_PERFORMANCE, &1.0, engine);
+        stats.boost_speed *= f32::lerp(&MIN_PERFORMANCE, &1.0, engine);
+        stats.steering *= f32::lerp(&MIN_PERFORMANCE, &1.0, wheels);
+        if self.effects.oil > 0.0 {
+            stats.grip *= OIL_GRIP;
+            stats.steering_grip *= OIL_GRIP;
+        }
+
+        let top_speed = if is_boost {
+            self.boost = clamp(self.boost - dt * stats.boost_drain, 0.0, stats.boost_capacity);
+            if self.boost > 0.1 {
+                stats.boost_speed
+            } else {
+                stats.top_speed
+            }
+        } else {
+            self.boost = clamp(self.boost + dt * stats.boost_recharge, 0.0, stats.boost_capacity);
+            stats.top_speed
+        };
+
+        let mass = self.body.mass();
+        let forward = self.body.forward();
+        let right = self.body.right();
+        let forward_speed = self.body.velocity.dot(&forward);
+        let side_speed = self.body.velocity.dot(&right);
+
+        // Engine and drag. The drag is chosen so that full throttle ends up at `top_speed`.
+        let drag = stats.acceleration / top_speed;
+        let engine = forward * accel * stats.acceleration * mass;
+        let mut planar_velocity = self.body.velocity;
+        planar_velocity[2] = 0.0;
+        self.body.apply_force(&(engine - planar_velocity * drag * mass));
+
+        // Tires do not like to slide sideways. Never remove more than the actual sliding speed.
+        let grip = (stats.grip * dt).min(1.0);
+        self.body.apply_force(&(-right * side_speed * grip * mass / dt));
+
+        // The faster we drive the better we can steer. Reverse driving inverts the steering.
         // x,y-axis rotation are fixed to 0. No rollovers!
-        let rotation = Matrix4::from_euler_angles(0., 0., self.rotation[2]);
-        let translation = Matrix4::new_translation(&self.position);
-        let model = translation * rotation * Matrix4::new_scaling(0.5f32);
-        self.model.draw(&model, view, projection);
+        let yaw_rate_goal = -steer * stats.steering * clamp(forward_speed / stats.top_speed, -1.0, 1.0);
+        let steering_grip = (stats.steering_grip * dt).min(1.0);
+        let yaw_rate_delta = yaw_rate_goal - self.body.angular_velocity[2];
+        self.body.apply_torque(yaw_rate_delta * steering_grip * self.body.inertia() / dt);
     }
 
-    /// Return a `Car` file name from an id.
+    /// Damage the side hit at `point` by an `impulse` in [Ns] from a collision.
     ///
-    /// 1:kart 2:parsche 3:farara 4:lamba 5:gtc1 6:gtc2 7:formula
-    pub fn model_from_id(id: u32) -> String {
-        match id {
-            1 => String::from("c01.obj"),
-            2 => String::from("c02.obj"),
-            3 => String::from("c03.obj"),
-            4 => String::from("c04.obj"),
-            5 => String::from("c05.obj"),
-            6 => String::from("c06.obj"),
-            7 => String::from("c07.obj"),
-            _ => String::from("c01.obj"),
+    /// Only the horizontal part counts, landing on the ground does not hurt. Heavier cars take
+    /// less damage from the same impulse, shielded cars none.
+    pub fn hit(&mut self, point: &Vector3<f32>, impulse: &Vector3<f32>) {
+        let speed_change = Vector3::new(impulse[0], impulse[1], 0.0).norm() * self.body.inv_mass();
+        if speed_change <= DAMAGE_THRESHOLD || self.is_wrecked() || self.effects.shield > 0.0 {
+            return;
+        }
+        let side = self.side_at(point) as usize;
+        self.health[side] = (self.health[side] - (speed_change - DAMAGE_THRESHOLD) * DAMAGE_PER_SPEED).max(0.0);
+    }
+
+    /// Side of the car closest to the world space `point`.
+    pub fn side_at(&self, point: &Vector3<f32>) -> Side {
+        let offset = point - self.body.position;
+        let half_extents = self.body.half_extents;
+        let forward = offset.dot(&self.body.forward()) / half_extents[1];
+        let right = offset.dot(&self.body.right()) / half_extents[0];
+        if forward.abs() >= right.abs() {
+            if forward >= 0.0 {
+                Side::Front
+            } else {
+                Side::Rear
+            }
+        } else if right >= 0.0 {
+            Side::Right
+        } else {
+            Side::Left
         }
     }
 
-    /// Return a `Car` color palette file name from an id.
+    /// Health left on `side` as a fraction of a new car in [0.0; 1.0].
+    pub fn health_fraction(&self, side: Side) -> f32 {
+        self.health[side as usize] / self.stats.durability
+    }
+
+    /// Health left on the most damaged side, see `health_fraction()`.
+    pub fn weakest_health_fraction(&self) -> f32 {
+        Side::ALL.iter().map(|&side| self.health_fraction(side)).fold(1.0, f32::min)
+    }
+
+    /// A car with a destroyed side ignores its controller until it is repaired.
+    pub fn is_wrecked(&self) -> bool {
+        self.health.iter().any(|&health| health <= 0.0)
+    }
+
+    /// Undo all damage.
+    pub fn repair(&mut self) {
+        self.health = [self.stats.durability; 4];
+    }
+
+    /// Remember the current body state before it is changed by the next simulation step.
+    pub(super) fn store_previous(&mut self) {
+        self.previous_position = self.body.position;
+        self.previous_rotation = self.body.rotation;
+    }
+
+    /// Position before the last simulation step.
+    pub fn previous_position(&self) -> Vector3<f32> {
+        self.previous_position
+    }
+
+    /// Position between the previous (`alpha` = 0.0) and the current (`alpha` = 1.0) simulation step.
+    pub fn interpolated_position(&self, alpha: f32) -> Vector3<f32> {
+        Vector3::lerp(&self.previous_position, &self.body.position, alpha)
+    }
+
+    /// Rotation between the previous (`alpha` = 0.0) and the current (`alpha` = 1.0) simulation step.
+    pub fn interpolated_rotation(&self, alpha: f32) -> Vector3<f32> {
+        Vector3::lerp(&self.previous_rotation, &self.body.rotation, alpha)
+    }
+
+    /// Boost left as a fraction of a full tank in [0.0; 1.0].
+    pub fn boost_fraction(&self) -> f32 {
+        self.boost / self.stats.boost_capacity
+    }
+
+    /// Model matrix for rendering.
     ///
-    /// 1:blue 2:green 3:lime 4:orange 5:purple 6:red 7:yellow
-    pub fn color_from_id(id: u32) -> String {
-        match id {
-            1 => String::from("car-blue.png"),
-            2 => String::from("car-green.png"),
-            3 => String::from("car-lime.png"),
-            4 => String::from("car-orange.png"),
-            5 => String::from("car-purple.png"),
-            6 => String::from("car-red.png"),
-            7 => String::from("car-yellow.png"),
-            _ => String::from("car-blue.png"),
+    /// `alpha` interpolates between the previous and the current simulation step.
+    pub fn model_matrix(&self, alpha: f32) -> Matrix4<f32> {
+        Self::placement_matrix(&self.interpolated_position(alpha), self.interpolated_rotation(alpha)[2])
+    }
+
+    /// Model matrix of a car at `position` rotated by `yaw` around the z-axis, e.g. for a ghost car.
+    pub fn placement_matrix(position: &Vector3<f32>, yaw: f32) -> Matrix4<f32> {
+        // x,y-axis rotation are fixed to 0. No rollovers!
+        let rotation = Matrix4::from_euler_angles(0., 0., yaw);
+        let translation = Matrix4::new_translation(position);
+        translation * rotation * Matrix4::new_scaling(0.5f32)
+    }
+}
+
+#[cfg(test)]
+impl Car {
+    /// A kart in its first palette, the car used by the tests of the game modules.
+    pub(crate) fn test_kart() -> Car {
+        let definition = CarDefinition::find("kart").unwrap();
+        Car::new(&definition, &definition.palettes[0])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Car, Side};
+    use crate::game::controller::Controller;
+    use nalgebra::Vector3;
+
+    fn drive(car: &mut Car, ticks: usize) {
+        for _ in 0..ticks {
+            car.update(1.0 / 60.0, Some(Controller::with_state(0.0, 1.0, false)));
+            car.body.integrate(1.0 / 60.0);
+            // There is no ground to stand on.
+            car.body.position[2] = 0.0;
+            car.body.velocity[2] = 0.0;
+        }
+    }
+
+    #[test]
+    fn damage_and_wreck() {
+        let mut car = Car::test_kart();
+        car.body.position = Vector3::new(0.0, 0.0, 0.0);
+        let front = car.body.position + car.body.forward();
+        let left = car.body.position - car.body.right() * 0.5;
+        assert_eq!(car.side_at(&front), Side::Front);
+        assert_eq!(car.side_at(&left), Side::Left);
+
+        // Scratches and landing do no harm.
+        car.hit(&front, &Vector3::new(0.0, -0.5, 0.0));
+        car.hit(&front, &Vector3::new(0.0, 0.0, 20.0));
+        assert_eq!(car.health_fraction(Side::Front), 1.0);
+
+        let mut healthy = Car::test_kart();
+        drive(&mut healthy, 600);
+        car.hit(&front, &Vector3::new(0.0, -5.0, 0.0));
+        let damaged = car.health_fraction(Side::Front);
+        assert!(damaged > 0.0 && damaged < 1.0);
+        assert_eq!(car.health_fraction(Side::Rear), 1.0);
+        drive(&mut car, 600);
+        assert!(car.body.velocity.norm() < healthy.body.velocity.norm());
+
+        car.hit(&left, &Vector3::new(100.0, 0.0, 0.0));
+        assert!(car.is_wrecked());
+        let position = car.body.position;
+        car.body.velocity = Vector3::new(0.0, 0.0, 0.0);
+        drive(&mut car, 60);
+        assert!((car.body.position - position).norm() < 1e-3);
+
+        car.repair();
+        assert!(!car.is_wrecked());
+        assert_eq!(car.health_fraction(Side::Front), 1.0);
+    }
+
+    #[test]
+    fn no_time_step() {
+        let mut car = Car::test_kart();
+        drive(&mut car, 60);
+        for &dt in &[0.0, -1.0 / 60.0] {
+            car.update(dt, Some(Controller::with_state(1.0, 1.0, true)));
+            car.body.integrate(1.0 / 60.0);
+            assert!(car.body.velocity.iter().all(|v| v.is_finite()));
+            assert!(car.body.angular_velocity.iter().all(|v| v.is_finite()));
         }
     }
 }
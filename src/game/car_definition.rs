@@ -0,0 +1,274 @@
+// This file is part of Carambolage.
+
+// Carambolage is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Carambolage is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Carambolage.  If not, see <http://www.gnu.org/licenses/>.
+use crate::util::{find_resource, resource_names, Resource};
+use serde_derive::{Deserialize, Serialize};
+use std::fmt;
+
+/// Every `*.toml` file in this directory of the resources describes a car.
+const CARS_DIR: &str = "cars";
+
+/// Description of a car, loaded from `res/cars/<name>.toml`.
+///
+/// ```toml
+/// name = "Kart"
+/// model = "c01.obj"
+/// palettes = ["car-blue.png", "car-red.png"]
+///
+/// [stats]
+/// top_speed = 9.0
+/// steering = 4.0
+/// ```
+/// The model is relative to `res/models`, the palettes are relative to `res/textures`.
+/// All stats are optional and default to a well balanced car.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CarDefinition {
+    /// Display name of the car.
+    pub name: String,
+    pub model: String,
+    /// Color palettes the car is available in.
+    pub palettes: Vec<String>,
+    #[serde(default)]
+    pub stats: CarStats,
+}
+
+/// Handling of a car.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CarStats {
+    /// Mass in [kg], heavier cars push lighter ones around.
+    pub mass: f32,
+    /// Top speed in [m/s] without boost.
+    pub top_speed: f32,
+    /// Top speed in [m/s] while boosting.
+    pub boost_speed: f32,
+    /// Acceleration in [m/s²] at full throttle from standstill.
+    pub acceleration: f32,
+    /// Maximum yaw rate in [rad/s] at top speed.
+    pub steering: f32,
+    /// How fast the tires cancel sideways sliding in [1/s].
+    pub grip: f32,
+    /// How fast the tires force the car into the steered yaw rate in [1/s].
+    pub steering_grip: f32,
+    /// Boost of a full tank.
+    pub boost_capacity: f32,
+    /// Boost used per second while boosting.
+    pub boost_drain: f32,
+    /// Boost regained per second while not boosting.
+    pub boost_recharge: f32,
+    /// Health of every side of the car, collisions damage it.
+    pub durability: f32,
+}
+
+/// A car and its color chosen by a player.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CarSelection {
+    /// Name of the car definition, see `CarDefinition::find()`.
+    pub car: String,
+    /// Index into `CarDefinition::palettes`, `None` picks a different color for every player.
+    pub palette: Option<usize>,
+}
+
+impl Default for CarStats {
+    fn default() -> CarStats {
+        CarStats {
+            mass: 1.0,
+            top_speed: 10.0,
+            boost_speed: 14.0,
+            acceleration: 8.0,
+            steering: 3.5,
+            grip: 8.0,
+            steering_grip: 6.0,
+            boost_capacity: 100.0,
+            boost_drain: 30.0,
+            boost_recharge: 14.0,
+            durability: 100.0,
+        }
+    }
+}
+
+impl CarSelection {
+    pub fn new(car: &str, palette: Option<usize>) -> CarSelection {
+        CarSelection {
+            car: car.to_string(),
+            palette,
+        }
+    }
+
+    /// Parse a comma separated list of `car` or `car:palette`, e.g. `kart,formula:3`.
+    pub fn parse_list(text: &str) -> Result<Vec<CarSelection>, String> {
+        text.split(',')
+            .map(|item| {
+                let mut fields = item.trim().splitn(2, ':');
+                let car = fields.next().unwrap_or("");
+                if car.is_empty() {
+                    return Err(format!("Missing car name in '{}'", text));
+                }
+                let palette = match fields.next() {
+                    Some(palette) => Some(palette.parse().map_err(|_| format!("Invalid palette '{}' for {}", palette, car))?),
+                    None => None,
+                };
+                Ok(CarSelection::new(car, palette))
+            })
+            .collect()
+    }
+}
+
+/// The inverse of `CarSelection::parse_list()` for a single car.
+impl fmt::Display for CarSelection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.palette {
+            Some(palette) => write!(f, "{}:{}", self.car, palette),
+            None => write!(f, "{}", self.car),
+        }
+    }
+}
+
+impl CarDefinition {
+    /// Names of all cars in `res/cars` and its overrides, sorted alphabetically.
+    pub fn available() -> Vec<String> {
+        resource_names(CARS_DIR, "toml")
+    }
+
+    /// Load the definition of the car called `name`.
+    ///
+    /// Only names of available cars are looked up, so a name like "../cars/kart" is unknown.
+    pub fn find(name: &str) -> Result<CarDefinition, String> {
+        let available = Self::available();
+        let unknown = || format!("Unknown car '{}', available cars: {}", name, available.join(", "));
+        if !available.iter().any(|car| car == name) {
+            return Err(unknown());
+        }
+        let file = find_resource(&format!("{}/{}.toml", CARS_DIR, name)).ok_or_else(unknown)?;
+        Self::load(&file)
+    }
+
+    /// Load a definition from a loose or packed file.
+    pub fn load(file: &Resource) -> Result<CarDefinition, String> {
+        let text = file.read_to_string().map_err(|e| format!("Failed to read {}: {}", file, e))?;
+        Self::parse(&text).map_err(|e| format!("{}: {}", file, e))
+    }
+
+    /// Parse and validate a definition.
+    pub fn parse(text: &str) -> Result<CarDefinition, String> {
+        let definition: CarDefinition = toml::from_str(text).map_err(|e| e.to_string())?;
+        definition.validate()?;
+        Ok(definition)
+    }
+
+    /// Palette file for a `CarSelection::palette` of player `id`.
+    pub fn palette(&self, index: Option<usize>, id: usize) -> Result<&str, String> {
+        // Stepping by 5 through the 7 stock palettes starts with blue and red for the first
+        // two players, like before there were car definitions.
+        let index = index.unwrap_or(id * 5 % self.palettes.len());
+        match self.palettes.get(index) {
+            Some(palette) => Ok(palette),
+            None => Err(format!("{} has no palette {}, only {}", self.name, index, self.palettes.len())),
+        }
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.palettes.is_empty() {
+            return Err("palettes: at least one palette is needed".to_string());
+        }
+        let stats = &self.stats;
+        let positive = [
+            ("mass", stats.mass),
+            ("top_speed", stats.top_speed),
+            ("boost_speed", stats.boost_speed),
+            ("acceleration", stats.acceleration),
+            ("steering", stats.steering),
+            ("grip", stats.grip),
+            ("steering_grip", stats.steering_grip),
+            ("boost_capacity", stats.boost_capacity),
+            ("durability", stats.durability),
+        ];
+        for (name, value) in &positive {
+            if !(value.is_finite() && *value > 0.0) {
+                return Err(format!("stats: {} must be positive", name));
+            }
+        }
+        let non_negative = [stats.boost_drain, stats.boost_recharge];
+        if non_negative.iter().any(|value| !(value.is_finite() && *value >= 0.0)) {
+            return Err("stats: boost_drain and boost_recharge must not be negative".to_string());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CarDefinition, CarSelection, CarStats};
+
+    const MINIMAL: &str = r#"
+        name = "Test"
+        model = "c01.obj"
+        palettes = ["car-blue.png", "car-red.png"]
+    "#;
+
+    #[test]
+    fn parse_definition() {
+        let definition = CarDefinition::parse(MINIMAL).unwrap();
+        assert_eq!(definition.model, "c01.obj");
+        assert_eq!(definition.stats, CarStats::default());
+        assert_eq!(definition.palette(Some(1), 0), Ok("car-red.png"));
+        assert!(definition.palette(Some(2), 0).is_err());
+        assert_eq!(definition.palette(None, 0), Ok("car-blue.png"));
+        assert_eq!(definition.palette(None, 1), Ok("car-red.png"));
+
+        let definition = CarDefinition::parse(&format!("{}\n[stats]\nmass = 2.0\ntop_speed = 12", MINIMAL)).unwrap();
+        assert_eq!(definition.stats.mass, 2.0);
+        assert_eq!(definition.stats.top_speed, 12.0);
+        assert_eq!(definition.stats.steering, CarStats::default().steering);
+    }
+
+    #[test]
+    fn malformed_definition() {
+        assert!(CarDefinition::parse("name = \"Test\"\nmodel = \"c01.obj\"").is_err());
+        assert!(CarDefinition::parse(&MINIMAL.replace("[\"car-blue.png\", \"car-red.png\"]", "[]")).is_err());
+        assert!(CarDefinition::parse(&format!("{}\n[stats]\nmass = 0.0", MINIMAL)).is_err());
+        assert!(CarDefinition::parse(&format!("{}\n[stats]\nboost_drain = -1.0", MINIMAL)).is_err());
+        assert!(CarDefinition::parse(&format!("{}\n[stats]\ndurability = 0.0", MINIMAL)).is_err());
+        assert!(CarDefinition::parse(&format!("{}\n[stats]\ntop_speed = \"fast\"", MINIMAL)).is_err());
+    }
+
+    #[test]
+    fn non_finite_stats() {
+        assert!(CarDefinition::parse(&format!("{}\n[stats]\nmass = nan", MINIMAL)).is_err());
+        assert!(CarDefinition::parse(&format!("{}\n[stats]\ntop_speed = inf", MINIMAL)).is_err());
+        assert!(CarDefinition::parse(&format!("{}\n[stats]\nboost_drain = nan", MINIMAL)).is_err());
+        assert!(CarDefinition::parse(&format!("{}\n[stats]\nboost_recharge = inf", MINIMAL)).is_err());
+    }
+
+    #[test]
+    fn parse_selection() {
+        assert_eq!(
+            CarSelection::parse_list("kart, formula:3").unwrap(),
+            vec![CarSelection::new("kart", None), CarSelection::new("formula", Some(3))]
+        );
+        assert!(CarSelection::parse_list("kart,").is_err());
+        assert!(CarSelection::parse_list("kart:red").is_err());
+        assert_eq!(CarSelection::new("formula", Some(3)).to_string(), "formula:3");
+    }
+
+    #[test]
+    fn shipped_cars() {
+        let cars = CarDefinition::available();
+        assert!(cars.len() >= 2);
+        for car in &cars {
+            CarDefinition::find(car).unwrap();
+        }
+        assert!(CarDefinition::find("no_such_car").is_err());
+    }
+}
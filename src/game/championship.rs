@@ -0,0 +1,327 @@
+// This file is part of Carambolage.
+
+// Carambolage is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Carambolage is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Carambolage.  If not, see <http://www.gnu.org/licenses/>.
+use super::scene::Scene;
+use crate::util::{find_resource, resource_names, Resource};
+use serde_derive::{Deserialize, Serialize};
+use std::cmp::Ordering;
+
+/// Every `*.toml` file in this directory of the resources describes a championship.
+const CHAMPIONSHIPS_DIR: &str = "championships";
+
+/// Levels and points of a championship, loaded from `res/championships/<name>.toml`.
+///
+/// ```toml
+/// name = "Grand Prix"
+/// description = "Two races with a derby in between."
+/// levels = ["race_track_1", "arena_1", "race_track_2"]
+/// points = [10, 8, 6, 5, 4, 3, 2, 1]
+/// ```
+/// The levels are played in order, a race on race tracks and a derby on arenas. The first car of
+/// a level gets the first points, the second car the second and so on. Cars behind the end of the
+/// list and cars that did not finish a race get nothing. The description and the points are
+/// optional.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChampionshipManifest {
+    /// Display name of the championship.
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    /// Names of the levels in playing order, see `LevelManifest`.
+    pub levels: Vec<String>,
+    /// Points for the first, second, ... place of every level.
+    #[serde(default = "default_points")]
+    pub points: Vec<u32>,
+}
+
+fn default_points() -> Vec<u32> {
+    vec![10, 8, 6, 5, 4, 3, 2, 1]
+}
+
+impl Default for ChampionshipManifest {
+    fn default() -> ChampionshipManifest {
+        ChampionshipManifest {
+            name: "Custom".to_string(),
+            description: String::new(),
+            levels: vec!["race_track_1".to_string(), "race_track_2".to_string()],
+            points: default_points(),
+        }
+    }
+}
+
+impl ChampionshipManifest {
+    /// Names of all championships in `res/championships` and its overrides, sorted alphabetically.
+    pub fn available() -> Vec<String> {
+        resource_names(CHAMPIONSHIPS_DIR, "toml")
+    }
+
+    /// Load the championship called `name`.
+    ///
+    /// Only names of available championships are looked up, so a name like "../cars/kart" is
+    /// unknown.
+    pub fn find(name: &str) -> Result<ChampionshipManifest, String> {
+        let available = Self::available();
+        let unknown = || format!("Unknown championship '{}', available championships: {}", name, available.join(", "));
+        if !available.iter().any(|championship| championship == name) {
+            return Err(unknown());
+        }
+        let file = find_resource(&format!("{}/{}.toml", CHAMPIONSHIPS_DIR, name)).ok_or_else(unknown)?;
+        Self::load(&file)
+    }
+
+    /// Load a championship from a loose or packed file.
+    pub fn load(file: &Resource) -> Result<ChampionshipManifest, String> {
+        let text = file.read_to_string().map_err(|e| format!("Failed to read {}: {}", file, e))?;
+        Self::parse(&text).map_err(|e| format!("{}: {}", file, e))
+    }
+
+    /// Parse and validate a championship.
+    pub fn parse(text: &str) -> Result<ChampionshipManifest, String> {
+        let manifest: ChampionshipManifest = toml::from_str(text).map_err(|e| e.to_string())?;
+        if manifest.levels.is_empty() {
+            return Err("levels: at least one level is needed".to_string());
+        }
+        if manifest.points.windows(2).any(|pair| pair[0] < pair[1]) {
+            return Err("points: a place can not get more points than the place before".to_string());
+        }
+        Ok(manifest)
+    }
+}
+
+/// A line of the championship standings table.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ChampionshipStanding {
+    /// Index of the car in `Scene::cars`.
+    pub car: usize,
+    pub points: u32,
+    /// Levels won.
+    pub wins: u32,
+}
+
+/// Points a car got on the last level.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct RoundResult {
+    /// Index of the car in `Scene::cars`.
+    pub car: usize,
+    pub points: u32,
+    /// The car finished the race, always true in a derby.
+    pub is_finished: bool,
+}
+
+/// Standings of a championship across all of its levels.
+///
+/// Once the race or derby of a level is over, every car gets the points of its place. Between
+/// two levels the scoreboard shows the result, `next_round()` then moves on to the next level.
+pub struct Championship {
+    manifest: ChampionshipManifest,
+    /// Index of the current level in the manifest.
+    round: usize,
+    /// Points and wins of every car.
+    points: Vec<u32>,
+    wins: Vec<u32>,
+    /// Result of the current level in finishing order, set once it is over.
+    result: Option<Vec<RoundResult>>,
+}
+
+impl Championship {
+    pub fn new(manifest: ChampionshipManifest) -> Championship {
+        Championship {
+            manifest,
+            round: 0,
+            points: Vec::new(),
+            wins: Vec::new(),
+            result: None,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.manifest.name
+    }
+
+    /// Name of the level of the current round.
+    pub fn level(&self) -> &str {
+        &self.manifest.levels[self.round]
+    }
+
+    /// Current round from 1 to `rounds()`.
+    pub fn round(&self) -> usize {
+        self.round + 1
+    }
+
+    /// Number of levels.
+    pub fn rounds(&self) -> usize {
+        self.manifest.levels.len()
+    }
+
+    /// Result of the current level, `None` while it is still played.
+    pub fn result(&self) -> Option<&[RoundResult]> {
+        self.result.as_ref().map(|result| result.as_slice())
+    }
+
+    /// The current level is over, the scoreboard is shown.
+    pub fn is_round_over(&self) -> bool {
+        self.result.is_some()
+    }
+
+    /// The last level is over.
+    pub fn is_over(&self) -> bool {
+        self.is_round_over() && self.round + 1 >= self.rounds()
+    }
+
+    /// Award the points once the race or derby of the `scene` is over.
+    pub fn update(&mut self, scene: &Scene) {
+        if self.result.is_some() {
+            return;
+        }
+        // Cars in finishing order, whether they finished at all.
+        let order: Vec<(usize, bool)> = if scene.derby.is_active() {
+            if !scene.derby.is_over() {
+                return;
+            }
+            scene.derby.standings().iter().map(|standing| (standing.car, true)).collect()
+        } else {
+            if !scene.race.is_over() {
+                return;
+            }
+            scene
+                .race
+                .standings()
+                .iter()
+                .map(|standing| (standing.car, standing.finish_time.is_some()))
+                .collect()
+        };
+        self.award(&order);
+    }
+
+    /// Give every car in `order` the points of its place, if it finished.
+    fn award(&mut self, order: &[(usize, bool)]) {
+        let cars = order.iter().map(|&(car, _)| car + 1).max().unwrap_or(0);
+        if self.points.len() < cars {
+            self.points.resize(cars, 0);
+            self.wins.resize(cars, 0);
+        }
+        let result = order
+            .iter()
+            .enumerate()
+            .map(|(place, &(car, is_finished))| {
+                let points = if is_finished {
+                    self.manifest.points.get(place).cloned().unwrap_or(0)
+                } else {
+                    0
+                };
+                self.points[car] += points;
+                if place == 0 && is_finished {
+                    self.wins[car] += 1;
+                }
+                RoundResult { car, points, is_finished }
+            })
+            .collect();
+        self.result = Some(result);
+    }
+
+    /// Move on to the next level after the scoreboard, returns its name or `None` after the last.
+    pub fn next_round(&mut self) -> Option<&str> {
+        if !self.is_round_over() || self.is_over() {
+            return None;
+        }
+        self.round += 1;
+        self.result = None;
+        Some(self.level())
+    }
+
+    /// Ranking of all cars by points, then by levels won.
+    pub fn standings(&self) -> Vec<ChampionshipStanding> {
+        let mut standings: Vec<ChampionshipStanding> = self
+            .points
+            .iter()
+            .zip(&self.wins)
+            .enumerate()
+            .map(|(car, (&points, &wins))| ChampionshipStanding { car, points, wins })
+            .collect();
+        standings.sort_by(|a, b| match b.points.cmp(&a.points) {
+            Ordering::Equal => b.wins.cmp(&a.wins),
+            order => order,
+        });
+        standings
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Championship, ChampionshipManifest};
+    use crate::game::car_definition::CarSelection;
+    use crate::game::scene::Scene;
+
+    fn manifest() -> ChampionshipManifest {
+        ChampionshipManifest::parse("name = \"Test\"\nlevels = [\"race_track_1\", \"arena_1\"]\npoints = [5, 3, 1]").unwrap()
+    }
+
+    #[test]
+    fn parse_championship() {
+        let manifest = manifest();
+        assert_eq!(manifest.levels, vec!["race_track_1", "arena_1"]);
+        assert_eq!(manifest.points, vec![5, 3, 1]);
+        let defaults = ChampionshipManifest::parse("name = \"Test\"\nlevels = [\"race_track_1\"]").unwrap();
+        assert_eq!(defaults.points, ChampionshipManifest::default().points);
+
+        assert!(ChampionshipManifest::parse("name = \"Test\"\nlevels = []").is_err());
+        assert!(ChampionshipManifest::parse("name = \"Test\"\nlevels = [\"race_track_1\"]\npoints = [1, 2]").is_err());
+        assert!(ChampionshipManifest::parse("levels = [\"race_track_1\"]").is_err());
+    }
+
+    #[test]
+    fn shipped_championships() {
+        for name in &ChampionshipManifest::available() {
+            let manifest = ChampionshipManifest::find(name).unwrap();
+            for level in &manifest.levels {
+                assert!(Scene::new(level, &[]).is_ok(), "{}: {}", name, level);
+            }
+        }
+        assert!(ChampionshipManifest::find("no_such_championship").is_err());
+    }
+
+    #[test]
+    fn award_points() {
+        let mut championship = Championship::new(manifest());
+        let cars = [CarSelection::new("kart", None), CarSelection::new("formula", None)];
+        let mut scene = Scene::new(championship.level(), &cars).unwrap();
+        championship.update(&scene);
+        assert!(!championship.is_round_over());
+        assert_eq!(championship.next_round(), None);
+
+        // Car 2 wins the race, car 1 is wrecked.
+        scene.race.set_progress(1, 7, Some(60.0));
+        scene.race.update(0.0, &scene.cars);
+        scene.cars[0].health = [0.0; 4];
+        scene.race.update(0.0, &scene.cars);
+        championship.update(&scene);
+        let result = championship.result().unwrap().to_vec();
+        assert_eq!((result[0].car, result[0].points), (1, 5));
+        assert_eq!((result[1].car, result[1].points, result[1].is_finished), (0, 0, false));
+        assert!(!championship.is_over());
+
+        // Points are only awarded once.
+        championship.update(&scene);
+        assert_eq!(championship.standings()[0].points, 5);
+
+        assert_eq!(championship.next_round(), Some("arena_1"));
+        assert_eq!(championship.round(), 2);
+        championship.award(&[(0, true), (1, true)]);
+        let standings = championship.standings();
+        assert_eq!((standings[0].car, standings[0].points, standings[0].wins), (1, 8, 1));
+        assert_eq!((standings[1].car, standings[1].points, standings[1].wins), (0, 5, 1));
+        assert!(championship.is_over());
+        assert_eq!(championship.next_round(), None);
+    }
+}
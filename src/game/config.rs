@@ -0,0 +1,152 @@
+// This file is part of Carambolage.
+
+// Carambolage is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Carambolage is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Carambolage.  If not, see <http://www.gnu.org/licenses/>.
+use super::controller::key_name;
+use super::controller::{GamepadSettings, KeyAction, KeyBindings};
+use crate::util::data_dir;
+use imgui_glfw_rs::glfw::Key;
+use serde_derive::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Name of the file with the user settings.
+pub const CONFIG_FILE: &str = "carambolage.toml";
+
+/// User settings are kept in the `data_dir()` of the user, next to the time trial records.
+pub fn config_file() -> PathBuf {
+    data_dir().join(CONFIG_FILE)
+}
+
+/// Settings of the user that survive a restart.
+///
+/// ```toml
+/// [[keyboard]]
+/// forward = "W"
+/// backward = "S"
+/// left = "A"
+/// right = "D"
+/// boost = "LeftShift"
+///
+/// [gamepad]
+/// dead_zone = 0.15
+/// ```
+/// Every `[[keyboard]]` table is a player on the keyboard, key names are listed in
+/// `controller::KEYS`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub keyboard: Vec<KeyBindings>,
+    pub gamepad: GamepadSettings,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            keyboard: vec![KeyBindings::wasd(), KeyBindings::arrows()],
+            gamepad: Default::default(),
+        }
+    }
+}
+
+impl Config {
+    /// Load the config from a file.
+    pub fn load(file: &Path) -> Result<Config, String> {
+        let text = fs::read_to_string(file).map_err(|e| format!("Failed to read {}: {}", file.display(), e))?;
+        Self::parse(&text).map_err(|e| format!("{}: {}", file.display(), e))
+    }
+
+    /// Parse and validate a config.
+    pub fn parse(text: &str) -> Result<Config, String> {
+        let config: Config = toml::from_str(text).map_err(|e| e.to_string())?;
+        for (player, keys) in config.keyboard.iter().enumerate() {
+            for &action in KeyAction::ALL.iter() {
+                if let Some((other, other_action)) = config.conflict(player, action, keys.get(action)) {
+                    return Err(format!(
+                        "keyboard: {} is bound to player {} {} and player {} {}",
+                        key_name::to_name(keys.get(action)),
+                        player + 1,
+                        action.name(),
+                        other + 1,
+                        other_action.name()
+                    ));
+                }
+            }
+        }
+        let gamepad = &config.gamepad;
+        for dead_zone in &[gamepad.dead_zone, gamepad.trigger_dead_zone] {
+            if *dead_zone < 0.0 || *dead_zone >= 1.0 {
+                return Err("gamepad: dead zones have to be in [0.0; 1.0)".to_string());
+            }
+        }
+        Ok(config)
+    }
+
+    /// Write the config to a file, creating its directory if needed.
+    pub fn save(&self, file: &Path) -> Result<(), String> {
+        let text = toml::to_string(self).map_err(|e| format!("Failed to write {}: {}", file.display(), e))?;
+        if let Some(dir) = file.parent() {
+            fs::create_dir_all(dir).map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+        }
+        fs::write(file, text).map_err(|e| format!("Failed to write {}: {}", file.display(), e))
+    }
+
+    /// Find another player and action already using `key`, that `action` of `player` would clash with.
+    pub fn conflict(&self, player: usize, action: KeyAction, key: Key) -> Option<(usize, KeyAction)> {
+        for (other, keys) in self.keyboard.iter().enumerate() {
+            for &other_action in KeyAction::ALL.iter() {
+                if (other, other_action) != (player, action) && keys.get(other_action) == key {
+                    return Some((other, other_action));
+                }
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Config;
+    use crate::game::controller::KeyAction;
+    use imgui_glfw_rs::glfw::Key;
+
+    #[test]
+    fn save_and_parse() {
+        let mut config = Config::default();
+        config.keyboard[1].set(KeyAction::Boost, Key::RightControl);
+        config.gamepad.dead_zone = 0.25;
+
+        let text = toml::to_string(&config).unwrap();
+        assert!(text.contains("boost = \"RightControl\""));
+        assert_eq!(Config::parse(&text).unwrap(), config);
+        assert_eq!(Config::parse("").unwrap(), Config::default());
+    }
+
+    #[test]
+    fn malformed_config() {
+        let keys = "[[keyboard]]\nforward = \"Z\"\nbackward = \"S\"\nleft = \"Q\"\nright = \"D\"\nboost = \"LeftShift\"\n";
+        assert!(Config::parse(keys).is_ok());
+        assert!(Config::parse(&keys.replace("\"Z\"", "\"Foo\"")).is_err());
+        assert!(Config::parse(&keys.replace("\"Z\"", "\"S\"")).is_err());
+        assert!(Config::parse("[gamepad]\ndead_zone = 1.0").is_err());
+    }
+
+    #[test]
+    fn conflicts() {
+        let config = Config::default();
+        assert_eq!(config.conflict(0, KeyAction::Boost, Key::LeftShift), None);
+        assert_eq!(config.conflict(0, KeyAction::Forward, Key::Up), Some((1, KeyAction::Forward)));
+        assert_eq!(config.conflict(1, KeyAction::Left, Key::Right), Some((1, KeyAction::Right)));
+        assert_eq!(config.conflict(0, KeyAction::Forward, Key::Space), None);
+    }
+}
@@ -16,44 +16,328 @@ use imgui_glfw_rs::glfw;
 
 use self::ControllerInternal as CI;
 use self::ControllerLayout as CL;
-use glfw::{Action, Key, Window};
+use glfw::{Action, Joystick, JoystickId, Key, Window};
 use log::debug;
-use nalgebra::{zero, Vector2};
+use nalgebra::{clamp, zero, Vector2};
+use serde_derive::{Deserialize, Serialize};
 
-/// Control with WASD or arrow keys.
+/// All joysticks GLFW can tell apart.
+pub const JOYSTICKS: [JoystickId; 16] = [
+    JoystickId::Joystick1,
+    JoystickId::Joystick2,
+    JoystickId::Joystick3,
+    JoystickId::Joystick4,
+    JoystickId::Joystick5,
+    JoystickId::Joystick6,
+    JoystickId::Joystick7,
+    JoystickId::Joystick8,
+    JoystickId::Joystick9,
+    JoystickId::Joystick10,
+    JoystickId::Joystick11,
+    JoystickId::Joystick12,
+    JoystickId::Joystick13,
+    JoystickId::Joystick14,
+    JoystickId::Joystick15,
+    JoystickId::Joystick16,
+];
+
+/// Control with keys or a gamepad.
 #[derive(Debug)]
 pub enum ControllerLayout {
-    WASD,
-    Arrows,
+    Keyboard(KeyBindings),
+    /// Left stick steers, the triggers are throttle and brake.
+    Gamepad(JoystickId),
+}
+
+/// Keys of a keyboard player.
+///
+/// Keys are stored by their name in the config file, e.g. `forward = "W"`.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct KeyBindings {
+    #[serde(with = "key_name")]
+    pub forward: Key,
+    #[serde(with = "key_name")]
+    pub backward: Key,
+    #[serde(with = "key_name")]
+    pub left: Key,
+    #[serde(with = "key_name")]
+    pub right: Key,
+    #[serde(with = "key_name")]
+    pub boost: Key,
+}
+
+/// Everything a key can be bound to.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum KeyAction {
+    Forward,
+    Backward,
+    Left,
+    Right,
+    Boost,
+}
+
+impl KeyAction {
+    pub const ALL: [KeyAction; 5] = [
+        KeyAction::Forward,
+        KeyAction::Backward,
+        KeyAction::Left,
+        KeyAction::Right,
+        KeyAction::Boost,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            KeyAction::Forward => "Forward",
+            KeyAction::Backward => "Backward",
+            KeyAction::Left => "Left",
+            KeyAction::Right => "Right",
+            KeyAction::Boost => "Boost",
+        }
+    }
+}
+
+impl KeyBindings {
+    pub fn wasd() -> KeyBindings {
+        KeyBindings {
+            forward: Key::W,
+            backward: Key::S,
+            left: Key::A,
+            right: Key::D,
+            boost: Key::LeftShift,
+        }
+    }
+
+    pub fn arrows() -> KeyBindings {
+        KeyBindings {
+            forward: Key::Up,
+            backward: Key::Down,
+            left: Key::Left,
+            right: Key::Right,
+            boost: Key::RightShift,
+        }
+    }
+
+    /// Key bound to `action`.
+    pub fn get(&self, action: KeyAction) -> Key {
+        match action {
+            KeyAction::Forward => self.forward,
+            KeyAction::Backward => self.backward,
+            KeyAction::Left => self.left,
+            KeyAction::Right => self.right,
+            KeyAction::Boost => self.boost,
+        }
+    }
+
+    /// Bind `key` to `action`.
+    pub fn set(&mut self, action: KeyAction, key: Key) {
+        match action {
+            KeyAction::Forward => self.forward = key,
+            KeyAction::Backward => self.backward = key,
+            KeyAction::Left => self.left = key,
+            KeyAction::Right => self.right = key,
+            KeyAction::Boost => self.boost = key,
+        }
+    }
+}
+
+/// All keys that can be bound, see `key_name`.
+pub const KEYS: [Key; 120] = [
+    Key::Space,
+    Key::Apostrophe,
+    Key::Comma,
+    Key::Minus,
+    Key::Period,
+    Key::Slash,
+    Key::Num0,
+    Key::Num1,
+    Key::Num2,
+    Key::Num3,
+    Key::Num4,
+    Key::Num5,
+    Key::Num6,
+    Key::Num7,
+    Key::Num8,
+    Key::Num9,
+    Key::Semicolon,
+    Key::Equal,
+    Key::A,
+    Key::B,
+    Key::C,
+    Key::D,
+    Key::E,
+    Key::F,
+    Key::G,
+    Key::H,
+    Key::I,
+    Key::J,
+    Key::K,
+    Key::L,
+    Key::M,
+    Key::N,
+    Key::O,
+    Key::P,
+    Key::Q,
+    Key::R,
+    Key::S,
+    Key::T,
+    Key::U,
+    Key::V,
+    Key::W,
+    Key::X,
+    Key::Y,
+    Key::Z,
+    Key::LeftBracket,
+    Key::Backslash,
+    Key::RightBracket,
+    Key::GraveAccent,
+    Key::World1,
+    Key::World2,
+    Key::Escape,
+    Key::Enter,
+    Key::Tab,
+    Key::Backspace,
+    Key::Insert,
+    Key::Delete,
+    Key::Right,
+    Key::Left,
+    Key::Down,
+    Key::Up,
+    Key::PageUp,
+    Key::PageDown,
+    Key::Home,
+    Key::End,
+    Key::CapsLock,
+    Key::ScrollLock,
+    Key::NumLock,
+    Key::PrintScreen,
+    Key::Pause,
+    Key::F1,
+    Key::F2,
+    Key::F3,
+    Key::F4,
+    Key::F5,
+    Key::F6,
+    Key::F7,
+    Key::F8,
+    Key::F9,
+    Key::F10,
+    Key::F11,
+    Key::F12,
+    Key::F13,
+    Key::F14,
+    Key::F15,
+    Key::F16,
+    Key::F17,
+    Key::F18,
+    Key::F19,
+    Key::F20,
+    Key::F21,
+    Key::F22,
+    Key::F23,
+    Key::F24,
+    Key::F25,
+    Key::Kp0,
+    Key::Kp1,
+    Key::Kp2,
+    Key::Kp3,
+    Key::Kp4,
+    Key::Kp5,
+    Key::Kp6,
+    Key::Kp7,
+    Key::Kp8,
+    Key::Kp9,
+    Key::KpDecimal,
+    Key::KpDivide,
+    Key::KpMultiply,
+    Key::KpSubtract,
+    Key::KpAdd,
+    Key::KpEnter,
+    Key::KpEqual,
+    Key::LeftShift,
+    Key::LeftControl,
+    Key::LeftAlt,
+    Key::LeftSuper,
+    Key::RightShift,
+    Key::RightControl,
+    Key::RightAlt,
+    Key::RightSuper,
+    Key::Menu,
+];
+
+/// Keys are named like their `glfw::Key` variant, independent of the keyboard layout.
+pub mod key_name {
+    use super::KEYS;
+    use imgui_glfw_rs::glfw::Key;
+    use serde::de::{Deserialize, Deserializer, Error};
+    use serde::ser::Serializer;
+
+    pub fn to_name(key: Key) -> String {
+        format!("{:?}", key)
+    }
+
+    pub fn from_name(name: &str) -> Option<Key> {
+        KEYS.iter().cloned().find(|&key| to_name(key) == name)
+    }
+
+    pub fn serialize<S: Serializer>(key: &Key, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&to_name(*key))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Key, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        from_name(&name).ok_or_else(|| D::Error::custom(format!("unknown key '{}'", name)))
+    }
+}
+
+/// Axis and button mapping and dead zones of all gamepads.
+///
+/// GLFW reports raw axes and buttons, their order depends on the pad and the platform. The
+/// defaults fit Xbox style pads.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GamepadSettings {
+    /// Stick deflection around the center that is ignored, in [0.0; 1.0).
+    pub dead_zone: f32,
+    /// Trigger travel that is ignored before throttle or brake engage, in [0.0; 1.0).
+    pub trigger_dead_zone: f32,
+    pub steer_axis: usize,
+    pub throttle_axis: usize,
+    pub brake_axis: usize,
+    pub boost_button: usize,
+}
+
+impl Default for GamepadSettings {
+    fn default() -> GamepadSettings {
+        GamepadSettings {
+            dead_zone: 0.15,
+            trigger_dead_zone: 0.05,
+            steer_axis: 0,
+            throttle_axis: 5,
+            // XInput reports the left trigger after the right stick, Linux before it.
+            brake_axis: if cfg!(windows) { 4 } else { 2 },
+            boost_button: 0,
+        }
+    }
 }
 
 #[derive(Copy, Clone, Debug)]
 struct ControllerInternal {
-    forward: Key,
+    keys: KeyBindings,
     is_forward: bool,
-    backward: Key,
     is_backward: bool,
-    left: Key,
     is_left: bool,
-    right: Key,
     is_right: bool,
-
-    boost: Key,
     is_boost: bool,
 }
 
 impl ControllerInternal {
     pub fn new(controller_layout: &ControllerLayout) -> ControllerInternal {
         match controller_layout {
-            CL::WASD => Default::default(),
-            CL::Arrows => CI {
-                forward: Key::Up,
-                backward: Key::Down,
-                left: Key::Left,
-                right: Key::Right,
-                boost: Key::RightShift,
+            CL::Keyboard(keys) => CI {
+                keys: *keys,
                 ..Default::default()
             },
+            CL::Gamepad(_) => Default::default(),
         }
     }
 }
@@ -61,26 +345,24 @@ impl ControllerInternal {
 impl Default for ControllerInternal {
     fn default() -> ControllerInternal {
         ControllerInternal {
-            forward: Key::W,
+            keys: KeyBindings::wasd(),
             is_forward: false,
-            backward: Key::S,
             is_backward: false,
-            left: Key::A,
             is_left: false,
-            right: Key::D,
             is_right: false,
-            boost: Key::LeftShift,
             is_boost: false,
         }
     }
 }
 
-/// Prototype controller emulation for keyboard users.
+/// Input of a single player, from the keyboard or a gamepad.
 #[derive(Copy, Clone, Debug)]
 pub struct Controller {
     /// Internal settings and flags.
     is_smooth: bool,
     ci: ControllerInternal,
+    gamepad: Option<JoystickId>,
+    pub gamepad_settings: GamepadSettings,
     axis_goal: Vector2<f32>,
 
     /// Buttons and input axis that can be used in the game.
@@ -93,51 +375,78 @@ impl Controller {
     /// by setting `smooth`to true.
     pub fn new(smooth: bool, controller_layout: &ControllerLayout) -> Controller {
         debug!("New smooth: {}, layout: {:?}", smooth, controller_layout);
+        let gamepad = match controller_layout {
+            CL::Gamepad(id) => Some(*id),
+            _ => None,
+        };
         Controller {
             is_smooth: smooth,
             ci: ControllerInternal::new(&controller_layout),
+            gamepad,
+            gamepad_settings: Default::default(),
             axis_goal: zero(),
             axis: zero(),
             boost: false,
         }
     }
 
-    /// Process input keys for this controller.
+    /// Create a controller with fixed axis values that does not react to any keys.
+    ///
+    /// This is used to feed scripted input into a `Scene`.
+    pub fn with_state(x_axis: f32, y_axis: f32, boost: bool) -> Controller {
+        let axis = Vector2::new(clamp(x_axis, -1.0, 1.0), clamp(y_axis, -1.0, 1.0));
+        Controller {
+            is_smooth: false,
+            ci: Default::default(),
+            gamepad: None,
+            gamepad_settings: Default::default(),
+            axis_goal: axis,
+            axis,
+            boost,
+        }
+    }
+
+    /// Process input keys or the gamepad of this controller.
     ///
     /// The input is handled from glfw::Window due to event polling.
     pub fn process_input(&mut self, window: &Window, dt: f32) {
-        if window.get_key(self.ci.forward) == Action::Press && !self.ci.is_forward {
+        if let Some(id) = self.gamepad {
+            self.process_gamepad(&window.glfw.get_joystick(id));
+            return;
+        }
+
+        if window.get_key(self.ci.keys.forward) == Action::Press && !self.ci.is_forward {
             self.set_y_axis(1.);
             self.ci.is_forward = true;
-        } else if window.get_key(self.ci.forward) == Action::Release && self.ci.is_forward {
+        } else if window.get_key(self.ci.keys.forward) == Action::Release && self.ci.is_forward {
             self.set_y_axis(0.);
             self.ci.is_forward = false;
         }
-        if window.get_key(self.ci.backward) == Action::Press && !self.ci.is_backward {
+        if window.get_key(self.ci.keys.backward) == Action::Press && !self.ci.is_backward {
             self.set_y_axis(-1.);
             self.ci.is_backward = true;
-        } else if window.get_key(self.ci.backward) == Action::Release && self.ci.is_backward {
+        } else if window.get_key(self.ci.keys.backward) == Action::Release && self.ci.is_backward {
             self.set_y_axis(0.);
             self.ci.is_backward = false;
         }
-        if window.get_key(self.ci.left) == Action::Press && !self.ci.is_left {
+        if window.get_key(self.ci.keys.left) == Action::Press && !self.ci.is_left {
             self.set_x_axis(-1.);
             self.ci.is_left = true;
-        } else if window.get_key(self.ci.left) == Action::Release && self.ci.is_left {
+        } else if window.get_key(self.ci.keys.left) == Action::Release && self.ci.is_left {
             self.set_x_axis(0.);
             self.ci.is_left = false;
         }
-        if window.get_key(self.ci.right) == Action::Press && !self.ci.is_right {
+        if window.get_key(self.ci.keys.right) == Action::Press && !self.ci.is_right {
             self.set_x_axis(1.);
             self.ci.is_right = true;
-        } else if window.get_key(self.ci.right) == Action::Release && self.ci.is_right {
+        } else if window.get_key(self.ci.keys.right) == Action::Release && self.ci.is_right {
             self.set_x_axis(0.);
             self.ci.is_right = false;
         }
-        if window.get_key(self.ci.boost) == Action::Press && !self.ci.is_boost {
+        if window.get_key(self.ci.keys.boost) == Action::Press && !self.ci.is_boost {
             self.boost = true;
             self.ci.is_boost = true;
-        } else if window.get_key(self.ci.boost) == Action::Release && self.ci.is_boost {
+        } else if window.get_key(self.ci.keys.boost) == Action::Release && self.ci.is_boost {
             self.boost = false;
             self.ci.is_boost = false;
         }
@@ -151,6 +460,46 @@ impl Controller {
         }
     }
 
+    /// Read the sticks, triggers and buttons of a gamepad.
+    ///
+    /// A disconnected gamepad lets go of everything, the car rolls out until it is back.
+    fn process_gamepad(&mut self, joystick: &Joystick) {
+        if !joystick.is_present() {
+            self.axis = zero();
+            self.axis_goal = zero();
+            self.boost = false;
+            return;
+        }
+        let settings = self.gamepad_settings;
+        let axes = joystick.get_axes();
+        let buttons = joystick.get_buttons();
+
+        // Triggers rest at -1.0 and are fully pressed at 1.0.
+        let stick = |i: usize| axes.get(i).cloned().unwrap_or(0.0);
+        let trigger = |i: usize| axes.get(i).map_or(0.0, |value| (value + 1.0) * 0.5);
+        let steer = apply_dead_zone(stick(settings.steer_axis), settings.dead_zone);
+        let throttle = apply_dead_zone(trigger(settings.throttle_axis), settings.trigger_dead_zone);
+        let brake = apply_dead_zone(trigger(settings.brake_axis), settings.trigger_dead_zone);
+
+        self.axis = Vector2::new(clamp(steer, -1.0, 1.0), clamp(throttle - brake, -1.0, 1.0));
+        self.axis_goal = self.axis;
+        self.boost = buttons.get(settings.boost_button).map_or(false, |&b| b == Action::Press as i32);
+    }
+
+    /// Use other keys. Keys held down at the moment are released.
+    pub fn set_key_bindings(&mut self, keys: KeyBindings) {
+        if self.ci.keys != keys {
+            self.ci = ControllerInternal::new(&CL::Keyboard(keys));
+            self.axis_goal = zero();
+            self.boost = false;
+        }
+    }
+
+    /// The gamepad read by this controller, `None` for keyboard controllers.
+    pub fn gamepad(&self) -> Option<JoystickId> {
+        self.gamepad
+    }
+
     /// Return the x axis value, clamped between [-1.0f32; 1.0f32].
     pub fn get_x_axis(&self) -> f32 {
         self.axis[0]
@@ -176,3 +525,38 @@ impl Controller {
         self.axis_goal[1] = value;
     }
 }
+
+/// Ignore small stick deflections and rescale the rest to [-1.0; 1.0].
+fn apply_dead_zone(value: f32, dead_zone: f32) -> f32 {
+    if value.abs() <= dead_zone {
+        0.0
+    } else {
+        value.signum() * (value.abs() - dead_zone) / (1.0 - dead_zone)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{apply_dead_zone, key_name, KeyBindings, KEYS};
+    use imgui_glfw_rs::glfw::Key;
+
+    #[test]
+    fn dead_zone() {
+        assert_eq!(apply_dead_zone(0.1, 0.2), 0.0);
+        assert_eq!(apply_dead_zone(-0.2, 0.2), 0.0);
+        assert_eq!(apply_dead_zone(1.0, 0.2), 1.0);
+        assert_eq!(apply_dead_zone(-1.0, 0.2), -1.0);
+        assert!((apply_dead_zone(0.6, 0.2) - 0.5).abs() < 1e-6);
+        assert_eq!(apply_dead_zone(0.5, 0.0), 0.5);
+    }
+
+    #[test]
+    fn key_names() {
+        for &key in KEYS.iter() {
+            assert_eq!(key_name::from_name(&key_name::to_name(key)), Some(key));
+        }
+        assert_eq!(key_name::to_name(Key::LeftShift), "LeftShift");
+        assert_eq!(key_name::from_name("Unknown"), None);
+        assert_ne!(KeyBindings::wasd(), KeyBindings::arrows());
+    }
+}
@@ -0,0 +1,296 @@
+// This file is part of Carambolage.
+
+// Carambolage is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Carambolage is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Carambolage.  If not, see <http://www.gnu.org/licenses/>.
+use super::car::Car;
+use super::manifest::DerbyManifest;
+use std::cmp::Ordering;
+
+/// Cars below this height in [m] fell off the arena.
+pub const FALL_HEIGHT: f32 = -5.0;
+/// Seconds between the end of a round and the start of the next one.
+const ROUND_PAUSE: f32 = 3.0;
+
+/// Derby state of a single car.
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub struct DerbyProgress {
+    /// Falls or wrecks left in this round, the car is knocked out at 0.
+    pub lives: u32,
+    /// Rounds won as the last car standing.
+    pub wins: u32,
+}
+
+/// A line of the derby standings table.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct DerbyStanding {
+    /// Index of the car in `Scene::cars`.
+    pub car: usize,
+    pub wins: u32,
+    pub lives: u32,
+}
+
+/// Knockout rounds on an arena level.
+///
+/// Cars push each other over the edge of the arena or wreck each other. Every fall or wreck costs
+/// a life and puts the car back on the start grid, a car without lives is knocked out for the rest
+/// of the round. The last car standing wins the round, after the last round the car with the most
+/// wins takes the match.
+pub struct Derby {
+    rules: Option<DerbyManifest>,
+    /// Current round from 1 to `rounds()`.
+    round: u32,
+    progress: Vec<DerbyProgress>,
+    /// Time left in [s] until the next round starts, set between rounds.
+    pause: Option<f32>,
+    /// Winner of the last finished round, `None` if every car was knocked out.
+    winner: Option<usize>,
+    is_over: bool,
+}
+
+impl Derby {
+    /// Derby with `rules`, `None` for levels without a derby.
+    pub fn new(rules: Option<DerbyManifest>) -> Derby {
+        Derby {
+            rules,
+            round: 1,
+            progress: Vec::new(),
+            pause: None,
+            winner: None,
+            is_over: false,
+        }
+    }
+
+    /// Restart the match for all cars.
+    pub fn reset(&mut self) {
+        *self = Derby::new(self.rules);
+    }
+
+    /// Return false if the level has no derby rules.
+    pub fn is_active(&self) -> bool {
+        self.rules.is_some()
+    }
+
+    pub fn round(&self) -> u32 {
+        self.round
+    }
+
+    /// Number of rounds of the match.
+    pub fn rounds(&self) -> u32 {
+        self.rules.map_or(0, |rules| rules.rounds)
+    }
+
+    /// Derby state of car `id`.
+    pub fn progress(&self, id: usize) -> DerbyProgress {
+        match self.rules {
+            Some(rules) => self.progress.get(id).cloned().unwrap_or_else(|| DerbyProgress {
+                lives: rules.lives,
+                wins: 0,
+            }),
+            None => Default::default(),
+        }
+    }
+
+    /// Car `id` lost all its lives and waits for the next round.
+    pub fn is_out(&self, id: usize) -> bool {
+        self.is_active() && self.progress(id).lives == 0
+    }
+
+    /// The last round ended and the next one starts soon.
+    pub fn is_between_rounds(&self) -> bool {
+        self.pause.is_some()
+    }
+
+    /// Winner of the last finished round, `None` if every car was knocked out.
+    pub fn winner(&self) -> Option<usize> {
+        self.winner
+    }
+
+    /// Return true once the last round is over.
+    pub fn is_over(&self) -> bool {
+        self.is_over
+    }
+
+    /// Take over the round from another simulation, e.g. the host of a network game.
+    pub fn set_round(&mut self, round: u32) {
+        self.round = round;
+    }
+
+    /// Take over the progress of car `id` from another simulation.
+    pub fn set_progress(&mut self, id: usize, progress: DerbyProgress) {
+        if id >= self.progress.len() {
+            let default = self.progress(id);
+            self.progress.resize(id + 1, default);
+        }
+        self.progress[id] = progress;
+    }
+
+    /// Check all cars for falls after a simulation step of `dt`.
+    ///
+    /// Returns the cars that have to be put back on the start grid.
+    pub fn update(&mut self, dt: f32, cars: &[Car]) -> Vec<usize> {
+        let rules = match self.rules {
+            Some(rules) => rules,
+            None => return Vec::new(),
+        };
+        let lives = rules.lives;
+        self.progress.resize(cars.len(), DerbyProgress { lives, wins: 0 });
+        let progress = &self.progress;
+        let fallen: Vec<usize> = (0..cars.len())
+            .filter(|&id| progress[id].lives > 0 && (cars[id].body.position[2] < FALL_HEIGHT || cars[id].is_wrecked()))
+            .collect();
+
+        // Falls only count while a round is running.
+        if self.is_over {
+            return fallen;
+        }
+        if let Some(pause) = self.pause {
+            if pause > dt {
+                self.pause = Some(pause - dt);
+                return fallen;
+            }
+            self.pause = None;
+            self.round += 1;
+            self.progress.iter_mut().for_each(|progress| progress.lives = lives);
+            return (0..cars.len()).collect();
+        }
+
+        let mut respawn = Vec::with_capacity(fallen.len());
+        for id in fallen {
+            let progress = &mut self.progress[id];
+            progress.lives -= 1;
+            if progress.lives > 0 {
+                respawn.push(id);
+            }
+        }
+
+        let standing: Vec<usize> = (0..cars.len()).filter(|&id| self.progress[id].lives > 0).collect();
+        // Alone on the arena the round lasts until the car is knocked out.
+        if !cars.is_empty() && standing.len() <= 1 && (cars.len() > 1 || standing.is_empty()) {
+            self.winner = standing.first().cloned();
+            if let Some(winner) = self.winner {
+                self.progress[winner].wins += 1;
+            }
+            if self.round >= rules.rounds {
+                self.is_over = true;
+            } else {
+                self.pause = Some(ROUND_PAUSE);
+            }
+        }
+        respawn
+    }
+
+    /// Ranking of all cars by rounds won, then by lives left.
+    pub fn standings(&self) -> Vec<DerbyStanding> {
+        let mut standings: Vec<DerbyStanding> = self
+            .progress
+            .iter()
+            .enumerate()
+            .map(|(car, progress)| DerbyStanding {
+                car,
+                wins: progress.wins,
+                lives: progress.lives,
+            })
+            .collect();
+        standings.sort_by(|a, b| match b.wins.cmp(&a.wins) {
+            Ordering::Equal => b.lives.cmp(&a.lives),
+            order => order,
+        });
+        standings
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Derby, DerbyProgress, FALL_HEIGHT, ROUND_PAUSE};
+    use crate::game::car::Car;
+    use crate::game::manifest::DerbyManifest;
+
+    fn cars(count: usize) -> Vec<Car> {
+        (0..count).map(|_| Car::test_kart()).collect()
+    }
+
+    fn fall(car: &mut Car) {
+        car.body.position[2] = FALL_HEIGHT - 1.0;
+    }
+
+    fn respawn(cars: &mut [Car], ids: &[usize]) {
+        ids.iter().for_each(|&id| cars[id].body.position[2] = 0.0);
+    }
+
+    #[test]
+    fn last_car_standing() {
+        let mut derby = Derby::new(Some(DerbyManifest { lives: 2, rounds: 2 }));
+        let mut cars = cars(3);
+        let dt = 0.1;
+        assert!(derby.update(dt, &cars).is_empty());
+        assert_eq!(derby.progress(0), DerbyProgress { lives: 2, wins: 0 });
+
+        // The first fall costs a life and respawns the car.
+        fall(&mut cars[0]);
+        assert_eq!(derby.update(dt, &cars), vec![0]);
+        respawn(&mut cars, &[0]);
+        assert_eq!(derby.progress(0).lives, 1);
+
+        // The second one knocks it out.
+        fall(&mut cars[0]);
+        assert!(derby.update(dt, &cars).is_empty());
+        assert!(derby.is_out(0));
+        assert!(!derby.is_between_rounds());
+
+        // Car 1 falls twice, car 2 is the last one standing.
+        fall(&mut cars[1]);
+        assert_eq!(derby.update(dt, &cars), vec![1]);
+        respawn(&mut cars, &[1]);
+        fall(&mut cars[1]);
+        derby.update(dt, &cars);
+        assert!(derby.is_between_rounds());
+        assert_eq!(derby.winner(), Some(2));
+        assert_eq!(derby.progress(2).wins, 1);
+
+        // Everybody is back for the second round.
+        let mut respawned = Vec::new();
+        for _ in 0..(ROUND_PAUSE / dt) as usize + 2 {
+            respawned = derby.update(dt, &cars);
+            if !respawned.is_empty() {
+                break;
+            }
+        }
+        assert_eq!(respawned, vec![0, 1, 2]);
+        respawn(&mut cars, &respawned);
+        assert_eq!(derby.round(), 2);
+        assert!(!derby.is_out(0));
+
+        for id in &[1, 2, 1, 2] {
+            fall(&mut cars[*id]);
+            let respawned = derby.update(dt, &cars);
+            respawn(&mut cars, &respawned);
+        }
+        assert!(derby.is_over());
+        assert_eq!(derby.winner(), Some(0));
+        let standings = derby.standings();
+        assert_eq!((standings[0].car, standings[0].wins), (0, 1));
+        assert_eq!((standings[1].car, standings[1].wins), (2, 1));
+        assert_eq!((standings[2].car, standings[2].wins), (1, 0));
+    }
+
+    #[test]
+    fn inactive_without_rules() {
+        let mut derby = Derby::new(None);
+        let mut cars = cars(2);
+        fall(&mut cars[0]);
+        assert!(derby.update(0.1, &cars).is_empty());
+        assert!(!derby.is_active());
+        assert!(!derby.is_out(0));
+        assert!(!derby.is_over());
+    }
+}
@@ -0,0 +1,284 @@
+// This file is part of Carambolage.
+
+// Carambolage is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Carambolage is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Carambolage.  If not, see <http://www.gnu.org/licenses/>.
+use super::controller::Controller;
+use super::replay::Replay;
+use super::scene::Scene;
+use super::GameSettings;
+use log::{error, info, warn};
+use std::fs;
+
+/// A single input change of a scripted player.
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct ScriptEvent {
+    tick: u32,
+    player: usize,
+    x_axis: f32,
+    y_axis: f32,
+    boost: bool,
+}
+
+/// Scripted player input for a headless run.
+///
+/// Every line of a script sets the input of a player from the given tick on:
+/// ```text
+/// # tick player x_axis y_axis boost
+/// 0      0      0.0    1.0    0
+/// 120    0      1.0    1.0    1
+/// ```
+/// Players start without any input. Empty lines and lines starting with `#` are ignored.
+#[derive(Debug, Default)]
+pub struct InputScript {
+    events: Vec<ScriptEvent>,
+    next: usize,
+}
+
+impl InputScript {
+    /// Load a script from a file.
+    pub fn load(file: &str) -> Result<InputScript, String> {
+        let text = fs::read_to_string(file).map_err(|e| format!("Failed to read {}: {}", file, e))?;
+        Self::parse(&text).map_err(|e| format!("{}: {}", file, e))
+    }
+
+    /// Parse a script from text.
+    pub fn parse(text: &str) -> Result<InputScript, String> {
+        let mut events = Vec::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() != 5 {
+                return Err(format!("line {}: expected 'tick player x_axis y_axis boost'", number + 1));
+            }
+            let invalid = |name: &str| format!("line {}: invalid {} '{}'", number + 1, name, line);
+            events.push(ScriptEvent {
+                tick: fields[0].parse().map_err(|_| invalid("tick"))?,
+                player: fields[1].parse().map_err(|_| invalid("player"))?,
+                x_axis: fields[2].parse().map_err(|_| invalid("x_axis"))?,
+                y_axis: fields[3].parse().map_err(|_| invalid("y_axis"))?,
+                boost: match fields[4] {
+                    "0" | "false" => false,
+                    "1" | "true" => true,
+                    _ => return Err(invalid("boost")),
+                },
+            });
+        }
+        // Stable sort, so events of the same tick are applied in file order.
+        events.sort_by_key(|event| event.tick);
+
+        Ok(InputScript { events, next: 0 })
+    }
+
+    /// Apply all events of `tick` to the `controller`s of the players.
+    ///
+    /// Ticks have to be passed in increasing order.
+    pub fn apply(&mut self, tick: u32, controller: &mut [Controller]) {
+        while self.next < self.events.len() && self.events[self.next].tick <= tick {
+            let event = self.events[self.next];
+            match controller.get_mut(event.player) {
+                Some(ctrl) => *ctrl = Controller::with_state(event.x_axis, event.y_axis, event.boost),
+                None => warn!("Script input for unknown player {} ignored", event.player),
+            }
+            self.next += 1;
+        }
+    }
+}
+
+/// Run a match without window and OpenGL context and print the final state of all cars and the standings.
+///
+/// The number of ticks and an optional input script are taken from the `settings`. With
+/// `settings.ai` the script only drives the first `settings.players` cars and the computer drives
+/// the others, with `settings.players` set to 0 it is a match of computer drivers only.
+///
+/// With `settings.replay` the recorded match is played back instead, all of it unless the number
+/// of ticks is given.
+pub fn run(settings: &GameSettings) {
+    let result = match settings.replay {
+        Some(ref file) => play(file, settings.headless),
+        None => simulate(settings),
+    };
+    match result {
+        Ok((scene, ticks, dt)) => print_result(&scene, ticks, dt),
+        Err(e) => {
+            error!("{}", e);
+            eprintln!("{}", e);
+        }
+    }
+}
+
+/// Simulate a match with scripted players and computer drivers.
+fn simulate(settings: &GameSettings) -> Result<(Scene, u32, f32), String> {
+    let ticks = settings.headless.unwrap_or(0);
+    info!("Running {} ticks headless on map {}", ticks, settings.map);
+
+    let mut script = match settings.script {
+        Some(ref file) => InputScript::load(file)?,
+        None => Default::default(),
+    };
+
+    let mut scene = Scene::new(&settings.map, &settings.cars)?;
+    let players = match settings.ai {
+        Some(difficulty) => {
+            for id in settings.players..scene.cars.len() {
+                scene.set_driver(id, Some(difficulty));
+            }
+            settings.players.min(scene.cars.len())
+        }
+        None => scene.cars.len(),
+    };
+    let mut controller = vec![Controller::with_state(0., 0., false); players];
+    let dt = 1.0 / settings.tick_rate.max(1) as f32;
+
+    for tick in 0..ticks {
+        script.apply(tick, &mut controller);
+        let input = scene.input(dt, &controller);
+        scene.update(dt, &input);
+    }
+    Ok((scene, ticks, dt))
+}
+
+/// Play back the first `ticks` ticks of the replay `file`, or all of them.
+fn play(file: &str, ticks: Option<u32>) -> Result<(Scene, u32, f32), String> {
+    let mut replay = Replay::load(file)?;
+    let ticks = ticks.unwrap_or_else(|| replay.ticks()).min(replay.ticks());
+    info!(
+        "Playing back {} of {} ticks of {} on map {}",
+        ticks,
+        replay.ticks(),
+        file,
+        replay.map
+    );
+
+    let mut scene = replay.scene()?;
+    let dt = 1.0 / replay.tick_rate.max(1) as f32;
+    for tick in 0..ticks {
+        let input = replay.play(tick, &mut scene)?;
+        scene.update(dt, &input);
+    }
+
+    if ticks == replay.ticks() {
+        match replay.verify(&scene) {
+            Some(true) => println!("Playback of {} ended in the recorded state", file),
+            Some(false) => warn!("Playback of {} ended in a different state than recorded", file),
+            None => info!("{} has no checksum to verify the playback", file),
+        }
+    }
+    Ok((scene, ticks, dt))
+}
+
+/// Print the final state of all cars and the standings.
+fn print_result(scene: &Scene, ticks: u32, dt: f32) {
+    println!("Simulated {} ticks ({:.2} s) on map {}", ticks, ticks as f32 * dt, scene.level.name);
+    println!("car  position                      speed [m/s]  yaw [rad]  boost  health  lap");
+    for (id, car) in scene.cars.iter().enumerate() {
+        let p = car.body.position;
+        println!(
+            "{:>3}  ({:>7.2}, {:>7.2}, {:>5.2})  {:>11.2}  {:>9.2}  {:>5.1}  {:>5.0}%  {:>3}",
+            id + 1,
+            p[0],
+            p[1],
+            p[2],
+            car.body.velocity.norm(),
+            car.body.rotation[2],
+            car.boost,
+            car.weakest_health_fraction() * 100.0,
+            scene.race.lap(id)
+        );
+    }
+
+    if scene.race.is_active() {
+        println!("Standings after {:.2} s:", scene.race.time());
+        for (place, standing) in scene.race.standings().iter().enumerate() {
+            match standing.finish_time {
+                Some(time) => println!("{:>3}. car {}  finished in {:.2} s", place + 1, standing.car + 1, time),
+                None => println!(
+                    "{:>3}. car {}  {}lap {}/{}",
+                    place + 1,
+                    standing.car + 1,
+                    if standing.is_wrecked { "wrecked in " } else { "" },
+                    standing.lap,
+                    scene.race.laps()
+                ),
+            }
+        }
+    }
+
+    let derby = &scene.derby;
+    if derby.is_active() {
+        println!("Derby standings in round {}/{}:", derby.round(), derby.rounds());
+        for (place, standing) in derby.standings().iter().enumerate() {
+            println!(
+                "{:>3}. car {}  {} wins  {} lives",
+                place + 1,
+                standing.car + 1,
+                standing.wins,
+                standing.lives
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{InputScript, ScriptEvent};
+    use crate::game::controller::Controller;
+
+    #[test]
+    fn parse_script() {
+        let script = InputScript::parse("# comment\n\n120 1 -1.0 1 true\n0 0 0.5 1.0 0\n").unwrap();
+        assert_eq!(
+            script.events,
+            vec![
+                ScriptEvent {
+                    tick: 0,
+                    player: 0,
+                    x_axis: 0.5,
+                    y_axis: 1.0,
+                    boost: false
+                },
+                ScriptEvent {
+                    tick: 120,
+                    player: 1,
+                    x_axis: -1.0,
+                    y_axis: 1.0,
+                    boost: true
+                },
+            ]
+        );
+
+        assert!(InputScript::parse("0 0 0.0 1.0").is_err());
+        assert!(InputScript::parse("0 0 0.0 fast 0").is_err());
+        assert!(InputScript::parse("0 0 0.0 1.0 maybe").is_err());
+    }
+
+    #[test]
+    fn apply_script() {
+        let mut script = InputScript::parse("0 0 0.0 1.0 0\n10 1 1.0 0.5 1\n20 5 1.0 1.0 1").unwrap();
+        let mut controller = vec![Controller::with_state(0., 0., false); 2];
+
+        script.apply(0, &mut controller);
+        assert_eq!(controller[0].get_y_axis(), 1.0);
+        assert_eq!(controller[1].get_y_axis(), 0.0);
+
+        script.apply(15, &mut controller);
+        assert_eq!(controller[1].get_x_axis(), 1.0);
+        assert_eq!(controller[1].get_y_axis(), 0.5);
+        assert!(controller[1].get_boost());
+
+        // Unknown players are ignored.
+        script.apply(20, &mut controller);
+    }
+}
@@ -12,57 +12,93 @@
 
 // You should have received a copy of the GNU General Public License
 // along with Carambolage.  If not, see <http://www.gnu.org/licenses/>.
-use crate::grphx::Model;
+use super::manifest::LevelManifest;
+use super::race::Course;
+use crate::error::Error;
+use crate::grphx::read_obj;
+use crate::physx::StaticCollider;
 use log::debug;
-use nalgebra::{zero, Isometry3, Matrix4, Point3, Vector3};
+use nalgebra::{zero, Isometry3, Point3, Vector3};
 use ncollide3d::shape::{Cuboid, TriMesh};
-use std::path::Path;
+use std::collections::{BTreeMap, HashMap};
+
+/// Cars should not bounce on the ground and the tires already handle friction.
+const GROUND_RESTITUTION: f32 = 0.0;
+const GROUND_FRICTION: f32 = 0.0;
+/// Cars bounce off the track border and scratch along it.
+const BORDER_RESTITUTION: f32 = 0.3;
+const BORDER_FRICTION: f32 = 0.2;
+/// The border is split into square cells of this size in [m], so the broad phase only pairs a
+/// car with the parts of the border close to it.
+const BORDER_CELL_SIZE: f32 = 10.0;
 
 /// Environment of a `Scene`.
 ///
-/// Currently our Environment consist of a race track with some colliders.
+/// Currently our Environment consist of a race track or an arena with some colliders.
 pub struct Level {
-    /// Racetrack model
-    model: Model,
-    /// Identity matrix for model rendering.
-    matrix: Matrix4<f32>,
-    /// A simple box collider for the ground.
-    pub(super) ground: (Isometry3<f32>, Cuboid<f32>),
-    /// Racetrack border collider. Keep this mesh as simple as possible.
-    pub(super) border: (Isometry3<f32>, TriMesh<f32>),
+    /// Name of the level, the file name of its manifest.
+    pub name: String,
+    /// Models, start grid and camera limits of the level.
+    pub manifest: LevelManifest,
+    /// A simple box collider for the ground and the parts of the racetrack border, if any.
+    pub(super) colliders: Vec<StaticCollider>,
+    /// Checkpoints and laps of the race on this level.
+    pub course: Course,
+    /// Waypoints of the computer drivers.
+    pub racing_line: Vec<Vector3<f32>>,
 }
 
 impl Level {
-    /// Load a model from raw model files.
-    pub fn new(file: &str) -> Level {
-        debug!("New from {}", file);
-        let model = Model::new(file, "racetrack.png");
-
-        // No scaliing, rotating, translating (Just for render)
-        let matrix = Matrix4::identity();
-
-        let (col_ground, col_border) = Self::load_collider(file);
-        let ground = (Isometry3::new(Vector3::new(0., 0., -100.0), zero()), col_ground);
-        let border = (Isometry3::new(zero(), zero()), col_border);
-
-        Level {
-            model,
-            matrix,
-            ground,
-            border,
+    /// Load the level called `name` from its manifest in `res/models/maps`.
+    pub fn new(name: &str) -> Result<Level, String> {
+        debug!("New from {}", name);
+
+        let manifest = LevelManifest::find(name)?;
+        let ground = StaticCollider::new(
+            Isometry3::new(Vector3::new(0., 0., -100.0), zero()),
+            Cuboid::new(Vector3::new(manifest.ground[0], manifest.ground[1], 100.0)),
+            GROUND_RESTITUTION,
+            GROUND_FRICTION,
+        );
+        let mut colliders = vec![ground];
+        // Keep the border mesh as simple as possible.
+        if !manifest.collider.is_empty() {
+            for part in Self::load_collider(&manifest.collider)? {
+                colliders.push(StaticCollider::new(
+                    Isometry3::new(zero(), zero()),
+                    part,
+                    BORDER_RESTITUTION,
+                    BORDER_FRICTION,
+                ));
+            }
         }
-    }
+        let course = manifest.race.course();
+        let racing_line = manifest.race.racing_line();
 
-    /// Load the collider mesh from an obj file.
-    fn load_collider(file: &str) -> (Cuboid<f32>, TriMesh<f32>) {
-        let path_str = format!("res/models/{}.col", file);
-        let path = Path::new(&path_str);
-        let obj = tobj::load_obj(path);
-        let (models, _) = obj.unwrap();
+        Ok(Level {
+            name: name.to_string(),
+            manifest,
+            colliders,
+            course,
+            racing_line,
+        })
+    }
 
-        let mesh = &models[0].mesh;
+    /// Load the collider mesh from an obj file, split into one mesh per `BORDER_CELL_SIZE` cell.
+    ///
+    /// The path is relative to "res/models".
+    fn load_collider(file: &str) -> Result<Vec<TriMesh<f32>>, Error> {
+        let (path, models) = read_obj(file)?;
+        let mesh = match models.first() {
+            Some(model) => &model.mesh,
+            None => return Err(Error::Invalid(path, "collider contains no mesh".to_string())),
+        };
         let num_vertices = mesh.positions.len() / 3;
         let num_indices = mesh.indices.len() / 3;
+        // The collision detection would panic on these later.
+        if mesh.indices.iter().any(|&i| i as usize >= num_vertices) {
+            return Err(Error::Invalid(path, "collider index out of range".to_string()));
+        }
 
         let mut vertices = Vec::with_capacity(num_vertices);
         let mut indices = Vec::with_capacity(mesh.indices.len());
@@ -79,14 +115,56 @@ impl Level {
             vertices.push(Point3::new(p[i * 3], p[i * 3 + 1], p[i * 3 + 2]));
         }
 
-        let col_ground = Cuboid::new(Vector3::new(1_000.0, 1_000.0, 100.0));
-        let col_border = TriMesh::new(vertices, indices, None);
+        Ok(split_into_cells(&vertices, &indices, BORDER_CELL_SIZE))
+    }
+}
 
-        (col_ground, col_border)
+/// Split a mesh into one mesh per square cell of `size` in the xy plane.
+///
+/// Every triangle goes to the cell of its center, so a triangle can reach into neighbouring cells.
+fn split_into_cells(vertices: &[Point3<f32>], indices: &[Point3<usize>], size: f32) -> Vec<TriMesh<f32>> {
+    let mut cells: BTreeMap<(i32, i32), Vec<Point3<usize>>> = BTreeMap::new();
+    for triangle in indices {
+        let center = (vertices[triangle.x].coords + vertices[triangle.y].coords + vertices[triangle.z].coords) / 3.0;
+        let cell = ((center.x / size).floor() as i32, (center.y / size).floor() as i32);
+        cells.entry(cell).or_default().push(*triangle);
     }
 
-    /// Render the environment to the bound framebuffer.
-    pub fn draw(&self, view: &Matrix4<f32>, projection: &Matrix4<f32>) {
-        self.model.draw(&self.matrix, view, projection);
+    cells
+        .values()
+        .map(|triangles| {
+            // Only keep the vertices of the cell, the bounding box of a mesh includes all of them.
+            let mut cell_vertices = Vec::new();
+            let mut remap = HashMap::new();
+            let mut index = |i: usize| {
+                *remap.entry(i).or_insert_with(|| {
+                    cell_vertices.push(vertices[i]);
+                    cell_vertices.len() - 1
+                })
+            };
+            let cell_indices = triangles.iter().map(|t| Point3::new(index(t.x), index(t.y), index(t.z))).collect();
+            TriMesh::new(cell_vertices, cell_indices, None)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Level;
+    use crate::physx::Aabb;
+    use nalgebra::Vector3;
+
+    #[test]
+    fn border_split_into_cells() {
+        let level = Level::new("race_track_1").unwrap();
+        let border = &level.colliders[1..];
+        assert!(border.len() > 10);
+
+        // A car on the start grid only meets the parts of the border next to it.
+        let (position, _) = level.manifest.spawn.place(0);
+        let half_extents = Vector3::new(2.0, 2.0, 1.0);
+        let car = Aabb::new(position - half_extents, position + half_extents);
+        let near = border.iter().filter(|collider| collider.aabb().intersects(&car)).count();
+        assert!(near * 4 < border.len());
     }
 }
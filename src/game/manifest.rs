@@ -0,0 +1,487 @@
+// This file is part of Carambolage.
+
+// Carambolage is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Carambolage is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Carambolage.  If not, see <http://www.gnu.org/licenses/>.
+use super::pickup::{PickupKind, MAX_PICKUPS};
+use super::race::{Checkpoint, Course};
+use crate::util::{find_resource, resource_names, Resource};
+use nalgebra::{Rotation3, Vector3};
+use serde_derive::{Deserialize, Serialize};
+
+/// Every `*.toml` file in this directory of the resources describes a level.
+const MAPS_DIR: &str = "models/maps";
+/// Cars are dropped onto the track from this height in [m].
+const SPAWN_HEIGHT: f32 = 0.5;
+/// Half size of the ground in [m] if a level does not limit it.
+const GROUND_SIZE: f32 = 1_000.0;
+
+/// Description of a level, loaded from `res/models/maps/<name>.toml`.
+///
+/// ```toml
+/// name = "Race Track 1"
+/// model = "maps/race_track_1"
+/// texture = "racetrack.png"
+/// collider = "maps/race_track_1.col"
+///
+/// [spawn]
+/// position = [0.0, 0.0]
+///
+/// [race]
+/// laps = 3
+/// checkpoints = [
+///     { position = [2.5, 2.5], rotation = 0.0, half_width = 7.0 },
+/// ]
+/// racing_line = [[2.5, 2.5], [2.5, 20.0], [8.0, 50.0]]
+///
+/// [[pickups]]
+/// kind = "shield"
+/// position = [2.5, 30.0]
+/// ```
+/// Model and collider paths are relative to `res/models`, the texture is relative to
+/// `res/textures`. The `spawn`, `camera` and `race` sections and the pickups are optional.
+///
+/// Arenas have no border collider, but a small `ground` cars can fall off and a `derby` section:
+/// ```toml
+/// ground = [30.0, 30.0]
+///
+/// [derby]
+/// lives = 3
+/// rounds = 3
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LevelManifest {
+    /// Display name of the level.
+    pub name: String,
+    #[serde(default)]
+    pub author: String,
+    #[serde(default)]
+    pub description: String,
+
+    /// Render model.
+    pub model: String,
+    /// Palette texture of the render model.
+    pub texture: String,
+    /// Collision mesh of the track border, none if empty.
+    #[serde(default)]
+    pub collider: String,
+    /// Half size of the ground in [m] in x and y, cars driving over its edge fall off.
+    #[serde(default = "default_ground")]
+    pub ground: [f32; 2],
+
+    #[serde(default)]
+    pub spawn: SpawnGrid,
+    #[serde(default)]
+    pub camera: CameraBounds,
+    #[serde(default)]
+    pub race: RaceManifest,
+    /// Knockout rules, makes the level an arena.
+    #[serde(default)]
+    pub derby: Option<DerbyManifest>,
+    #[serde(default)]
+    pub pickups: Vec<PickupManifest>,
+}
+
+fn default_ground() -> [f32; 2] {
+    [GROUND_SIZE, GROUND_SIZE]
+}
+
+/// Start positions of the cars.
+///
+/// The cars line up in `columns` next to each other behind `position`, every car `car_spacing`
+/// behind the previous one, so neighbours in a row are staggered.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SpawnGrid {
+    /// Center of the first row in world space.
+    pub position: [f32; 2],
+    /// Driving direction in degrees around the z-axis, 0 is +y.
+    pub rotation: f32,
+    pub columns: u32,
+    /// Distance between the columns in [m].
+    pub column_spacing: f32,
+    /// Distance between a car and the next one in [m].
+    pub car_spacing: f32,
+}
+
+/// Limits of the camera following the cars.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CameraBounds {
+    pub height_min: f32,
+    pub height_max: f32,
+    /// Lower left corner of the area the camera may look at.
+    pub focus_min: [f32; 2],
+    /// Upper right corner of the area the camera may look at.
+    pub focus_max: [f32; 2],
+}
+
+/// Race settings of a level, see `Course`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RaceManifest {
+    pub laps: u32,
+    /// Start and finish line first, then all other checkpoints in driving order.
+    pub checkpoints: Vec<CheckpointManifest>,
+    /// Points in driving order the computer drivers steer through, the checkpoints if empty.
+    pub racing_line: Vec<[f32; 2]>,
+}
+
+/// A `Checkpoint` as written in the manifest.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CheckpointManifest {
+    pub position: [f32; 2],
+    /// Driving direction in degrees around the z-axis, 0 is +y.
+    pub rotation: f32,
+    pub half_width: f32,
+}
+
+/// Rules of a `Derby`.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DerbyManifest {
+    /// Falls a car survives per round, it is knocked out by the last one.
+    pub lives: u32,
+    pub rounds: u32,
+}
+
+/// A `Pickup` as written in the manifest.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PickupManifest {
+    /// One of `boost`, `shield`, `mass`, `oil_slick`, `shockwave` or `speed_pad`.
+    pub kind: PickupKind,
+    pub position: [f32; 2],
+    /// Direction of a speed pad in degrees around the z-axis, 0 is +y.
+    #[serde(default)]
+    pub rotation: f32,
+    /// Seconds until the pickup is back after it was collected, a default per kind if missing.
+    #[serde(default)]
+    pub respawn: Option<f32>,
+}
+
+impl Default for SpawnGrid {
+    fn default() -> SpawnGrid {
+        SpawnGrid {
+            position: [0.0, 0.0],
+            rotation: 0.0,
+            columns: 2,
+            column_spacing: 2.3,
+            car_spacing: 1.7,
+        }
+    }
+}
+
+impl SpawnGrid {
+    /// Start position and rotation around the z-axis in radians of car `id`.
+    pub fn place(&self, id: usize) -> (Vector3<f32>, f32) {
+        let columns = self.columns.max(1) as usize;
+        let column = (id % columns) as f32 - (columns - 1) as f32 * 0.5;
+        let offset = Vector3::new(column * self.column_spacing, -(id as f32) * self.car_spacing, 0.);
+
+        let rotation = self.rotation.to_radians();
+        let position = Vector3::new(self.position[0], self.position[1], SPAWN_HEIGHT);
+        (position + Rotation3::from_euler_angles(0., 0., rotation) * offset, rotation)
+    }
+}
+
+impl Default for CameraBounds {
+    fn default() -> CameraBounds {
+        CameraBounds {
+            height_min: 30.0,
+            height_max: 140.0,
+            focus_min: [-1_000.0, -1_000.0],
+            focus_max: [1_000.0, 1_000.0],
+        }
+    }
+}
+
+impl Default for RaceManifest {
+    fn default() -> RaceManifest {
+        RaceManifest {
+            laps: 1,
+            checkpoints: Vec::new(),
+            racing_line: Vec::new(),
+        }
+    }
+}
+
+impl Default for DerbyManifest {
+    fn default() -> DerbyManifest {
+        DerbyManifest { lives: 3, rounds: 3 }
+    }
+}
+
+impl RaceManifest {
+    /// Checkpoints in world space and laps for the `Race`.
+    pub fn course(&self) -> Course {
+        Course {
+            checkpoints: self
+                .checkpoints
+                .iter()
+                .map(|c| {
+                    Checkpoint::new(
+                        Vector3::new(c.position[0], c.position[1], 0.),
+                        c.rotation.to_radians(),
+                        c.half_width,
+                    )
+                })
+                .collect(),
+            laps: self.laps,
+        }
+    }
+
+    /// Waypoints of the computer drivers in world space, see `AiDriver`.
+    pub fn racing_line(&self) -> Vec<Vector3<f32>> {
+        if self.racing_line.is_empty() {
+            self.checkpoints
+                .iter()
+                .map(|c| Vector3::new(c.position[0], c.position[1], 0.))
+                .collect()
+        } else {
+            self.racing_line.iter().map(|p| Vector3::new(p[0], p[1], 0.)).collect()
+        }
+    }
+}
+
+impl LevelManifest {
+    /// Names of all levels in `res/models/maps` and its overrides, sorted alphabetically.
+    pub fn available() -> Vec<String> {
+        resource_names(MAPS_DIR, "toml")
+    }
+
+    /// Load the manifest of the level called `name`.
+    ///
+    /// Only names of available levels are looked up, so a name like "../cars/kart" is unknown.
+    pub fn find(name: &str) -> Result<LevelManifest, String> {
+        let available = Self::available();
+        let unknown = || format!("Unknown level '{}', available levels: {}", name, available.join(", "));
+        if !available.iter().any(|level| level == name) {
+            return Err(unknown());
+        }
+        let file = find_resource(&format!("{}/{}.toml", MAPS_DIR, name)).ok_or_else(unknown)?;
+        Self::load(&file)
+    }
+
+    /// Load a manifest from a loose or packed file.
+    pub fn load(file: &Resource) -> Result<LevelManifest, String> {
+        let text = file.read_to_string().map_err(|e| format!("Failed to read {}: {}", file, e))?;
+        Self::parse(&text).map_err(|e| format!("{}: {}", file, e))
+    }
+
+    /// Parse and validate a manifest.
+    pub fn parse(text: &str) -> Result<LevelManifest, String> {
+        let manifest: LevelManifest = toml::from_str(text).map_err(|e| e.to_string())?;
+        manifest.validate()?;
+        Ok(manifest)
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.ground[0] <= 0.0 || self.ground[1] <= 0.0 {
+            return Err("ground: size must be positive".to_string());
+        }
+        if self.spawn.columns == 0 {
+            return Err("spawn: columns must be at least 1".to_string());
+        }
+        let camera = &self.camera;
+        if camera.height_min <= 0.0 || camera.height_min > camera.height_max {
+            return Err("camera: expected 0 < height_min <= height_max".to_string());
+        }
+        if camera.focus_min[0] > camera.focus_max[0] || camera.focus_min[1] > camera.focus_max[1] {
+            return Err("camera: focus_min has to be below focus_max".to_string());
+        }
+        if self.race.laps == 0 {
+            return Err("race: laps must be at least 1".to_string());
+        }
+        for (i, checkpoint) in self.race.checkpoints.iter().enumerate() {
+            if checkpoint.half_width <= 0.0 {
+                return Err(format!("race: checkpoint {} needs a positive half_width", i + 1));
+            }
+        }
+        if self.race.racing_line.len() == 1 {
+            return Err("race: racing_line needs at least 2 points".to_string());
+        }
+        if let Some(derby) = self.derby {
+            if derby.lives == 0 || derby.rounds == 0 {
+                return Err("derby: lives and rounds must be at least 1".to_string());
+            }
+        }
+        if self.pickups.len() > MAX_PICKUPS {
+            return Err(format!("pickups: at most {} pickups are allowed", MAX_PICKUPS));
+        }
+        for (i, pickup) in self.pickups.iter().enumerate() {
+            if pickup.respawn.map_or(false, |respawn| respawn < 0.0) {
+                return Err(format!("pickups: pickup {} needs a respawn time of at least 0", i + 1));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DerbyManifest, LevelManifest, PickupManifest, SpawnGrid};
+    use crate::game::pickup::PickupKind;
+    use nalgebra::Vector3;
+
+    const MINIMAL: &str = r#"
+        name = "Test"
+        model = "maps/test"
+        texture = "test.png"
+        collider = "maps/test.col"
+    "#;
+
+    #[test]
+    fn parse_manifest() {
+        let manifest = LevelManifest::parse(&format!(
+            "{}\n{}",
+            MINIMAL,
+            r#"
+            [camera]
+            height_min = 20.0
+
+            [race]
+            laps = 2
+            checkpoints = [
+                { position = [20.0, 0.0], rotation = 0.0, half_width = 5.0 },
+                { position = [0.0, 20.0], rotation = 90.0, half_width = 5.0 },
+            ]
+            "#
+        ))
+        .unwrap();
+        assert_eq!(manifest.name, "Test");
+        assert_eq!(manifest.spawn, SpawnGrid::default());
+        assert_eq!(manifest.camera.height_min, 20.0);
+        assert_eq!(manifest.camera.height_max, 140.0);
+
+        let course = manifest.race.course();
+        assert_eq!(course.laps, 2);
+        assert_eq!(course.checkpoints.len(), 2);
+        assert!((course.checkpoints[1].forward() - Vector3::new(-1., 0., 0.)).norm() < 1e-5);
+        assert_eq!(
+            manifest.race.racing_line(),
+            vec![Vector3::new(20., 0., 0.), Vector3::new(0., 20., 0.)]
+        );
+    }
+
+    #[test]
+    fn parse_arena() {
+        let manifest = LevelManifest::parse(
+            r#"
+            name = "Arena"
+            model = "maps/arena"
+            texture = "test.png"
+            ground = [20.0, 25.0]
+
+            [derby]
+            rounds = 5
+            "#,
+        )
+        .unwrap();
+        assert_eq!(manifest.collider, "");
+        assert_eq!(manifest.ground, [20.0, 25.0]);
+        assert_eq!(manifest.derby, Some(DerbyManifest { lives: 3, rounds: 5 }));
+
+        let track = LevelManifest::parse(MINIMAL).unwrap();
+        assert_eq!(track.ground, [1_000.0, 1_000.0]);
+        assert_eq!(track.derby, None);
+    }
+
+    #[test]
+    fn parse_pickups() {
+        let manifest = LevelManifest::parse(&format!(
+            "{}\n{}",
+            MINIMAL,
+            r#"
+            [[pickups]]
+            kind = "oil_slick"
+            position = [1.0, 2.0]
+
+            [[pickups]]
+            kind = "speed_pad"
+            position = [3.0, 4.0]
+            rotation = 90.0
+            respawn = 2.0
+            "#
+        ))
+        .unwrap();
+        assert_eq!(
+            manifest.pickups,
+            vec![
+                PickupManifest {
+                    kind: PickupKind::OilSlick,
+                    position: [1.0, 2.0],
+                    rotation: 0.0,
+                    respawn: None,
+                },
+                PickupManifest {
+                    kind: PickupKind::SpeedPad,
+                    position: [3.0, 4.0],
+                    rotation: 90.0,
+                    respawn: Some(2.0),
+                },
+            ]
+        );
+        assert!(LevelManifest::parse(MINIMAL).unwrap().pickups.is_empty());
+    }
+
+    #[test]
+    fn malformed_manifest() {
+        assert!(LevelManifest::parse(MINIMAL).is_ok());
+        assert!(LevelManifest::parse("name = \"Test\"").is_err());
+        assert!(LevelManifest::parse(&MINIMAL.replace("\"Test\"", "3")).is_err());
+        assert!(LevelManifest::parse(&format!("{}\n[race]\nlaps = 0", MINIMAL)).is_err());
+        assert!(LevelManifest::parse(&format!("{}\n[spawn]\ncolumns = 0", MINIMAL)).is_err());
+        assert!(LevelManifest::parse(&format!("{}\n[camera]\nheight_min = 200.0", MINIMAL)).is_err());
+        let checkpoint = "[race]\ncheckpoints = [{ position = [0.0, 0.0], rotation = 0.0, half_width = -1.0 }]";
+        assert!(LevelManifest::parse(&format!("{}\n{}", MINIMAL, checkpoint)).is_err());
+        assert!(LevelManifest::parse(&format!("{}\n[race]\nracing_line = [[0.0, 0.0]]", MINIMAL)).is_err());
+        assert!(LevelManifest::parse(&format!("{}\n[derby]\nlives = 0", MINIMAL)).is_err());
+        assert!(LevelManifest::parse(&MINIMAL.replace("collider", "ground = [0.0, 10.0]\ncollider")).is_err());
+        let pickup = "[[pickups]]\nkind = \"banana\"\nposition = [0.0, 0.0]";
+        assert!(LevelManifest::parse(&format!("{}\n{}", MINIMAL, pickup)).is_err());
+        let pickup = "[[pickups]]\nkind = \"boost\"\nposition = [0.0, 0.0]\nrespawn = -1.0";
+        assert!(LevelManifest::parse(&format!("{}\n{}", MINIMAL, pickup)).is_err());
+    }
+
+    #[test]
+    fn spawn_grid() {
+        let grid = SpawnGrid::default();
+        let (position, rotation) = grid.place(0);
+        assert!((position - Vector3::new(-1.15, 0.0, 0.5)).norm() < 1e-5);
+        assert_eq!(rotation, 0.0);
+        let (position, _) = grid.place(3);
+        assert!((position - Vector3::new(1.15, -5.1, 0.5)).norm() < 1e-5);
+
+        // Facing -x, the grid extends to +x.
+        let grid = SpawnGrid {
+            position: [10.0, 0.0],
+            rotation: 90.0,
+            ..Default::default()
+        };
+        let (position, _) = grid.place(1);
+        assert!((position - Vector3::new(11.7, 1.15, 0.5)).norm() < 1e-5);
+    }
+
+    #[test]
+    fn shipped_levels() {
+        let levels = LevelManifest::available();
+        assert!(levels.contains(&"race_track_1".to_string()));
+        for level in &levels {
+            let manifest = LevelManifest::find(level).unwrap();
+            // Every level is either a race track or a derby arena.
+            assert!(!manifest.race.checkpoints.is_empty() || manifest.derby.is_some(), "{}", level);
+        }
+        assert!(LevelManifest::find("no_such_level").is_err());
+        // Names are not paths, even if the file exists.
+        assert!(LevelManifest::find("../maps/race_track_1").is_err());
+    }
+}
@@ -13,55 +13,133 @@
 // You should have received a copy of the GNU General Public License
 // along with Carambolage.  If not, see <http://www.gnu.org/licenses/>.
 
+/// Computer controlled drivers.
+pub mod ai;
 /// GameObject, currently only a car.
 pub mod car;
+/// Car description files.
+pub mod car_definition;
+/// Points and standings across several levels.
+pub mod championship;
+/// User settings that survive a restart.
+pub mod config;
 /// User input handling.
 pub mod controller;
+/// Knockout rounds on arena levels.
+pub mod derby;
+/// Simulation without window and OpenGL context.
+pub mod headless;
 /// Environment of a `Scene`.
 pub mod level;
+/// Level description files.
+pub mod manifest;
+/// Power-ups placed on levels.
+pub mod pickup;
+/// Checkpoints, lap counting and standings.
+pub mod race;
+/// OpenGL resources to draw a `Scene`.
+pub mod render;
+/// Recording and playback of matches.
+pub mod replay;
 /// Actual runtime data.
 pub mod scene;
+/// Lap times and ghost car of a single player driving alone.
+pub mod time_trial;
 /// 3D translation, rotation and scale.
 pub mod transform;
 
 use imgui_glfw_rs::glfw;
 
-use self::controller::{Controller, ControllerLayout};
+use self::ai::Difficulty;
+use self::car_definition::CarSelection;
+use self::championship::{Championship, ChampionshipManifest};
+use self::config::{config_file, Config};
+use self::controller::{Controller, ControllerLayout, JOYSTICKS};
+use self::render::SceneRenderer;
+use self::replay::{Replay, REPLAY_FILE};
 use self::scene::Scene;
-use crate::grphx::Screen;
-use crate::gui::AppUI;
-use crate::util::FrameLimiter;
+use self::time_trial::TimeTrial;
+use crate::grphx::{Assets, Screen};
+use crate::gui::{AppUI, FrameInfo};
+use crate::net::{Client, Server};
+use crate::util::{data_dir, FixedTimestep, FrameLimiter};
 use glfw::{Context, Glfw, Window};
-use log::{debug, info};
+use log::{debug, error, info, warn};
 use nalgebra::Perspective3;
 use std::cell::Cell;
 use std::sync::mpsc::Receiver;
 
 type Event = Receiver<(f64, glfw::WindowEvent)>;
 
-pub(crate) struct Game {
+/// Players on the keyboard and on gamepads together.
+pub const MAX_PLAYERS: usize = 4;
+
+pub struct Game {
     // Glfw and GL
     glfw: Glfw,
     window: Window,
     events: Event,
     frame_limiter: FrameLimiter,
+    timestep: FixedTimestep,
     gui: AppUI,
 
     screen: Screen,
+    /// Meshes, shaders and textures shared by all models, they stay loaded while used.
+    assets: Assets,
 
     // Game
     settings: GameSettings,
     scene: Scene,
+    renderer: SceneRenderer,
     controller: Vec<Controller>,
+    /// Recording of the session, saved to `REPLAY_FILE` on exit. Only the host of a network game
+    /// records it.
+    replay: Option<Replay>,
+    /// Set while hosting a network game.
+    server: Option<Server>,
+    /// Set while playing in the network game of another computer.
+    client: Option<Client>,
+    /// Set while a championship started from the menu is played.
+    championship: Option<Championship>,
+    /// Config the game started with. An unchanged config is not saved on exit, so a config file
+    /// that failed to load is not overwritten with the defaults.
+    initial_config: Config,
 }
 
 pub struct GameSettings {
     pub is_fullscreen: bool,
     pub width: u32,
     pub height: u32,
-    pub map: u32,
+    /// Name of the level, see `LevelManifest`.
+    pub map: String,
+    /// Car and color of every player. Players joining with a gamepad reuse these in turn.
+    pub cars: Vec<CarSelection>,
+    /// Key bindings and gamepad settings, saved to `config_file()` on exit if they changed.
+    pub config: Config,
+    /// Players sharing the keyboard, one per `Config::keyboard` layout.
+    pub players: usize,
+    /// Give every player an own camera and part of the screen instead of one shared camera.
+    pub split_screen: bool,
+    /// Skill of the computer drivers of all cars without a player, `None` leaves them standing.
+    pub ai: Option<Difficulty>,
     pub fps: u32,
+    /// Simulation steps per second, independent of `fps`.
+    pub tick_rate: u32,
     pub gamma: f32,
+    /// Run this many ticks without a window instead of starting the game.
+    pub headless: Option<u32>,
+    /// Input script file for a headless run.
+    pub script: Option<String>,
+    /// Replay file to play back without window instead of starting the game.
+    pub replay: Option<String>,
+    /// Host a network game on this address, e.g. `0.0.0.0:7878`.
+    pub host: Option<String>,
+    /// Join the network game of the host at this address instead of starting an own game.
+    pub join: Option<String>,
+    /// Drive alone against the ghost of the best lap with the first car.
+    pub time_trial: bool,
+    /// Levels and points of the championship the menu starts.
+    pub championship: ChampionshipManifest,
 }
 
 impl Default for GameSettings {
@@ -70,17 +148,89 @@ impl Default for GameSettings {
             is_fullscreen: false,
             width: 640,
             height: 480,
-            map: 1,
+            map: "race_track_1".to_string(),
+            cars: vec![CarSelection::new("farara", None), CarSelection::new("lamba", None)],
+            config: Default::default(),
+            players: 2,
+            split_screen: false,
+            ai: None,
             fps: 60,
+            tick_rate: 60,
             gamma: 2.2,
+            headless: None,
+            script: None,
+            replay: None,
+            host: None,
+            join: None,
+            time_trial: false,
+            championship: Default::default(),
         }
     }
 }
 
 impl Game {
-    pub(crate) fn new(settings: GameSettings) -> Game {
+    /// Load the level and open the game window.
+    ///
+    /// When joining a network game, this waits for the host and plays its map with its tick rate.
+    pub fn new(mut settings: GameSettings) -> Result<Game, String> {
         info!("Initializing game");
+        if settings.time_trial && (settings.host.is_some() || settings.join.is_some()) {
+            return Err("A time trial is driven alone, not in a network game".to_string());
+        }
+        let client = match settings.join {
+            Some(ref address) => Some(Client::connect(address, &settings.cars[0])?),
+            None => None,
+        };
+        // A client and the driver of a time trial drive a single car.
+        let players = match client {
+            Some(_) => 1,
+            None if settings.time_trial => 1,
+            None => settings.players.max(1).min(MAX_PLAYERS),
+        };
+        if players > settings.config.keyboard.len() {
+            return Err(format!(
+                "{} players need {} keyboard layouts in {}, found {}",
+                players,
+                players,
+                config_file().display(),
+                settings.config.keyboard.len()
+            ));
+        }
+        let (scene, replay, server) = match client {
+            Some(ref client) => {
+                let welcome = client.welcome().unwrap();
+                settings.map = welcome.map.clone();
+                settings.tick_rate = welcome.tick_rate;
+                (client.scene()?, None, None)
+            }
+            None => {
+                // Every player on the keyboard needs a car, a time trial only the first one.
+                let mut cars = settings.cars.clone();
+                if settings.time_trial {
+                    cars.truncate(1);
+                }
+                for player in cars.len()..players {
+                    let mut selection = settings.cars[player % settings.cars.len()].clone();
+                    selection.palette = None;
+                    cars.push(selection);
+                }
+                let mut scene = Scene::new(&settings.map, &cars)?;
+                for id in players..scene.cars.len() {
+                    scene.set_driver(id, settings.ai);
+                }
+                if settings.time_trial {
+                    scene.time_trial = Some(Self::time_trial(&scene, &cars[0])?);
+                }
+                let replay = Replay::new(&settings.map, &cars, settings.tick_rate);
+                let server = match settings.host {
+                    Some(ref address) => Some(Server::bind(address, settings.tick_rate)?),
+                    None => None,
+                };
+                (scene, Some(replay), server)
+            }
+        };
         let frame_limiter = FrameLimiter::new(settings.fps);
+        let timestep = FixedTimestep::new(settings.tick_rate);
 
         debug!("Initializing glfw window");
         let mut glfw = glfw::init(glfw::FAIL_ON_ERRORS).unwrap();
@@ -119,50 +269,137 @@ impl Game {
             gl::DepthFunc(gl::LESS);
         }
 
-        let screen = Screen::new(settings.width, settings.height);
-
-        let controller = vec![
-            Controller::new(true, &ControllerLayout::WASD),
-            Controller::new(true, &ControllerLayout::Arrows),
-        ];
-        let scene = Scene::new(settings.map);
-
-        Game {
+        let screen = Screen::new(settings.width, settings.height)?;
+
+        let controller = settings
+            .config
+            .keyboard
+            .iter()
+            .take(players)
+            .map(|keys| Controller::new(true, &ControllerLayout::Keyboard(*keys)))
+            .collect();
+        let mut assets = Assets::new();
+        let renderer = SceneRenderer::new(&scene, &mut assets);
+        debug!("Loaded assets: {}", assets.stats());
+
+        let initial_config = settings.config.clone();
+        Ok(Game {
             glfw,
             window,
             events,
             frame_limiter,
+            timestep,
             gui,
 
             screen,
+            assets,
 
             settings,
             scene,
+            renderer,
             controller,
+            replay,
+            server,
+            client,
+            championship: None,
+            initial_config,
+        })
+    }
+
+    /// Time trial with the record of `car` on the level of `scene` from the data dir of the user.
+    fn time_trial(scene: &Scene, car: &CarSelection) -> Result<TimeTrial, String> {
+        if !scene.race.is_active() {
+            return Err(format!(
+                "A time trial needs a level with checkpoints, {} has none",
+                scene.level.name
+            ));
+        }
+        let mut time_trial = TimeTrial::new(&scene.level.name, &car.car, scene.race.checkpoints());
+        if let Err(e) = time_trial.load(&data_dir()) {
+            warn!("{}, starting without a record", e);
         }
+        Ok(time_trial)
     }
 
-    pub(crate) fn run(&mut self) {
+    pub fn run(&mut self) {
         while !self.window.should_close() {
-            let dt = self.frame_limiter.start();
+            let frame_time = self.frame_limiter.start();
             self.window.make_current();
             self.glfw.poll_events();
             self.process_events();
-            self.process_input(dt);
-
-            if !self.gui.is_menu_control {
-                self.scene.update(dt, &self.controller, self.gui.is_ingame);
-            } else {
-                self.scene.update(dt, &[], self.gui.is_ingame);
+            self.connect_gamepads();
+
+            // Run the simulation in fixed steps, so it behaves the same with any frame rate.
+            for _ in 0..self.timestep.advance(frame_time) {
+                let dt = self.timestep.dt();
+                self.process_input(dt);
+                if self.client.is_some() {
+                    self.update_client(dt);
+                } else {
+                    self.update_scene(dt);
+                }
             }
+            self.renderer.add_new_cars(&self.scene, &mut self.assets);
+            let alpha = self.timestep.alpha();
+            self.scene.update_camera(frame_time, alpha, self.gui.menu.is_ingame);
 
             self.screen.first_step();
-            let projection = Perspective3::new(self.settings.width as f32 / self.settings.height as f32, 70., 1.0, 200.).into_inner();
-            self.scene.draw(&projection);
+            let players = self.split_screen_players();
+            if players > 0 {
+                for (viewport, camera) in self.screen.split(players).iter().zip(&self.scene.player_cameras) {
+                    self.screen.set_viewport(viewport);
+                    let projection = Perspective3::new(viewport.aspect(), 70., 1.0, 200.).into_inner();
+                    self.renderer.draw(&self.scene, camera, &projection, alpha);
+                }
+            } else {
+                let projection = Perspective3::new(self.screen.full().aspect(), 70., 1.0, 200.).into_inner();
+                self.renderer.draw(&self.scene, &self.scene.camera, &projection, alpha);
+            }
 
             self.screen.second_step(self.settings.gamma);
 
-            self.gui.draw(&mut self.window, &mut self.scene, &mut self.settings);
+            // The host of a network game starts and ends the race of its clients.
+            if let Some(ref client) = self.client {
+                self.gui.menu.is_ingame = client.is_racing() && client.error().is_none();
+            }
+            let lobby = match (&self.server, &self.client) {
+                (Some(server), _) => Some(server.lobby(&self.scene)),
+                (_, Some(client)) => Some(client.lobby()),
+                _ => None,
+            };
+            let was_ingame = self.gui.menu.is_ingame;
+            // Broken files of models loaded since the last frame, they are drawn as placeholders.
+            self.gui.show_errors(self.assets.take_errors());
+            let frame = FrameInfo {
+                split_screen: players,
+                lobby: lobby.as_ref(),
+                championship: self.championship.as_ref(),
+                assets: self.assets.stats(),
+            };
+            self.gui.draw(&mut self.window, &mut self.scene, &mut self.settings, &frame);
+            // The menus put all cars back on the start grid when a game starts or ends.
+            if self.gui.menu.is_ingame != was_ingame {
+                if self.championship.take().is_some() {
+                    // Back to the level of the settings after a championship.
+                    let map = self.settings.map.clone();
+                    if let Err(e) = self.load_level(&map) {
+                        error!("{}", e);
+                    }
+                }
+                if self.gui.menu.is_ingame && self.gui.menu.is_championship {
+                    self.start_championship();
+                }
+                if let Some(ref mut replay) = self.replay {
+                    replay.record_reset();
+                }
+                if let Some(ref mut server) = self.server {
+                    server.set_racing(self.gui.menu.is_ingame, &self.scene);
+                }
+            }
+            if self.gui.menu.is_next_round {
+                self.gui.menu.is_next_round = false;
+                self.next_round();
+            }
 
             self.window.swap_buffers();
 
@@ -171,6 +408,149 @@ impl Game {
                 self.process_events();
             }
         }
+
+        if self.settings.config != self.initial_config {
+            if let Err(e) = self.settings.config.save(&config_file()) {
+                error!("{}", e);
+            }
+        }
+        if let Some(ref mut server) = self.server {
+            server.shutdown();
+        }
+        if let Some(ref mut client) = self.client {
+            client.leave();
+        }
+        if let Some(ref mut replay) = self.replay {
+            replay.finish(&self.scene);
+            if let Err(e) = replay.save(REPLAY_FILE) {
+                error!("{}", e);
+            }
+        }
+    }
+
+    /// Start the championship of the settings on its first level.
+    fn start_championship(&mut self) {
+        let championship = Championship::new(self.settings.championship.clone());
+        info!("Championship {} starts on {}", championship.name(), championship.level());
+        match self.load_level(championship.level()) {
+            Ok(()) => self.championship = Some(championship),
+            Err(e) => {
+                error!("{}", e);
+                self.gui.menu.is_ingame = false;
+            }
+        }
+    }
+
+    /// Continue the championship on its next level after the scoreboard.
+    ///
+    /// A level that fails to load ends the championship.
+    fn next_round(&mut self) {
+        let level = match self.championship {
+            Some(ref mut championship) => championship.next_round().map(|level| level.to_string()),
+            None => None,
+        };
+        if let Some(level) = level {
+            if let Err(e) = self.load_level(&level) {
+                error!("{}", e);
+                self.championship = None;
+                self.gui.menu.is_ingame = false;
+            }
+        }
+    }
+
+    /// Replace the scene by the level called `map` with the same cars, drivers and camera settings.
+    ///
+    /// The replay starts over on the new level, so it always holds the last level played.
+    fn load_level(&mut self, map: &str) -> Result<(), String> {
+        let mut scene = Scene::new(map, &self.scene.selections)?;
+        for (id, driver) in self.scene.drivers.iter().enumerate() {
+            if driver.is_some() {
+                scene.set_driver(id, self.settings.ai);
+            }
+        }
+        scene.camera.is_smooth_zoom = self.scene.camera.is_smooth_zoom;
+        scene.camera.is_smooth_pan = self.scene.camera.is_smooth_pan;
+        if self.replay.is_some() {
+            self.replay = Some(Replay::new(map, &scene.selections, self.settings.tick_rate));
+        }
+        // The old models are only dropped after the new ones are created, so assets used on both
+        // levels are not loaded again.
+        self.renderer = SceneRenderer::new(&scene, &mut self.assets);
+        self.scene = scene;
+        debug!("Loaded assets: {}", self.assets.stats());
+        Ok(())
+    }
+
+    /// Advance the scene by one tick, together with the players of a hosted network game.
+    fn update_scene(&mut self, dt: f32) {
+        if let Some(ref mut server) = self.server {
+            for id in server.receive(&mut self.scene) {
+                if let Some(ref mut replay) = self.replay {
+                    replay.record_join(&self.scene.selections[id]);
+                }
+            }
+        }
+
+        // Cars of network players keep driving while a menu of the host is open.
+        let mut input = if !self.gui.is_menu_control {
+            self.scene.input(dt, &self.controller)
+        } else {
+            vec![Controller::with_state(0., 0., false); self.scene.cars.len()]
+        };
+        if let Some(ref mut server) = self.server {
+            server.apply_inputs(&mut input);
+        }
+        if let Some(ref mut replay) = self.replay {
+            replay.record(&input);
+        }
+        self.scene.update(dt, &input);
+        if let Some(ref mut server) = self.server {
+            server.send_state(&self.scene);
+        }
+        if let Some(ref mut championship) = self.championship {
+            championship.update(&self.scene);
+        }
+        // Save a new record right away, not only on exit.
+        if let Some(ref mut time_trial) = self.scene.time_trial {
+            if time_trial.is_unsaved() {
+                if let Err(e) = time_trial.save(&data_dir()) {
+                    error!("{}", e);
+                }
+            }
+        }
+    }
+
+    /// Send the input of the player to the host and predict the scene for one tick.
+    fn update_client(&mut self, dt: f32) {
+        let input = if !self.gui.is_menu_control {
+            self.controller[0]
+        } else {
+            Controller::with_state(0., 0., false)
+        };
+        if let Some(ref mut client) = self.client {
+            // Once the connection is lost, the lobby shows why until the game is closed.
+            if client.error().is_none() {
+                match client.update(&mut self.scene, dt, &input) {
+                    Ok(true) => {
+                        self.settings.map = self.scene.level.name.clone();
+                        self.renderer = SceneRenderer::new(&self.scene, &mut self.assets);
+                    }
+                    Ok(false) => {}
+                    Err(e) => error!("{}", e),
+                }
+            }
+        }
+    }
+
+    /// Number of split-screen views, 0 while the shared camera is used.
+    ///
+    /// A network client always uses the shared camera.
+    fn split_screen_players(&self) -> usize {
+        if self.settings.split_screen && self.gui.menu.is_ingame && self.client.is_none() {
+            self.controller.len().min(self.scene.cars.len())
+        } else {
+            0
+        }
     }
 
     pub fn process_events(&mut self) {
@@ -188,10 +568,59 @@ impl Game {
     }
 
     pub fn process_input(&mut self, dt: f32) {
-        for ctrl in &mut self.controller.iter_mut() {
+        let config = &self.settings.config;
+        for (player, ctrl) in self.controller.iter_mut().enumerate() {
+            ctrl.gamepad_settings = config.gamepad;
+            if let (None, Some(keys)) = (ctrl.gamepad(), config.keyboard.get(player)) {
+                ctrl.set_key_bindings(*keys);
+            }
             ctrl.process_input(&self.window, dt);
         }
     }
+
+    /// Every newly plugged in gamepad joins the game as an additional player.
+    ///
+    /// A gamepad that is plugged out keeps its player and car, so it can be plugged in again.
+    /// Gamepads beyond `MAX_PLAYERS` are ignored, as are all gamepads of a network client and
+    /// gamepads that would take over the car of a network player. Nobody joins a time trial.
+    fn connect_gamepads(&mut self) {
+        if self.client.is_some() || self.scene.time_trial.is_some() {
+            return;
+        }
+        for &id in JOYSTICKS.iter() {
+            if self.controller.len() >= MAX_PLAYERS {
+                return;
+            }
+            if self.controller.iter().any(|ctrl| ctrl.gamepad() == Some(id)) {
+                continue;
+            }
+            let joystick = self.glfw.get_joystick(id);
+            if !joystick.is_present() {
+                continue;
+            }
+
+            // Take over a car nobody or the computer drives yet, or bring a new one.
+            let player = self.controller.len();
+            if self.server.as_ref().map_or(false, |server| server.is_remote(player)) {
+                continue;
+            }
+            if player >= self.scene.cars.len() {
+                let mut selection = self.settings.cars[player % self.settings.cars.len()].clone();
+                selection.palette = None;
+                if let Err(e) = self.scene.add_car(&selection) {
+                    error!("Gamepad {:?} can not join: {}", id, e);
+                    continue;
+                }
+                self.renderer.add_new_cars(&self.scene, &mut self.assets);
+                if let Some(ref mut replay) = self.replay {
+                    replay.record_join(&selection);
+                }
+            }
+            self.scene.set_driver(player, None);
+            info!("Gamepad {:?} {:?} joins as player {}", id, joystick.get_name(), player + 1);
+            self.controller.push(Controller::new(false, &ControllerLayout::Gamepad(id)));
+        }
+    }
 }
 
 #[allow(clippy::needless_pass_by_value)]
@@ -0,0 +1,376 @@
+// This file is part of Carambolage.
+
+// Carambolage is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Carambolage is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Carambolage.  If not, see <http://www.gnu.org/licenses/>.
+use super::car::Car;
+use super::manifest::PickupManifest;
+use crate::physx::Aabb;
+use nalgebra::{Matrix4, Rotation3, Vector3};
+use serde_derive::{Deserialize, Serialize};
+
+/// Most pickups on a level, so the state of all of them fits into a network snapshot.
+pub const MAX_PICKUPS: usize = 32;
+/// Most oil slicks on the track at once, the oldest one dries up first.
+pub const MAX_SLICKS: usize = 8;
+/// Height of the pickups above the track in [m], about the center of a car.
+const PICKUP_HEIGHT: f32 = 0.5;
+/// Half size of the box around a pickup cars collect it in [m].
+const PICKUP_SIZE: f32 = 0.75;
+/// Half size of a speed pad in [m].
+const PAD_SIZE: f32 = 1.5;
+/// Rotation speed of the gems in [rad/s].
+const SPIN: f32 = 2.0;
+
+/// Seconds a shield protects from damage.
+pub const SHIELD_TIME: f32 = 6.0;
+/// Seconds a car stays heavy.
+pub const HEAVY_TIME: f32 = 8.0;
+/// Seconds the tires of a car stay oily after driving through a slick.
+pub const OIL_TIME: f32 = 1.5;
+/// Seconds until a slick dries up.
+const SLICK_TIME: f32 = 20.0;
+/// Radius of a slick in [m].
+const SLICK_RADIUS: f32 = 2.0;
+/// Slicks are dropped this far behind the car in [m], so the car does not slip on its own oil.
+const SLICK_OFFSET: f32 = 3.0;
+/// Cars within this distance in [m] are pushed away by a shockwave.
+const SHOCKWAVE_RADIUS: f32 = 12.0;
+/// Impulse in [Ns] of a shockwave on a car right next to it, less further away.
+const SHOCKWAVE_IMPULSE: f32 = 10.0;
+/// Speed in [m/s] a speed pad gives a car in its direction.
+const PAD_SPEED: f32 = 18.0;
+
+/// What a pickup does to the car collecting it.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PickupKind {
+    /// Fills up the boost tank.
+    Boost,
+    /// No damage for `SHIELD_TIME`.
+    Shield,
+    /// Heavier for `HEAVY_TIME`, pushing others around.
+    Mass,
+    /// Drops an oil slick behind the car, cars driving through it lose grip.
+    OilSlick,
+    /// Pushes all cars nearby away.
+    Shockwave,
+    /// Lies flat on the track and launches cars in its direction, usually without respawn time.
+    SpeedPad,
+}
+
+impl PickupKind {
+    /// Seconds until a collected pickup is back, unless the manifest says otherwise.
+    pub fn default_respawn(self) -> f32 {
+        match self {
+            PickupKind::Boost => 8.0,
+            PickupKind::Shield | PickupKind::Mass => 15.0,
+            PickupKind::OilSlick => 12.0,
+            PickupKind::Shockwave => 20.0,
+            PickupKind::SpeedPad => 0.0,
+        }
+    }
+
+    /// Render model, relative to `res/models`.
+    pub fn model(self) -> &'static str {
+        match self {
+            PickupKind::SpeedPad => "pickups/pad.obj",
+            _ => "pickups/gem.obj",
+        }
+    }
+
+    /// Color palette of the render model, relative to `res/textures`.
+    pub fn palette(self) -> &'static str {
+        match self {
+            PickupKind::Boost => "car-orange.png",
+            PickupKind::Shield => "car-blue.png",
+            PickupKind::Mass => "car-purple.png",
+            PickupKind::OilSlick => "pickup-oil.png",
+            PickupKind::Shockwave => "car-yellow.png",
+            PickupKind::SpeedPad => "car-lime.png",
+        }
+    }
+}
+
+/// A power-up placed on the level.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Pickup {
+    pub kind: PickupKind,
+    pub position: Vector3<f32>,
+    /// Rotation around the z-axis in radians, the direction of a speed pad.
+    pub rotation: f32,
+    /// Seconds until the pickup is back after it was collected.
+    pub respawn: f32,
+    /// Seconds until the pickup is back, 0 while it can be collected.
+    pub timer: f32,
+}
+
+impl Pickup {
+    pub fn new(manifest: &PickupManifest) -> Pickup {
+        let height = if manifest.kind == PickupKind::SpeedPad {
+            0.0
+        } else {
+            PICKUP_HEIGHT
+        };
+        Pickup {
+            kind: manifest.kind,
+            position: Vector3::new(manifest.position[0], manifest.position[1], height),
+            rotation: manifest.rotation.to_radians(),
+            respawn: manifest.respawn.unwrap_or_else(|| manifest.kind.default_respawn()),
+            timer: 0.0,
+        }
+    }
+
+    pub fn is_available(&self) -> bool {
+        self.timer <= 0.0
+    }
+
+    /// Cars touching this box collect the pickup.
+    fn aabb(&self) -> Aabb {
+        let size = if self.kind == PickupKind::SpeedPad { PAD_SIZE } else { PICKUP_SIZE };
+        let half_extents = Vector3::new(size, size, 1.0);
+        Aabb::new(self.position - half_extents, self.position + half_extents)
+    }
+
+    /// Model matrix for rendering, gems spin with the `time` in [s].
+    pub fn model_matrix(&self, time: f32) -> Matrix4<f32> {
+        let rotation = if self.kind == PickupKind::SpeedPad {
+            self.rotation
+        } else {
+            time * SPIN
+        };
+        Matrix4::new_translation(&self.position) * Matrix4::from_euler_angles(0., 0., rotation)
+    }
+}
+
+/// Oil dropped on the track.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Slick {
+    pub position: Vector3<f32>,
+    /// Seconds until the slick dries up.
+    pub time: f32,
+}
+
+impl Slick {
+    pub fn model_matrix(&self) -> Matrix4<f32> {
+        Matrix4::new_translation(&self.position)
+    }
+}
+
+/// All power-ups of a level and the oil slicks on the track.
+///
+/// A car driving into an available pickup collects it and the pickup is gone for its respawn
+/// time. Most power-ups set a temporary `Effects` on the car.
+pub struct Pickups {
+    pickups: Vec<Pickup>,
+    slicks: Vec<Slick>,
+    /// Seconds since the level was loaded, spins the gems.
+    time: f32,
+}
+
+impl Pickups {
+    pub fn new(manifests: &[PickupManifest]) -> Pickups {
+        Pickups {
+            pickups: manifests.iter().map(Pickup::new).collect(),
+            slicks: Vec::new(),
+            time: 0.0,
+        }
+    }
+
+    /// Make all pickups available and clean the track.
+    pub fn reset(&mut self) {
+        for pickup in &mut self.pickups {
+            pickup.timer = 0.0;
+        }
+        self.slicks.clear();
+    }
+
+    pub fn pickups(&self) -> &[Pickup] {
+        &self.pickups
+    }
+
+    pub fn slicks(&self) -> &[Slick] {
+        &self.slicks
+    }
+
+    /// Seconds since the level was loaded.
+    pub fn time(&self) -> f32 {
+        self.time
+    }
+
+    /// Take over the pickup timers and slicks from another simulation, e.g. the host of a network game.
+    pub fn set_state(&mut self, timers: &[f32], slicks: &[Slick]) {
+        for (pickup, &timer) in self.pickups.iter_mut().zip(timers) {
+            pickup.timer = timer;
+        }
+        self.slicks = slicks.to_vec();
+    }
+
+    /// Count down the respawn times and let all cars for which `is_playing` is true collect pickups
+    /// after a simulation step of `dt`.
+    pub fn update<F: Fn(usize) -> bool>(&mut self, dt: f32, cars: &mut [Car], is_playing: F) {
+        self.time += dt;
+        for pickup in &mut self.pickups {
+            pickup.timer = (pickup.timer - dt).max(0.0);
+        }
+        for slick in &mut self.slicks {
+            slick.time -= dt;
+        }
+        self.slicks.retain(|slick| slick.time > 0.0);
+
+        for id in (0..cars.len()).filter(|&id| is_playing(id)) {
+            let aabb = cars[id].body.aabb();
+            for i in 0..self.pickups.len() {
+                let pickup = self.pickups[i];
+                if pickup.is_available() && pickup.aabb().intersects(&aabb) {
+                    self.pickups[i].timer = pickup.respawn;
+                    self.collect(&pickup, id, cars, &is_playing);
+                }
+            }
+
+            let position = cars[id].body.position;
+            let is_oily = self.slicks.iter().any(|slick| {
+                let offset = position - slick.position;
+                offset[0] * offset[0] + offset[1] * offset[1] < SLICK_RADIUS * SLICK_RADIUS
+            });
+            if is_oily {
+                cars[id].effects.oil = OIL_TIME;
+            }
+        }
+    }
+
+    /// Apply the power-up of `pickup` to car `id`.
+    fn collect<F: Fn(usize) -> bool>(&mut self, pickup: &Pickup, id: usize, cars: &mut [Car], is_playing: &F) {
+        let car = &mut cars[id];
+        match pickup.kind {
+            PickupKind::Boost => car.boost = car.stats.boost_capacity,
+            PickupKind::Shield => car.effects.shield = SHIELD_TIME,
+            PickupKind::Mass => car.effects.heavy = HEAVY_TIME,
+            PickupKind::OilSlick => {
+                let mut position = car.body.position - car.body.forward() * SLICK_OFFSET;
+                position[2] = 0.0;
+                if self.slicks.len() >= MAX_SLICKS {
+                    self.slicks.remove(0);
+                }
+                self.slicks.push(Slick {
+                    position,
+                    time: SLICK_TIME,
+                });
+            }
+            PickupKind::Shockwave => {
+                let center = car.body.position;
+                for (other, car) in cars.iter_mut().enumerate() {
+                    let mut offset = car.body.position - center;
+                    offset[2] = 0.0;
+                    let distance = offset.norm();
+                    if other == id || !is_playing(other) || distance >= SHOCKWAVE_RADIUS || distance < 1e-3 {
+                        continue;
+                    }
+                    let impulse = offset / distance * SHOCKWAVE_IMPULSE * (1.0 - distance / SHOCKWAVE_RADIUS);
+                    car.body.apply_impulse(&impulse);
+                }
+            }
+            PickupKind::SpeedPad => {
+                let direction = Rotation3::from_euler_angles(0., 0., pickup.rotation) * Vector3::new(0., 1., 0.);
+                let speed = car.body.velocity.dot(&direction);
+                if speed < PAD_SPEED {
+                    car.body.velocity += direction * (PAD_SPEED - speed);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{PickupKind, Pickups, HEAVY_TIME, OIL_TIME, SHIELD_TIME};
+    use crate::game::car::Car;
+    use crate::game::manifest::PickupManifest;
+    use nalgebra::Vector3;
+
+    fn pickup(kind: PickupKind, x: f32, respawn: Option<f32>) -> PickupManifest {
+        PickupManifest {
+            kind,
+            position: [x, 0.0],
+            rotation: 0.0,
+            respawn,
+        }
+    }
+
+    /// Cars standing on the x-axis, facing +y.
+    fn cars(xs: &[f32]) -> Vec<Car> {
+        xs.iter()
+            .map(|&x| {
+                let mut car = Car::test_kart();
+                car.body.position = Vector3::new(x, 0.0, 0.4);
+                car
+            })
+            .collect()
+    }
+
+    #[test]
+    fn collect_and_respawn() {
+        let mut pickups = Pickups::new(&[
+            pickup(PickupKind::Boost, 0.0, Some(1.0)),
+            pickup(PickupKind::Shield, 20.0, None),
+            pickup(PickupKind::Mass, 40.0, None),
+        ]);
+        let mut cars = cars(&[0.0, 20.0, 40.0, 60.0]);
+        cars[0].boost = 0.0;
+        pickups.update(0.1, &mut cars, |id| id != 2);
+
+        assert_eq!(cars[0].boost, cars[0].stats.boost_capacity);
+        assert_eq!(cars[1].effects.shield, SHIELD_TIME);
+        // Knocked out cars collect nothing.
+        assert_eq!(cars[2].effects.heavy, 0.0);
+        let available: Vec<bool> = pickups.pickups().iter().map(|p| p.is_available()).collect();
+        assert_eq!(available, vec![false, false, true]);
+
+        // The boost is back after its respawn time, the shield takes longer.
+        for _ in 0..10 {
+            cars[0].body.position[0] = 100.0;
+            pickups.update(0.1, &mut cars, |_| true);
+        }
+        assert!(pickups.pickups()[0].is_available());
+        assert!(!pickups.pickups()[1].is_available());
+        assert_eq!(cars[2].effects.heavy, HEAVY_TIME);
+
+        pickups.reset();
+        assert!(pickups.pickups().iter().all(|p| p.is_available()));
+    }
+
+    #[test]
+    fn hazards() {
+        let mut pickups = Pickups::new(&[
+            pickup(PickupKind::OilSlick, 0.0, None),
+            pickup(PickupKind::Shockwave, 20.0, None),
+            pickup(PickupKind::SpeedPad, 40.0, None),
+        ]);
+        let mut cars = cars(&[0.0, 20.0, 24.0, 40.0]);
+        pickups.update(0.1, &mut cars, |_| true);
+
+        // The slick lies behind the car that dropped it.
+        assert_eq!(pickups.slicks().len(), 1);
+        assert!((pickups.slicks()[0].position - Vector3::new(0.0, -3.0, 0.0)).norm() < 1e-5);
+        assert_eq!(cars[0].effects.oil, 0.0);
+        // The shockwave pushes the neighbour away, but not the car collecting it.
+        assert!(cars[2].body.velocity[0] > 1.0);
+        assert_eq!(cars[1].body.velocity[0], 0.0);
+        // Speed pads launch the car and stay.
+        assert!(cars[3].body.velocity[1] > 10.0);
+        assert!(pickups.pickups()[2].is_available());
+
+        cars[1].body.position = Vector3::new(0.5, -3.0, 0.4);
+        pickups.update(0.1, &mut cars, |_| true);
+        assert_eq!(cars[1].effects.oil, OIL_TIME);
+    }
+}
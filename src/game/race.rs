@@ -0,0 +1,451 @@
+// This file is part of Carambolage.
+
+// Carambolage is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Carambolage is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Carambolage.  If not, see <http://www.gnu.org/licenses/>.
+use super::car::Car;
+use nalgebra::{Rotation3, Vector3};
+use std::cmp::Ordering;
+
+/// Checkpoints only count cars passing at most this far above or below their center in [m].
+const CHECKPOINT_HALF_HEIGHT: f32 = 3.0;
+/// Cars slower than this in [m/s] are never considered driving the wrong way.
+const WRONG_WAY_SPEED: f32 = 2.0;
+/// Seconds a car has to drive against the track direction before it is driving the wrong way.
+const WRONG_WAY_TIME: f32 = 1.0;
+
+/// A gate on the track the cars have to drive through.
+///
+/// The checkpoint is a thin box volume standing upright on the track. Like for the cars its local
+/// +y-axis points forward, which is the direction the cars have to pass it.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Checkpoint {
+    /// Center of the gate in world space.
+    pub position: Vector3<f32>,
+    /// Rotation around the z-axis in radians. 0 lets the cars pass in +y direction.
+    pub rotation: f32,
+    /// Half width of the gate across the track in [m].
+    pub half_width: f32,
+}
+
+/// How a car moved through a `Checkpoint`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Crossing {
+    None,
+    Forward,
+    Backward,
+}
+
+impl Checkpoint {
+    pub fn new(position: Vector3<f32>, rotation: f32, half_width: f32) -> Checkpoint {
+        Checkpoint {
+            position,
+            rotation,
+            half_width,
+        }
+    }
+
+    /// Unit vector in driving direction.
+    pub fn forward(&self) -> Vector3<f32> {
+        Rotation3::from_euler_angles(0., 0., self.rotation) * Vector3::new(0., 1., 0.)
+    }
+
+    /// Check if a car moving from `from` to `to` in one step passed the gate.
+    pub fn crossing(&self, from: &Vector3<f32>, to: &Vector3<f32>) -> Crossing {
+        let rotation = Rotation3::from_euler_angles(0., 0., -self.rotation);
+        let from = rotation * (from - self.position);
+        let to = rotation * (to - self.position);
+
+        let direction = if from[1] < 0.0 && to[1] >= 0.0 {
+            Crossing::Forward
+        } else if from[1] >= 0.0 && to[1] < 0.0 {
+            Crossing::Backward
+        } else {
+            return Crossing::None;
+        };
+
+        // Where did the car pass the plane of the gate?
+        let t = from[1] / (from[1] - to[1]);
+        let hit = from + (to - from) * t;
+        if hit[0].abs() <= self.half_width && hit[2].abs() <= CHECKPOINT_HALF_HEIGHT {
+            direction
+        } else {
+            Crossing::None
+        }
+    }
+}
+
+/// Checkpoints and number of laps of a level, see `LevelManifest`.
+///
+/// The first checkpoint is the start and finish line, the others follow in driving order.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Course {
+    pub checkpoints: Vec<Checkpoint>,
+    pub laps: u32,
+}
+
+/// Race state of a single car.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct RaceProgress {
+    /// Checkpoints passed in the right order. The start line counts at the start of every lap.
+    passed: usize,
+    /// Race time in [s] when the car crossed the finish line after the last lap.
+    pub finish_time: Option<f32>,
+    /// The car drives against the track direction.
+    pub wrong_way: bool,
+    wrong_way_time: f32,
+    /// The car was wrecked before it finished, it is out of the race.
+    pub is_wrecked: bool,
+    /// Distance to the next checkpoint in [m], ranks cars in the same section of the track.
+    distance: f32,
+}
+
+impl RaceProgress {
+    /// Checkpoints passed in the right order, counting the start line at the start of every lap.
+    pub fn passed(&self) -> usize {
+        self.passed
+    }
+}
+
+/// A line of the standings table.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Standing {
+    /// Index of the car in `Scene::cars`.
+    pub car: usize,
+    /// Current lap, see `Race::lap()`.
+    pub lap: u32,
+    pub finish_time: Option<f32>,
+    pub is_wrecked: bool,
+}
+
+/// Lap counting and ranking of all cars on a `Course`.
+///
+/// Every car has to pass the checkpoints in order. Crossing the start line starts the first lap
+/// and crossing it after the last checkpoint of the last lap finishes the race for the car.
+/// Driving back through a checkpoint takes it back, so nobody gains anything by turning around.
+pub struct Race {
+    course: Course,
+    time: f32,
+    progress: Vec<RaceProgress>,
+}
+
+impl Race {
+    pub fn new(course: Course) -> Race {
+        Race {
+            course,
+            time: 0.0,
+            progress: Vec::new(),
+        }
+    }
+
+    /// Restart the race for all cars.
+    pub fn reset(&mut self) {
+        self.time = 0.0;
+        self.progress.clear();
+    }
+
+    /// Take over the race time from another simulation, e.g. the host of a network game.
+    pub fn set_time(&mut self, time: f32) {
+        self.time = time;
+    }
+
+    /// Take over the progress of car `id` from another simulation.
+    pub fn set_progress(&mut self, id: usize, passed: usize, finish_time: Option<f32>) {
+        if id >= self.progress.len() {
+            self.progress.resize(id + 1, Default::default());
+        }
+        let progress = &mut self.progress[id];
+        progress.passed = passed;
+        progress.finish_time = finish_time;
+    }
+
+    /// Number of laps to finish the race.
+    pub fn laps(&self) -> u32 {
+        self.course.laps
+    }
+
+    /// Number of checkpoints every lap passes, including the start and finish line.
+    pub fn checkpoints(&self) -> usize {
+        self.course.checkpoints.len()
+    }
+
+    /// Time since the start of the race in [s].
+    pub fn time(&self) -> f32 {
+        self.time
+    }
+
+    /// Return false if the level has no checkpoints. There is nothing to win then.
+    pub fn is_active(&self) -> bool {
+        !self.course.checkpoints.is_empty()
+    }
+
+    /// Race state of car `id`.
+    pub fn progress(&self, id: usize) -> RaceProgress {
+        self.progress.get(id).cloned().unwrap_or_default()
+    }
+
+    /// Current lap of car `id` from 1 to `laps()`, or 0 before it crossed the start line.
+    pub fn lap(&self, id: usize) -> u32 {
+        let n = self.course.checkpoints.len();
+        if n == 0 {
+            return 0;
+        }
+        let passed = self.progress(id).passed;
+        (((passed + n - 1) / n) as u32).min(self.course.laps)
+    }
+
+    /// Return true once every car has finished or is wrecked.
+    pub fn is_over(&self) -> bool {
+        self.is_active() && !self.progress.is_empty() && self.progress.iter().all(|p| p.finish_time.is_some() || p.is_wrecked)
+    }
+
+    /// Check all cars against the checkpoints after a simulation step of `dt`.
+    pub fn update(&mut self, dt: f32, cars: &[Car]) {
+        self.progress.resize(cars.len(), Default::default());
+        if !self.is_active() || self.is_over() {
+            return;
+        }
+        self.time += dt;
+        for (id, car) in cars.iter().enumerate() {
+            let progress = &mut self.progress[id];
+            if car.is_wrecked() && progress.finish_time.is_none() {
+                progress.is_wrecked = true;
+                progress.wrong_way = false;
+            }
+            if !progress.is_wrecked {
+                self.update_car(id, dt, &car.previous_position(), &car.body.position);
+            }
+        }
+    }
+
+    /// Track a single car moving from `from` to `to`.
+    fn update_car(&mut self, id: usize, dt: f32, from: &Vector3<f32>, to: &Vector3<f32>) {
+        let n = self.course.checkpoints.len();
+        let finish = self.course.laps as usize * n + 1;
+        let time = self.time;
+        let direction = self.track_direction(id, to);
+        let progress = &mut self.progress[id];
+        if progress.finish_time.is_some() {
+            progress.wrong_way = false;
+            return;
+        }
+
+        let next = &self.course.checkpoints[progress.passed % n];
+        if next.crossing(from, to) == Crossing::Forward {
+            progress.passed += 1;
+            if progress.passed == finish {
+                progress.finish_time = Some(time);
+            }
+        } else if progress.passed > 0 {
+            let last = &self.course.checkpoints[(progress.passed - 1) % n];
+            if last.crossing(from, to) == Crossing::Backward {
+                progress.passed -= 1;
+            }
+        }
+
+        // Driving against the track direction for a while, not just bouncing off a wall.
+        let movement = to - from;
+        let speed = movement.norm() / dt;
+        match direction {
+            Some(direction) if speed > WRONG_WAY_SPEED && movement.normalize().dot(&direction) < -0.5 => {
+                progress.wrong_way_time += dt;
+            }
+            _ => progress.wrong_way_time = 0.0,
+        }
+        progress.wrong_way = progress.wrong_way_time >= WRONG_WAY_TIME;
+        progress.distance = (self.course.checkpoints[progress.passed % n].position - to).norm();
+    }
+
+    /// Direction of the track at `position` of car `id`.
+    ///
+    /// Blends the directions of the last passed and the next checkpoint by their distance.
+    fn track_direction(&self, id: usize, position: &Vector3<f32>) -> Option<Vector3<f32>> {
+        let n = self.course.checkpoints.len();
+        let passed = self.progress[id].passed;
+        let next = &self.course.checkpoints[passed % n];
+        if passed == 0 {
+            return Some(next.forward());
+        }
+        let last = &self.course.checkpoints[(passed - 1) % n];
+        let to_last = (last.position - position).norm();
+        let to_next = (next.position - position).norm();
+        let direction = last.forward() * to_next + next.forward() * to_last;
+        if direction.norm() > 1e-3 {
+            Some(direction.normalize())
+        } else {
+            None
+        }
+    }
+
+    /// Ranking of all cars, the leader first.
+    ///
+    /// Finished cars are ordered by their finish time, all others by their progress on the track.
+    pub fn standings(&self) -> Vec<Standing> {
+        let mut order: Vec<usize> = (0..self.progress.len()).collect();
+        if self.is_active() {
+            order.sort_by(|&a, &b| {
+                let (pa, pb) = (&self.progress[a], &self.progress[b]);
+                match (pa.finish_time, pb.finish_time) {
+                    (Some(ta), Some(tb)) => ta.partial_cmp(&tb).unwrap_or(Ordering::Equal),
+                    (Some(_), None) => Ordering::Less,
+                    (None, Some(_)) => Ordering::Greater,
+                    (None, None) => pb
+                        .passed
+                        .cmp(&pa.passed)
+                        .then_with(|| pa.distance.partial_cmp(&pb.distance).unwrap_or(Ordering::Equal)),
+                }
+            });
+        }
+        order
+            .into_iter()
+            .map(|car| Standing {
+                car,
+                lap: self.lap(car),
+                finish_time: self.progress[car].finish_time,
+                is_wrecked: self.progress[car].is_wrecked,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Checkpoint, Course, Crossing, Race};
+    use nalgebra::Vector3;
+
+    /// A square course of 40m around the origin, driven counterclockwise.
+    fn square() -> Course {
+        let checkpoint = |x: f32, y: f32, rotation: f32| Checkpoint::new(Vector3::new(x, y, 0.), rotation.to_radians(), 5.0);
+        Course {
+            checkpoints: vec![
+                checkpoint(20., 0., 0.),
+                checkpoint(0., 20., 90.),
+                checkpoint(-20., 0., 180.),
+                checkpoint(0., -20., -90.),
+            ],
+            laps: 2,
+        }
+    }
+
+    /// Drive car `id` once around the square starting right behind the start line.
+    fn drive_lap(race: &mut Race, id: usize) {
+        let corners = [(20., -1.), (20., 20.), (-20., 20.), (-20., -20.), (20., -20.), (20., -1.)];
+        for corner in corners.windows(2) {
+            let from = Vector3::new((corner[0]).0, (corner[0]).1, 0.);
+            let to = Vector3::new((corner[1]).0, (corner[1]).1, 0.);
+            for i in 0..20 {
+                let a = from + (to - from) * (i as f32 / 20.);
+                let b = from + (to - from) * ((i + 1) as f32 / 20.);
+                race.update_car(id, 0.1, &a, &b);
+            }
+        }
+    }
+
+    #[test]
+    fn crossing() {
+        let checkpoint = Checkpoint::new(Vector3::new(1., 1., 0.), std::f32::consts::FRAC_PI_2, 2.0);
+        assert_eq!(
+            checkpoint.crossing(&Vector3::new(2., 1., 0.), &Vector3::new(0., 1., 0.)),
+            Crossing::Forward
+        );
+        assert_eq!(
+            checkpoint.crossing(&Vector3::new(0., 2., 0.), &Vector3::new(2., 2., 0.)),
+            Crossing::Backward
+        );
+        // Beside the gate, too high and not passing at all.
+        assert_eq!(
+            checkpoint.crossing(&Vector3::new(2., 4., 0.), &Vector3::new(0., 4., 0.)),
+            Crossing::None
+        );
+        assert_eq!(
+            checkpoint.crossing(&Vector3::new(2., 1., 5.), &Vector3::new(0., 1., 5.)),
+            Crossing::None
+        );
+        assert_eq!(
+            checkpoint.crossing(&Vector3::new(3., 1., 0.), &Vector3::new(2., 1., 0.)),
+            Crossing::None
+        );
+    }
+
+    #[test]
+    fn count_laps_and_finish() {
+        let mut race = Race::new(square());
+        race.progress.resize(2, Default::default());
+        assert_eq!(race.lap(0), 0);
+
+        drive_lap(&mut race, 0);
+        assert_eq!(race.lap(0), 1);
+        assert_eq!(race.progress(0).passed, 4);
+        assert_eq!(race.standings()[0].car, 0);
+
+        drive_lap(&mut race, 0);
+        assert_eq!(race.lap(0), 2);
+        // Only crossing the finish line after the last lap finishes the race.
+        assert!(race.progress(0).finish_time.is_none());
+        race.update_car(0, 0.1, &Vector3::new(20., -1., 0.), &Vector3::new(20., 1., 0.));
+        assert!(race.progress(0).finish_time.is_some());
+        assert_eq!(race.lap(0), 2);
+        assert!(!race.is_over());
+
+        race.time = 10.0;
+        drive_lap(&mut race, 1);
+        drive_lap(&mut race, 1);
+        race.update_car(1, 0.1, &Vector3::new(20., -1., 0.), &Vector3::new(20., 1., 0.));
+        assert!(race.is_over());
+        let standings = race.standings();
+        assert_eq!(standings[0].car, 0);
+        assert_eq!(standings[1].car, 1);
+    }
+
+    #[test]
+    fn skipped_checkpoints_do_not_count() {
+        let mut race = Race::new(square());
+        race.progress.resize(1, Default::default());
+        race.update_car(0, 0.1, &Vector3::new(20., -1., 0.), &Vector3::new(20., 1., 0.));
+        // Shortcut straight to the third checkpoint.
+        race.update_car(0, 0.1, &Vector3::new(-19., 0., 0.), &Vector3::new(-21., 0., 0.));
+        assert_eq!(race.progress(0).passed, 1);
+    }
+
+    #[test]
+    fn wrong_way() {
+        let mut race = Race::new(square());
+        race.progress.resize(1, Default::default());
+        race.update_car(0, 0.1, &Vector3::new(20., -1., 0.), &Vector3::new(20., 1., 0.));
+        race.update_car(0, 0.1, &Vector3::new(20., 1., 0.), &Vector3::new(20., 2., 0.));
+        assert!(!race.progress(0).wrong_way);
+
+        // Turn around and drive back through the start line.
+        let mut y = 2.0;
+        for _ in 0..20 {
+            race.update_car(0, 0.1, &Vector3::new(20., y, 0.), &Vector3::new(20., y - 1.0, 0.));
+            y -= 1.0;
+        }
+        assert!(race.progress(0).wrong_way);
+        assert_eq!(race.progress(0).passed, 0);
+        assert_eq!(race.lap(0), 0);
+
+        race.update_car(0, 0.1, &Vector3::new(20., y, 0.), &Vector3::new(20., y + 1.0, 0.));
+        assert!(!race.progress(0).wrong_way);
+    }
+
+    #[test]
+    fn no_checkpoints() {
+        let mut race = Race::new(Default::default());
+        assert!(!race.is_active());
+        race.progress.resize(3, Default::default());
+        assert!(!race.is_over());
+        assert_eq!(race.lap(1), 0);
+        let cars: Vec<usize> = race.standings().iter().map(|s| s.car).collect();
+        assert_eq!(cars, vec![0, 1, 2]);
+    }
+}
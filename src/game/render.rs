@@ -0,0 +1,99 @@
+// This file is part of Carambolage.
+
+// Carambolage is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Carambolage is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Carambolage.  If not, see <http://www.gnu.org/licenses/>.
+use super::pickup::PickupKind;
+use super::scene::Scene;
+use super::time_trial::GHOST_ALPHA;
+use crate::grphx::{Assets, Camera, Model};
+use nalgebra::Matrix4;
+
+/// Render resources of a `Scene`.
+///
+/// The `Scene` only holds the simulation state, so it can run without a window or an OpenGL
+/// context. Everything that lives on the GPU is requested here from the shared `Assets`.
+pub struct SceneRenderer {
+    level: Model,
+    cars: Vec<Model>,
+    /// One model per pickup of the level.
+    pickups: Vec<Model>,
+    slick: Model,
+    /// Translucent copy of the car of a time trial.
+    ghost: Option<Model>,
+}
+
+impl SceneRenderer {
+    /// Load the models of the level, of its pickups and of all cars in `scene`, and the ghost of
+    /// a time trial.
+    pub fn new(scene: &Scene, assets: &mut Assets) -> SceneRenderer {
+        let manifest = &scene.level.manifest;
+        let level = Model::new(assets, &manifest.model, &manifest.texture);
+        let cars = scene.cars.iter().map(|car| Model::new(assets, &car.model, &car.palette)).collect();
+        let pickups = scene
+            .pickups
+            .pickups()
+            .iter()
+            .map(|pickup| Model::new(assets, pickup.kind.model(), pickup.kind.palette()))
+            .collect();
+        let slick = Model::new(assets, "pickups/slick.obj", PickupKind::OilSlick.palette());
+        let ghost = match (&scene.time_trial, scene.cars.first()) {
+            (Some(_), Some(car)) => Some(Model::new(assets, &car.model, &car.palette)),
+            _ => None,
+        };
+
+        SceneRenderer {
+            level,
+            cars,
+            pickups,
+            slick,
+            ghost,
+        }
+    }
+
+    /// Load the models of cars added to `scene` since the last call.
+    pub fn add_new_cars(&mut self, scene: &Scene, assets: &mut Assets) {
+        for car in &scene.cars[self.cars.len().min(scene.cars.len())..] {
+            self.cars.push(Model::new(assets, &car.model, &car.palette));
+        }
+    }
+
+    /// Draw the entire `Scene` seen by `camera` to the bound framebuffer.
+    ///
+    /// `alpha` interpolates all objects between the previous and the current simulation step.
+    pub fn draw(&self, scene: &Scene, camera: &Camera, projection: &Matrix4<f32>, alpha: f32) {
+        let view = camera.get_viewmatrix();
+        // Draw map. No scaling, rotating, translating.
+        self.level.draw(&Matrix4::identity(), &view, projection);
+        for slick in scene.pickups.slicks() {
+            self.slick.draw(&slick.model_matrix(), &view, projection);
+        }
+        let time = scene.pickups.time();
+        for (pickup, model) in scene.pickups.pickups().iter().zip(&self.pickups) {
+            if pickup.is_available() {
+                model.draw(&pickup.model_matrix(time), &view, projection);
+            }
+        }
+        // Draw objects, knocked out cars are gone until the next derby round.
+        for (id, (car, model)) in scene.cars.iter().zip(&self.cars).enumerate() {
+            if scene.is_playing(id) {
+                model.draw(&car.model_matrix(alpha), &view, projection);
+            }
+        }
+        // The ghost is see-through, so it comes last.
+        if let (Some(ghost), Some(time_trial)) = (&self.ghost, &scene.time_trial) {
+            if let Some(matrix) = time_trial.ghost_matrix(alpha) {
+                ghost.draw_translucent(&matrix, &view, projection, GHOST_ALPHA);
+            }
+        }
+    }
+}
@@ -0,0 +1,378 @@
+// This file is part of Carambolage.
+
+// Carambolage is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Carambolage is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Carambolage.  If not, see <http://www.gnu.org/licenses/>.
+use super::car_definition::CarSelection;
+use super::controller::Controller;
+use super::scene::Scene;
+use std::fmt;
+use std::fs;
+
+/// The game records every session to this file, next to the log file.
+pub const REPLAY_FILE: &str = "carambolage.replay";
+
+/// Something that happened before a tick of a replay.
+#[derive(Debug, Clone, PartialEq)]
+enum ReplayAction {
+    /// Input of a car from this tick on.
+    Input { car: usize, x_axis: f32, y_axis: f32, boost: bool },
+    /// A car joined the match, e.g. with a gamepad.
+    Join(CarSelection),
+    /// All cars were put back on the start grid.
+    Reset,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct ReplayEvent {
+    tick: u32,
+    action: ReplayAction,
+}
+
+/// Input of every car for every tick of a match, enough to simulate it again bit for bit.
+///
+/// A replay is a text file with the level, the cars and the tick rate, followed by everything
+/// that happened before a tick:
+/// ```text
+/// map race_track_1
+/// cars kart,formula:3
+/// tick_rate 60
+/// ticks 5400
+/// checksum 9e3779b97f4a7c15
+/// # tick car x_axis y_axis boost
+/// 0 0 0.0 1.0 0
+/// 120 1 -1.0 1.0 1
+/// 3000 join lamba
+/// 3600 reset
+/// ```
+/// Inputs are only written when they change, so a car keeps its input until the next line for
+/// it. The checksum of the final state tells whether a playback ended up in the same state.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Replay {
+    /// Name of the level, see `LevelManifest`.
+    pub map: String,
+    /// Cars at the start of the match.
+    pub cars: Vec<CarSelection>,
+    pub tick_rate: u32,
+    /// Number of recorded ticks.
+    ticks: u32,
+    /// See `checksum()`, `None` until the recording is finished.
+    checksum: Option<u64>,
+    events: Vec<ReplayEvent>,
+    /// Input of every car at the current tick, while recording or playing back.
+    inputs: Vec<(f32, f32, bool)>,
+    /// Next event to play back.
+    next: usize,
+}
+
+impl Replay {
+    /// Start recording a match on `map` with `cars`.
+    pub fn new(map: &str, cars: &[CarSelection], tick_rate: u32) -> Replay {
+        Replay {
+            map: map.to_string(),
+            cars: cars.to_vec(),
+            tick_rate,
+            ticks: 0,
+            checksum: None,
+            events: Vec::new(),
+            inputs: vec![(0., 0., false); cars.len()],
+            next: 0,
+        }
+    }
+
+    /// Load a replay from a file.
+    pub fn load(file: &str) -> Result<Replay, String> {
+        let text = fs::read_to_string(file).map_err(|e| format!("Failed to read {}: {}", file, e))?;
+        Self::parse(&text).map_err(|e| format!("{}: {}", file, e))
+    }
+
+    /// Parse a replay from text.
+    pub fn parse(text: &str) -> Result<Replay, String> {
+        let (mut map, mut cars, mut tick_rate, mut ticks, mut checksum) = (None, None, None, None, None);
+        let mut events = Vec::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let invalid = |name: &str| format!("line {}: invalid {} '{}'", number + 1, name, line);
+            match (fields[0], fields.len()) {
+                ("map", 2) => map = Some(fields[1].to_string()),
+                ("cars", 2) => cars = Some(CarSelection::parse_list(fields[1]).map_err(|e| format!("line {}: {}", number + 1, e))?),
+                ("tick_rate", 2) => tick_rate = Some(fields[1].parse().map_err(|_| invalid("tick_rate"))?),
+                ("ticks", 2) => ticks = Some(fields[1].parse().map_err(|_| invalid("ticks"))?),
+                ("checksum", 2) => checksum = Some(u64::from_str_radix(fields[1], 16).map_err(|_| invalid("checksum"))?),
+                (_, 2) if fields[1] == "reset" => events.push(ReplayEvent {
+                    tick: fields[0].parse().map_err(|_| invalid("tick"))?,
+                    action: ReplayAction::Reset,
+                }),
+                (_, 3) if fields[1] == "join" => events.push(ReplayEvent {
+                    tick: fields[0].parse().map_err(|_| invalid("tick"))?,
+                    action: ReplayAction::Join(
+                        CarSelection::parse_list(fields[2])
+                            .ok()
+                            .and_then(|mut cars| cars.pop())
+                            .ok_or_else(|| invalid("car"))?,
+                    ),
+                }),
+                (_, 5) => events.push(ReplayEvent {
+                    tick: fields[0].parse().map_err(|_| invalid("tick"))?,
+                    action: ReplayAction::Input {
+                        car: fields[1].parse().map_err(|_| invalid("car"))?,
+                        x_axis: fields[2].parse().map_err(|_| invalid("x_axis"))?,
+                        y_axis: fields[3].parse().map_err(|_| invalid("y_axis"))?,
+                        boost: match fields[4] {
+                            "0" => false,
+                            "1" => true,
+                            _ => return Err(invalid("boost")),
+                        },
+                    },
+                }),
+                _ => return Err(format!("line {}: unexpected '{}'", number + 1, line)),
+            }
+        }
+        if events.windows(2).any(|pair| pair[0].tick > pair[1].tick) {
+            return Err("events are not ordered by tick".to_string());
+        }
+
+        let missing = |name: &str| format!("missing {}", name);
+        let cars: Vec<CarSelection> = cars.ok_or_else(|| missing("cars"))?;
+        let mut replay = Replay::new(
+            &map.ok_or_else(|| missing("map"))?,
+            &cars,
+            tick_rate.ok_or_else(|| missing("tick_rate"))?,
+        );
+        replay.ticks = ticks.ok_or_else(|| missing("ticks"))?;
+        replay.checksum = checksum;
+        replay.events = events;
+        Ok(replay)
+    }
+
+    /// Write the replay to a file.
+    pub fn save(&self, file: &str) -> Result<(), String> {
+        fs::write(file, self.to_string()).map_err(|e| format!("Failed to write {}: {}", file, e))
+    }
+
+    /// Number of recorded ticks.
+    pub fn ticks(&self) -> u32 {
+        self.ticks
+    }
+
+    /// Record the `input` of all cars for the next tick, cars without input get none.
+    pub fn record(&mut self, input: &[Controller]) {
+        for car in 0..self.inputs.len() {
+            let state = input
+                .get(car)
+                .map_or((0., 0., false), |ctrl| (ctrl.get_x_axis(), ctrl.get_y_axis(), ctrl.get_boost()));
+            // Compare the bits, so even a change of the sign of zero is kept.
+            let last = self.inputs[car];
+            if state.0.to_bits() != last.0.to_bits() || state.1.to_bits() != last.1.to_bits() || state.2 != last.2 {
+                self.push(ReplayAction::Input {
+                    car,
+                    x_axis: state.0,
+                    y_axis: state.1,
+                    boost: state.2,
+                });
+                self.inputs[car] = state;
+            }
+        }
+        self.ticks += 1;
+    }
+
+    /// Record a car joining before the next tick.
+    pub fn record_join(&mut self, selection: &CarSelection) {
+        self.push(ReplayAction::Join(selection.clone()));
+        self.inputs.push((0., 0., false));
+    }
+
+    /// Record that all cars were put back on the start grid before the next tick.
+    pub fn record_reset(&mut self) {
+        self.push(ReplayAction::Reset);
+    }
+
+    /// Finish the recording with the final state of the `scene`.
+    pub fn finish(&mut self, scene: &Scene) {
+        self.checksum = Some(checksum(scene));
+    }
+
+    fn push(&mut self, action: ReplayAction) {
+        self.events.push(ReplayEvent { tick: self.ticks, action });
+    }
+
+    /// The scene at the start of the recording.
+    pub fn scene(&self) -> Result<Scene, String> {
+        Scene::new(&self.map, &self.cars)
+    }
+
+    /// Play back everything that happened before `tick` on the `scene` and return the input of all
+    /// cars for it.
+    ///
+    /// Ticks have to be passed in increasing order, starting at 0 with `scene()`.
+    pub fn play(&mut self, tick: u32, scene: &mut Scene) -> Result<Vec<Controller>, String> {
+        while self.next < self.events.len() && self.events[self.next].tick <= tick {
+            match self.events[self.next].action {
+                ReplayAction::Input {
+                    car,
+                    x_axis,
+                    y_axis,
+                    boost,
+                } => match self.inputs.get_mut(car) {
+                    Some(input) => *input = (x_axis, y_axis, boost),
+                    None => return Err(format!("Input for unknown car {} at tick {}", car, tick)),
+                },
+                ReplayAction::Join(ref selection) => {
+                    scene.add_car(selection)?;
+                    self.inputs.push((0., 0., false));
+                }
+                ReplayAction::Reset => scene.reset_cars(),
+            }
+            self.next += 1;
+        }
+        Ok(self
+            .inputs
+            .iter()
+            .map(|&(x_axis, y_axis, boost)| Controller::with_state(x_axis, y_axis, boost))
+            .collect())
+    }
+
+    /// Whether the `scene` after the playback ended in the recorded state, `None` for replays
+    /// without checksum.
+    pub fn verify(&self, scene: &Scene) -> Option<bool> {
+        self.checksum.map(|expected| expected == checksum(scene))
+    }
+}
+
+impl fmt::Display for Replay {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let cars: Vec<String> = self.cars.iter().map(|car| car.to_string()).collect();
+        writeln!(f, "map {}", self.map)?;
+        writeln!(f, "cars {}", cars.join(","))?;
+        writeln!(f, "tick_rate {}", self.tick_rate)?;
+        writeln!(f, "ticks {}", self.ticks)?;
+        if let Some(checksum) = self.checksum {
+            writeln!(f, "checksum {:016x}", checksum)?;
+        }
+        writeln!(f, "# tick car x_axis y_axis boost")?;
+        for event in &self.events {
+            match event.action {
+                // Debug formatting of floats parses back to the same bits.
+                ReplayAction::Input {
+                    car,
+                    x_axis,
+                    y_axis,
+                    boost,
+                } => writeln!(f, "{} {} {:?} {:?} {}", event.tick, car, x_axis, y_axis, boost as u8)?,
+                ReplayAction::Join(ref selection) => writeln!(f, "{} join {}", event.tick, selection)?,
+                ReplayAction::Reset => writeln!(f, "{} reset", event.tick)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// FNV-1a hash of the motion state and health of all cars and the race time.
+pub fn checksum(scene: &Scene) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    let mut add = |value: f32| {
+        let bits = value.to_bits();
+        for shift in &[0, 8, 16, 24] {
+            hash = (hash ^ u64::from((bits >> shift) & 0xff)).wrapping_mul(0x0100_0000_01b3);
+        }
+    };
+    for car in &scene.cars {
+        let body = &car.body;
+        for v in [body.position, body.rotation, body.velocity, body.angular_velocity].iter() {
+            v.iter().for_each(|&x| add(x));
+        }
+        add(car.boost);
+        car.health.iter().for_each(|&x| add(x));
+    }
+    add(scene.race.time());
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{checksum, Replay};
+    use crate::game::ai::Difficulty;
+    use crate::game::car_definition::CarSelection;
+    use crate::game::controller::Controller;
+
+    #[test]
+    fn parse_replay() {
+        let text = "map race_track_2\ncars kart,formula:3\ntick_rate 60\nticks 200\n\n# comment\n0 0 0.0 1.0 0\n10 1 -0.0 0.5 1\n20 join lamba:1\n30 reset\n";
+        let replay = Replay::parse(text).unwrap();
+        assert_eq!(replay.map, "race_track_2");
+        assert_eq!(replay.cars[1], CarSelection::new("formula", Some(3)));
+        assert_eq!(replay.ticks(), 200);
+        assert_eq!(replay.events.len(), 4);
+        assert_eq!(Replay::parse(&replay.to_string()).unwrap(), replay);
+
+        assert!(Replay::parse("cars kart\ntick_rate 60\nticks 0").is_err());
+        assert!(Replay::parse(&format!("{}5 0 0.0 1.0 0\n", text)).is_err());
+        assert!(Replay::parse(&format!("{}40 0 0.0 1.0 maybe\n", text)).is_err());
+        assert!(Replay::parse(&format!("{}40 leave 0\n", text)).is_err());
+    }
+
+    #[test]
+    fn record_only_changes() {
+        let mut replay = Replay::new("race_track_1", &[CarSelection::new("kart", None)], 60);
+        let forward = Controller::with_state(0., 1., false);
+        replay.record(&[forward]);
+        replay.record(&[forward]);
+        replay.record(&[]);
+        replay.record_join(&CarSelection::new("formula", None));
+        replay.record(&[forward, Controller::with_state(-0., 0., false)]);
+        assert_eq!(replay.ticks(), 4);
+        assert_eq!(
+            replay.to_string().lines().skip(5).collect::<Vec<_>>(),
+            vec![
+                "0 0 0.0 1.0 0",
+                "2 0 0.0 0.0 0",
+                "3 join formula",
+                "3 0 0.0 1.0 0",
+                "3 1 -0.0 0.0 0"
+            ]
+        );
+    }
+
+    #[test]
+    fn play_back_a_match() {
+        let cars = [CarSelection::new("kart", None), CarSelection::new("formula", None)];
+        let mut replay = Replay::new("race_track_1", &cars, 60);
+        let mut scene = replay.scene().unwrap();
+        for id in 0..scene.cars.len() {
+            scene.set_driver(id, Some(Difficulty::Medium));
+        }
+        let dt = 1.0 / 60.0;
+        for tick in 0..1200 {
+            if tick == 600 {
+                replay.record_join(&CarSelection::new("lamba", None));
+                scene.add_car(&CarSelection::new("lamba", None)).unwrap();
+            }
+            let input = scene.input(dt, &[]);
+            replay.record(&input);
+            scene.update(dt, &input);
+        }
+        replay.finish(&scene);
+
+        let mut replay = Replay::parse(&replay.to_string()).unwrap();
+        let mut playback = replay.scene().unwrap();
+        for tick in 0..replay.ticks() {
+            let input = replay.play(tick, &mut playback).unwrap();
+            playback.update(dt, &input);
+        }
+        assert_eq!(checksum(&playback), checksum(&scene));
+        assert_eq!(replay.verify(&playback), Some(true));
+    }
+}
@@ -12,58 +12,177 @@
 
 // You should have received a copy of the GNU General Public License
 // along with Carambolage.  If not, see <http://www.gnu.org/licenses/>.
+use super::ai::{AiDriver, Difficulty};
 use super::car::Car;
+use super::car_definition::{CarDefinition, CarSelection};
 use super::controller::Controller;
+use super::derby::Derby;
 use super::level::Level;
+use super::pickup::Pickups;
+use super::race::Race;
+use super::time_trial::TimeTrial;
 use crate::grphx::Camera;
-use nalgebra::{inf, sup, zero, Isometry3, Matrix4, Vector3};
-use ncollide3d::query;
+use crate::physx::{CollisionPipeline, RigidBody};
+use nalgebra::{inf, sup, zero, Vector3};
 
 /// Main application Scene.
 ///
-/// This scene consists of `GameObject`s, an `Environment` and a main `Camera`.
+/// This scene consists of `GameObject`s, an `Environment` and a main `Camera`. It only holds the
+/// simulation state and needs no OpenGL context, see `SceneRenderer` for drawing it.
 pub struct Scene {
     pub cars: Vec<Car>,
+    /// Car and color every car was created with.
+    pub selections: Vec<CarSelection>,
+    /// Computer driver of every car, `None` for cars of players.
+    pub drivers: Vec<Option<AiDriver>>,
     pub level: Level,
+    pub race: Race,
+    /// Knockout rounds on arena levels.
+    pub derby: Derby,
+    /// Power-ups of the level.
+    pub pickups: Pickups,
+    /// Lap times and ghost of a single car driving alone, `None` in all other games.
+    pub time_trial: Option<TimeTrial>,
+    /// Camera following all cars.
     pub camera: Camera,
+    /// Camera following a single car in split-screen, one per car.
+    pub player_cameras: Vec<Camera>,
+    collision: CollisionPipeline,
 }
 
 impl Scene {
-    /// Create a new scene. Choose a map via id.
-    pub fn new(map_id: u32) -> Scene {
-        let mut cars = Vec::new();
-        cars.push(Car::new(&Car::model_from_id(3), &Car::color_from_id(1), 1.0));
-        cars.push(Car::new(&Car::model_from_id(4), &Car::color_from_id(6), 1.0));
-
-        // Choose the level according to an id.
-        let level = match map_id {
-            1 => Level::new("maps/race_track_1"),
-            2 => Level::new("maps/race_track_2"),
-            _ => Level::new("maps/race_track_1"),
-        };
-        let camera = Camera::new();
+    /// Create a new scene on the level called `map` with one car per `CarSelection`.
+    pub fn new(map: &str, selection: &[CarSelection]) -> Result<Scene, String> {
+        let mut cars = Vec::with_capacity(selection.len());
+        for (id, selected) in selection.iter().enumerate() {
+            cars.push(Self::create_car(selected, id)?);
+        }
+
+        let level = Level::new(map)?;
+        let race = Race::new(level.course.clone());
+        let derby = Derby::new(level.manifest.derby);
+        let pickups = Pickups::new(&level.manifest.pickups);
+        let camera = Self::create_camera(&level);
+        let player_cameras = vec![camera; cars.len()];
+        let drivers = vec![None; cars.len()];
+        let collision = CollisionPipeline::new();
 
-        let mut scene = Scene { cars, level, camera };
+        let mut scene = Scene {
+            cars,
+            selections: selection.to_vec(),
+            drivers,
+            level,
+            race,
+            derby,
+            pickups,
+            time_trial: None,
+            camera,
+            player_cameras,
+            collision,
+        };
         scene.reset_cars();
-        scene
+        Ok(scene)
+    }
+
+    fn create_car(selected: &CarSelection, id: usize) -> Result<Car, String> {
+        let definition = CarDefinition::find(&selected.car)?;
+        Ok(Car::new(&definition, definition.palette(selected.palette, id)?))
+    }
+
+    /// Camera limited to the bounds of `level`.
+    fn create_camera(level: &Level) -> Camera {
+        let mut camera = Camera::new();
+        let bounds = &level.manifest.camera;
+        camera.set_bounds(
+            bounds.height_min,
+            bounds.height_max,
+            Vector3::new(bounds.focus_min[0], bounds.focus_min[1], -1_000.),
+            Vector3::new(bounds.focus_max[0], bounds.focus_max[1], 1_000.),
+        );
+        camera
+    }
+
+    /// Add a car for a player joining the game and put it on the start grid.
+    pub fn add_car(&mut self, selected: &CarSelection) -> Result<(), String> {
+        let id = self.cars.len();
+        self.cars.push(Self::create_car(selected, id)?);
+        self.selections.push(selected.clone());
+        self.player_cameras.push(Self::create_camera(&self.level));
+        self.drivers.push(None);
+        self.reset_car(id);
+        Ok(())
     }
 
+    /// Let the computer drive car `id` with `difficulty`, or hand it back to a player with `None`.
+    pub fn set_driver(&mut self, id: usize, difficulty: Option<Difficulty>) {
+        self.drivers[id] = difficulty.map(AiDriver::new);
+    }
+
+    /// Put all cars back on the start grid, restart the race or derby, all pickups and the current
+    /// lap of a time trial.
     pub fn reset_cars(&mut self) {
         for i in 0..self.cars.len() {
-            self.cars[i].boost = 100.0;
-            self.cars[i].rotation = zero();
-            if i % 2 == 0 {
-                self.cars[i].position = Vector3::new(-1.15, -1.7 * i as f32, 0.5);
-            } else {
-                self.cars[i].position = Vector3::new(1.15, -1.7 * i as f32, 0.5);
-            }
+            self.reset_car(i);
+        }
+        for driver in self.drivers.iter_mut().flatten() {
+            driver.reset();
         }
+        self.race.reset();
+        self.derby.reset();
+        self.pickups.reset();
+        if let Some(ref mut time_trial) = self.time_trial {
+            time_trial.reset();
+        }
+    }
+
+    /// Car `id` takes part in the simulation, it is not knocked out of a derby round.
+    pub fn is_playing(&self, id: usize) -> bool {
+        !self.derby.is_out(id)
+    }
+
+    /// Put car `id` back on its place on the start grid, repaired and without power-ups.
+    fn reset_car(&mut self, id: usize) {
+        let (position, rotation) = self.level.manifest.spawn.place(id);
+        let car = &mut self.cars[id];
+        car.boost = car.stats.boost_capacity;
+        car.repair();
+        car.effects = Default::default();
+        car.body.position = position;
+        car.body.rotation = Vector3::new(0., 0., rotation);
+        car.body.velocity = zero();
+        car.body.angular_velocity = zero();
+        car.store_previous();
     }
 
-    /// Update the scene.
-    pub fn update(&mut self, dt: f32, controller: &[Controller], is_ingame: bool) {
+    /// Input of every car for the next step of `dt`.
+    ///
+    /// Cars are driven by the `players` controllers first, then by their computer driver. Cars
+    /// without either get no input.
+    pub fn input(&mut self, dt: f32, players: &[Controller]) -> Vec<Controller> {
+        let cars = &self.cars;
+        let line = &self.level.racing_line;
+        self.drivers
+            .iter_mut()
+            .enumerate()
+            .map(|(id, driver)| match (players.get(id), driver) {
+                (Some(controller), _) => *controller,
+                (None, Some(driver)) => driver.drive(id, cars, line, dt),
+                (None, None) => Controller::with_state(0., 0., false),
+            })
+            .collect()
+    }
+
+    /// Advance the simulation by one fixed time step `dt`.
+    ///
+    /// Cars knocked out of a derby round stay where they are until the next round.
+    pub fn update(&mut self, dt: f32, controller: &[Controller]) {
+        for car in &mut self.cars {
+            car.store_previous();
+        }
+
         // User Input
-        for (id, car) in &mut self.cars.iter_mut().enumerate() {
+        let derby = &self.derby;
+        for (id, car) in self.cars.iter_mut().enumerate().filter(|(id, _)| !derby.is_out(*id)) {
             if id < controller.len() {
                 car.update(dt, Some(controller[id]));
             } else {
@@ -71,83 +190,77 @@ impl Scene {
             }
         }
 
-        self.update_collisions(dt);
-        self.update_scene_camera(dt, is_ingame);
-    }
-
-    /// Calculate and solve collisions.
-    fn update_collisions(&mut self, dt: f32) {
-        // Update physics/position
-        let mut car_pos = Vec::with_capacity(self.cars.len());
-        for car in &self.cars {
-            car_pos.push(Isometry3::new(car.position, car.rotation));
+        // Physics
+        for (_, car) in self.cars.iter_mut().enumerate().filter(|(id, _)| !derby.is_out(*id)) {
+            car.body.integrate(dt);
         }
 
-        // The whole collision detection is stupid right now. I have learned a lot during my work on this game and
-        // the way it is will do the job. I just want to finish the game so it feels "round" and continue with another
-        // project. Sorry ¯\_(ツ)_/¯
-        let prediction = 0.0;
-        // Cars with cars
-        for i in 0..car_pos.len() {
-            for j in i + 1..car_pos.len() {
-                // Most stupid collision detection, but we'll have a world an maybe max 8 cars, wo who cares.
-                let penetrate = query::contact(&car_pos[i], &self.cars[i].cuboid, &car_pos[j], &self.cars[j].cuboid, prediction);
-                if penetrate.is_some() {
-                    let pen = penetrate.unwrap();
-                    let w1 = pen.world1;
-                    let w2 = pen.world2;
-                    let dir = w1 - w2;
-                    self.cars[i].position -= dir * 0.5;
-                    self.cars[j].position += dir * 0.5;
-                }
-            }
+        self.update_collisions();
+        let derby = &self.derby;
+        self.pickups.update(dt, &mut self.cars, |id| !derby.is_out(id));
+        self.race.update(dt, &self.cars);
+        if let (Some(time_trial), Some(car)) = (&mut self.time_trial, self.cars.first()) {
+            time_trial.update(dt, car, &self.race);
         }
+        for id in self.derby.update(dt, &self.cars) {
+            self.reset_car(id);
+        }
+    }
 
-        // Cars with level
-        for (i, cp) in car_pos.iter().enumerate() {
-            self.cars[i].position[2] -= 0.81 * dt;
-
-            let penetrate_ground = query::contact(&cp, &self.cars[i].cuboid, &self.level.ground.0, &self.level.ground.1, prediction);
-            if penetrate_ground.is_some() {
-                let pen = penetrate_ground.unwrap();
-                let w1 = pen.world1;
-                let w2 = pen.world2;
-                let dir = w1 - w2;
-                self.cars[i].position -= dir;
-            };
-
-            let penetrate_border = query::contact(&cp, &self.cars[i].cuboid, &self.level.border.0, &self.level.border.1, prediction);
-            if penetrate_border.is_some() {
-                let pen = penetrate_border.unwrap();
-                let w1 = pen.world1;
-                let w2 = pen.world2;
-                let dir = w1 - w2;
-                self.cars[i].position -= dir;
-            };
+    /// Calculate and solve collisions, the impacts damage the cars.
+    fn update_collisions(&mut self) {
+        let derby = &self.derby;
+        let ids: Vec<usize> = (0..self.cars.len()).filter(|&id| !derby.is_out(id)).collect();
+        let mut bodies: Vec<&mut RigidBody> = self
+            .cars
+            .iter_mut()
+            .enumerate()
+            .filter(|(id, _)| !derby.is_out(*id))
+            .map(|(_, car)| &mut car.body)
+            .collect();
+        let impacts = self.collision.step(&mut bodies, &self.level.colliders);
+        for impact in impacts {
+            self.cars[ids[impact.body]].hit(&impact.point, &impact.impulse);
         }
     }
 
     /// Calculate the position the camera should move to.
-    fn update_scene_camera(&mut self, dt: f32, is_ingame: bool) {
+    ///
+    /// This is done once per rendered frame with the frame time `dt`, following the cars
+    /// interpolated by `alpha` between the last two simulation steps.
+    pub fn update_camera(&mut self, dt: f32, alpha: f32, is_ingame: bool) {
         if is_ingame {
-            let camera_focus = if self.cars.is_empty() {
+            let positions: Vec<Vector3<f32>> = (0..self.cars.len())
+                .filter(|&id| self.is_playing(id))
+                .map(|id| self.cars[id].interpolated_position(alpha))
+                .collect();
+            let camera_focus = if positions.is_empty() {
                 Vector3::new(0., 0., 0.)
             } else {
-                let mut min = self.cars[0].position;
-                let mut max = self.cars[0].position;
+                let mut min = positions[0];
+                let mut max = min;
                 let mut lerp_pos = Vector3::new(0., 0., 0.);
-                for car in &self.cars {
-                    lerp_pos += car.position;
-                    min = inf(&min, &car.position);
-                    max = sup(&max, &car.position);
+                for position in &positions {
+                    lerp_pos += position;
+                    min = inf(&min, position);
+                    max = sup(&max, position);
                 }
-                lerp_pos /= self.cars.len() as f32;
+                lerp_pos /= positions.len() as f32;
                 let camera_distance = (max - min).norm() * 1.20 + 10.0;
                 self.camera.move_to_height(camera_distance);
                 lerp_pos
             };
             self.camera.move_to_focus(camera_focus);
             self.camera.update(dt);
+
+            // Split-screen cameras zoom out with the speed of their car.
+            for (car, camera) in self.cars.iter().zip(&mut self.player_cameras) {
+                camera.is_smooth_zoom = self.camera.is_smooth_zoom;
+                camera.is_smooth_pan = self.camera.is_smooth_pan;
+                camera.move_to_focus(car.interpolated_position(alpha));
+                camera.move_to_height(camera.height_min + car.body.velocity.norm() * 1.5);
+                camera.update(dt);
+            }
         } else {
             let height_min = self.camera.height_min;
             let is_smooth_zoom = self.camera.is_smooth_zoom;
@@ -165,15 +278,4 @@ impl Scene {
             self.camera.is_smooth_pan = is_smooth_pan;
         }
     }
-
-    /// Draw the entire `Scene` to the bound framebuffer.
-    pub fn draw(&mut self, projection: &Matrix4<f32>) {
-        let view = self.camera.get_viewmatrix();
-        // Draw map.
-        self.level.draw(&view, &projection);
-        // Draw objects.
-        for i in 0..self.cars.len() {
-            self.cars[i].draw(&view, &projection);
-        }
-    }
 }
@@ -0,0 +1,463 @@
+// This file is part of Carambolage.
+
+// Carambolage is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Carambolage is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Carambolage.  If not, see <http://www.gnu.org/licenses/>.
+use super::car::Car;
+use super::race::Race;
+use crate::util::Lerp;
+use nalgebra::{Matrix4, Vector3};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Opacity of the ghost car, see `Model::draw_translucent()`.
+pub const GHOST_ALPHA: f32 = 0.35;
+
+/// Place of a car at one tick of a lap.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct GhostFrame {
+    pub position: Vector3<f32>,
+    /// Rotation around the z-axis in radians.
+    pub yaw: f32,
+}
+
+/// Best lap of a level with a car, with its ghost.
+///
+/// A record is a text file named after the level and the car in the `data_dir()` of the user:
+/// ```text
+/// level race_track_1
+/// car kart
+/// lap 61.43333
+/// sectors 14.683333 15.3 16.016666 15.433333
+/// best_sectors 14.516666 15.3 15.85 15.433333
+/// dt 0.016666668
+/// # x y z yaw
+/// 20.0 -0.41666666 0.4 0.0
+/// ```
+/// Sector times run from one checkpoint to the next, the last one back to the finish line. The
+/// best sectors are the fastest times ever driven in each sector, not necessarily in one lap. The
+/// ghost has one line per tick of the lap, `dt` seconds apart.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LapRecord {
+    /// Name of the level, see `LevelManifest`.
+    pub level: String,
+    /// Name of the car, see `CarDefinition`.
+    pub car: String,
+    /// Time of the lap in [s].
+    pub lap: f32,
+    /// Sector times of the lap in [s].
+    pub sectors: Vec<f32>,
+    /// Fastest time in [s] of every sector in any lap.
+    pub best_sectors: Vec<f32>,
+    /// Seconds between two ghost frames.
+    dt: f32,
+    ghost: Vec<GhostFrame>,
+}
+
+impl LapRecord {
+    /// Load a record from a file.
+    pub fn load(file: &Path) -> Result<LapRecord, String> {
+        let text = fs::read_to_string(file).map_err(|e| format!("Failed to read {}: {}", file.display(), e))?;
+        Self::parse(&text).map_err(|e| format!("{}: {}", file.display(), e))
+    }
+
+    /// Parse a record from text.
+    pub fn parse(text: &str) -> Result<LapRecord, String> {
+        let (mut level, mut car, mut lap, mut sectors, mut best_sectors, mut dt) = (None, None, None, None, None, None);
+        let mut ghost = Vec::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let invalid = |name: &str| format!("line {}: invalid {} '{}'", number + 1, name, line);
+            let times = |name: &str| -> Result<Vec<f32>, String> {
+                fields[1..].iter().map(|time| time.parse().map_err(|_| invalid(name))).collect()
+            };
+            match (fields[0], fields.len()) {
+                ("level", 2) => level = Some(fields[1].to_string()),
+                ("car", 2) => car = Some(fields[1].to_string()),
+                ("lap", 2) => lap = Some(fields[1].parse().map_err(|_| invalid("lap"))?),
+                ("sectors", _) => sectors = Some(times("sectors")?),
+                ("best_sectors", _) => best_sectors = Some(times("best_sectors")?),
+                ("dt", 2) => dt = Some(fields[1].parse().map_err(|_| invalid("dt"))?),
+                (_, 4) => {
+                    let values: Vec<f32> = fields
+                        .iter()
+                        .map(|value| value.parse().map_err(|_| invalid("ghost frame")))
+                        .collect::<Result<_, _>>()?;
+                    ghost.push(GhostFrame {
+                        position: Vector3::new(values[0], values[1], values[2]),
+                        yaw: values[3],
+                    });
+                }
+                _ => return Err(format!("line {}: unexpected '{}'", number + 1, line)),
+            }
+        }
+
+        let missing = |name: &str| format!("missing {}", name);
+        let record = LapRecord {
+            level: level.ok_or_else(|| missing("level"))?,
+            car: car.ok_or_else(|| missing("car"))?,
+            lap: lap.ok_or_else(|| missing("lap"))?,
+            sectors: sectors.ok_or_else(|| missing("sectors"))?,
+            best_sectors: best_sectors.ok_or_else(|| missing("best_sectors"))?,
+            dt: dt.ok_or_else(|| missing("dt"))?,
+            ghost,
+        };
+        if record.sectors.is_empty() || record.sectors.len() != record.best_sectors.len() {
+            return Err("sectors and best_sectors need the same number of times".to_string());
+        }
+        if record.dt <= 0.0 {
+            return Err("dt has to be positive".to_string());
+        }
+        Ok(record)
+    }
+
+    /// Write the record to a file.
+    pub fn save(&self, file: &Path) -> Result<(), String> {
+        fs::write(file, self.to_string()).map_err(|e| format!("Failed to write {}: {}", file.display(), e))
+    }
+
+    /// Place of the ghost `time` seconds into the lap, `None` once the lap is over.
+    pub fn ghost_at(&self, time: f32) -> Option<GhostFrame> {
+        let t = time.max(0.0) / self.dt;
+        let i = t as usize;
+        let frame = self.ghost.get(i)?;
+        match self.ghost.get(i + 1) {
+            Some(next) => Some(GhostFrame {
+                position: Vector3::lerp(&frame.position, &next.position, t.fract()),
+                yaw: f32::lerp(&frame.yaw, &next.yaw, t.fract()),
+            }),
+            None => Some(*frame),
+        }
+    }
+}
+
+impl fmt::Display for LapRecord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Debug formatting of floats parses back to the same bits.
+        let times = |times: &[f32]| times.iter().map(|time| format!("{:?}", time)).collect::<Vec<_>>().join(" ");
+        writeln!(f, "level {}", self.level)?;
+        writeln!(f, "car {}", self.car)?;
+        writeln!(f, "lap {:?}", self.lap)?;
+        writeln!(f, "sectors {}", times(&self.sectors))?;
+        writeln!(f, "best_sectors {}", times(&self.best_sectors))?;
+        writeln!(f, "dt {:?}", self.dt)?;
+        writeln!(f, "# x y z yaw")?;
+        for frame in &self.ghost {
+            let p = frame.position;
+            writeln!(f, "{:?} {:?} {:?} {:?}", p[0], p[1], p[2], frame.yaw)?;
+        }
+        Ok(())
+    }
+}
+
+/// Lap and sector times of a single car driving alone, racing the ghost of its best lap.
+///
+/// A lap starts whenever the car crosses the start line and ends when it crosses it again after
+/// passing all checkpoints. Driving back over the start line throws the lap away. Every lap faster
+/// than the record becomes the new record, see `save()`.
+pub struct TimeTrial {
+    level: String,
+    car: String,
+    /// Checkpoints per lap, every lap has as many sectors.
+    sectors: usize,
+    best: Option<LapRecord>,
+    last_lap: Option<f32>,
+    /// Checkpoints passed so far, see `RaceProgress::passed()`.
+    passed: usize,
+    /// Race time the current lap started at, `None` between laps.
+    lap_start: Option<f32>,
+    /// Lap time at every checkpoint passed in the current lap.
+    splits: Vec<f32>,
+    /// Place of the car at every tick of the current lap.
+    ghost: Vec<GhostFrame>,
+    time: f32,
+    dt: f32,
+    /// The record changed since the last `save()`.
+    is_unsaved: bool,
+}
+
+impl TimeTrial {
+    /// Time trial on `level` with `car` on a course with `sectors` checkpoints, without record yet.
+    pub fn new(level: &str, car: &str, sectors: usize) -> TimeTrial {
+        TimeTrial {
+            level: level.to_string(),
+            car: car.to_string(),
+            sectors,
+            best: None,
+            last_lap: None,
+            passed: 0,
+            lap_start: None,
+            splits: Vec::new(),
+            ghost: Vec::new(),
+            time: 0.0,
+            dt: 0.0,
+            is_unsaved: false,
+        }
+    }
+
+    /// File of the record in `dir`, e.g. `race_track_1.kart.lap`.
+    pub fn file(&self, dir: &Path) -> PathBuf {
+        dir.join(format!("{}.{}.lap", self.level, self.car))
+    }
+
+    /// Load the record of the level and car from `dir`, if there is one.
+    ///
+    /// A record of a level with a different number of checkpoints is not loaded.
+    pub fn load(&mut self, dir: &Path) -> Result<(), String> {
+        let file = self.file(dir);
+        if !file.is_file() {
+            return Ok(());
+        }
+        let record = LapRecord::load(&file)?;
+        if record.sectors.len() != self.sectors {
+            return Err(format!(
+                "{}: {} sectors, but the level has {}",
+                file.display(),
+                record.sectors.len(),
+                self.sectors
+            ));
+        }
+        self.best = Some(record);
+        Ok(())
+    }
+
+    /// Write the record to `dir`, creating it if necessary.
+    ///
+    /// A failed save is not retried until the record changes again.
+    pub fn save(&mut self, dir: &Path) -> Result<(), String> {
+        self.is_unsaved = false;
+        if let Some(ref best) = self.best {
+            fs::create_dir_all(dir).map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+            best.save(&self.file(dir))?;
+        }
+        Ok(())
+    }
+
+    /// The record changed since the last `save()`.
+    pub fn is_unsaved(&self) -> bool {
+        self.is_unsaved
+    }
+
+    /// Throw away the current lap, e.g. when the car is put back on the start grid.
+    pub fn reset(&mut self) {
+        self.last_lap = None;
+        self.passed = 0;
+        self.lap_start = None;
+        self.splits.clear();
+        self.ghost.clear();
+        self.time = 0.0;
+    }
+
+    /// Best lap driven so far.
+    pub fn best(&self) -> Option<&LapRecord> {
+        self.best.as_ref()
+    }
+
+    /// Time of the last finished lap in [s].
+    pub fn last_lap(&self) -> Option<f32> {
+        self.last_lap
+    }
+
+    /// Time of the current lap in [s], `None` between laps.
+    pub fn lap_time(&self) -> Option<f32> {
+        self.lap_start.map(|start| self.time - start)
+    }
+
+    /// Sector times of the current lap in [s], one per passed checkpoint.
+    pub fn sectors(&self) -> Vec<f32> {
+        let mut last = 0.0;
+        self.splits
+            .iter()
+            .map(|&split| {
+                let sector = split - last;
+                last = split;
+                sector
+            })
+            .collect()
+    }
+
+    /// Follow the only car of the `race` after a simulation step of `dt`.
+    pub fn update(&mut self, dt: f32, car: &Car, race: &Race) {
+        let progress = race.progress(0);
+        let passed = progress.passed();
+        self.time = race.time();
+        self.dt = dt;
+        if passed > self.passed {
+            if (passed - 1) % self.sectors == 0 {
+                // Crossed the start line, either finishing a lap or starting the first one.
+                if let Some(start) = self.lap_start {
+                    self.splits.push(self.time - start);
+                    self.finish_lap();
+                }
+                self.splits.clear();
+                self.ghost.clear();
+                self.lap_start = if progress.finish_time.is_none() { Some(self.time) } else { None };
+            } else if let Some(start) = self.lap_start {
+                self.splits.push(self.time - start);
+            }
+        } else if passed < self.passed && self.splits.pop().is_none() {
+            // Driving back takes back the last split, or the whole lap behind the start line.
+            self.lap_start = None;
+        }
+        self.passed = passed;
+
+        if self.lap_start.is_some() {
+            self.ghost.push(GhostFrame {
+                position: car.body.position,
+                yaw: car.body.rotation[2],
+            });
+        }
+    }
+
+    /// Keep the lap that just ended if it beats the record.
+    fn finish_lap(&mut self) {
+        let sectors = self.sectors();
+        if sectors.len() != self.sectors {
+            return;
+        }
+        let lap = self.splits[self.splits.len() - 1];
+        self.last_lap = Some(lap);
+        self.is_unsaved = true;
+        let best_sectors = match self.best {
+            Some(ref best) => best.best_sectors.iter().zip(&sectors).map(|(a, b)| a.min(*b)).collect(),
+            None => sectors.clone(),
+        };
+        match self.best {
+            Some(ref mut best) if best.lap <= lap => best.best_sectors = best_sectors,
+            _ => {
+                self.best = Some(LapRecord {
+                    level: self.level.clone(),
+                    car: self.car.clone(),
+                    lap,
+                    sectors,
+                    best_sectors,
+                    dt: self.dt,
+                    ghost: self.ghost.clone(),
+                })
+            }
+        }
+    }
+
+    /// Difference of a sector time of the current lap to the same sector of the record in [s],
+    /// negative when faster.
+    pub fn sector_delta(&self, sector: usize) -> Option<f32> {
+        let current = *self.sectors().get(sector)?;
+        let best = *self.best.as_ref()?.sectors.get(sector)?;
+        Some(current - best)
+    }
+
+    /// Model matrix of the ghost of the record, racing along the current lap.
+    ///
+    /// `alpha` interpolates between the previous and the current simulation step like for the cars.
+    pub fn ghost_matrix(&self, alpha: f32) -> Option<Matrix4<f32>> {
+        let time = self.lap_time()? - (1.0 - alpha) * self.dt;
+        let frame = self.best.as_ref()?.ghost_at(time)?;
+        Some(Car::placement_matrix(&frame.position, frame.yaw))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LapRecord, TimeTrial};
+    use crate::game::car::Car;
+    use crate::game::race::{Checkpoint, Course, Race};
+    use nalgebra::Vector3;
+
+    /// A square course of 40m around the origin with 3 laps, driven counterclockwise.
+    fn square() -> Course {
+        let checkpoint = |x: f32, y: f32, rotation: f32| Checkpoint::new(Vector3::new(x, y, 0.), rotation.to_radians(), 5.0);
+        Course {
+            checkpoints: vec![
+                checkpoint(20., 0., 0.),
+                checkpoint(0., 20., 90.),
+                checkpoint(-20., 0., 180.),
+                checkpoint(0., -20., -90.),
+            ],
+            laps: 3,
+        }
+    }
+
+    /// Drive once around the square from right behind the start line, `steps` ticks per side.
+    fn drive_lap(car: &mut Car, race: &mut Race, trial: &mut TimeTrial, steps: usize) {
+        let corners = [(20., -1.), (20., 20.), (-20., 20.), (-20., -20.), (20., -20.), (20., -1.)];
+        for corner in corners.windows(2) {
+            let from = Vector3::new((corner[0]).0, (corner[0]).1, 0.);
+            let to = Vector3::new((corner[1]).0, (corner[1]).1, 0.);
+            for i in 0..steps {
+                car.store_previous();
+                car.body.position = from + (to - from) * ((i + 1) as f32 / steps as f32);
+                race.update(0.1, std::slice::from_ref(car));
+                trial.update(0.1, car, race);
+            }
+        }
+    }
+
+    #[test]
+    fn record_best_lap() {
+        let mut car = Car::test_kart();
+        let mut race = Race::new(square());
+        let mut trial = TimeTrial::new("square", "kart", 4);
+        car.body.position = Vector3::new(20., -1., 0.);
+
+        drive_lap(&mut car, &mut race, &mut trial, 20);
+        assert!(trial.best().is_none());
+        assert!(trial.lap_time().is_some());
+
+        // The first lap ends in the first step of the second.
+        drive_lap(&mut car, &mut race, &mut trial, 10);
+        assert!((trial.last_lap().unwrap() - 10.0).abs() < 1e-3);
+        assert!(trial.is_unsaved());
+        assert!(trial.ghost_matrix(1.0).is_some());
+
+        drive_lap(&mut car, &mut race, &mut trial, 20);
+        let best = trial.best().unwrap().clone();
+        assert!((best.lap - 5.0).abs() < 1e-3);
+        assert!((best.sectors.iter().sum::<f32>() - best.lap).abs() < 1e-3);
+        assert_eq!(best.ghost.len(), 50);
+        assert!(trial.sector_delta(0).unwrap() > 0.0);
+
+        // The slower third lap keeps the record. The race is over then, no new lap starts.
+        drive_lap(&mut car, &mut race, &mut trial, 10);
+        assert!((trial.last_lap().unwrap() - 10.0).abs() < 1e-3);
+        assert!((trial.best().unwrap().lap - 5.0).abs() < 1e-3);
+        assert!(trial.lap_time().is_none());
+        assert!(trial.ghost_matrix(1.0).is_none());
+
+        trial.reset();
+        assert!(trial.best().is_some());
+        assert!(trial.last_lap().is_none());
+    }
+
+    #[test]
+    fn ghost_replays_lap() {
+        let text = "level square\ncar kart\nlap 0.2\nsectors 0.1 0.1\nbest_sectors 0.1 0.05\ndt 0.1\n# x y z yaw\n0.0 0.0 0.4 0.0\n1.0 0.0 0.4 1.0\n";
+        let record = LapRecord::parse(text).unwrap();
+        assert_eq!(LapRecord::parse(&record.to_string()).unwrap(), record);
+
+        let frame = record.ghost_at(0.05).unwrap();
+        assert!((frame.position[0] - 0.5).abs() < 1e-5);
+        assert!((frame.yaw - 0.5).abs() < 1e-5);
+        assert_eq!(record.ghost_at(-1.0).unwrap().position[0], 0.0);
+        assert_eq!(record.ghost_at(0.15).unwrap().position[0], 1.0);
+        assert!(record.ghost_at(0.25).is_none());
+
+        assert!(LapRecord::parse(&text.replace("dt 0.1", "dt 0.0")).is_err());
+        assert!(LapRecord::parse(&text.replace("best_sectors 0.1 0.05", "best_sectors 0.1")).is_err());
+        assert!(LapRecord::parse(&text.replace("lap 0.2\n", "")).is_err());
+        assert!(LapRecord::parse(&format!("{}0.0 zero 0.4 0.0\n", text)).is_err());
+    }
+}
@@ -0,0 +1,185 @@
+// This file is part of Carambolage.
+
+// Carambolage is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Carambolage is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Carambolage.  If not, see <http://www.gnu.org/licenses/>.
+use crate::error::Error;
+use crate::grphx::{load_model, Meshes, ModelData, Shader, Texture};
+use log::{debug, error};
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::{Rc, Weak};
+
+/// Loaded assets of one kind, keyed by their path.
+///
+/// The cache only holds weak references, an asset is dropped with its last handle.
+struct Cache<T> {
+    assets: HashMap<String, Weak<T>>,
+    /// Assets loaded from disk, including ones loaded again after they were dropped.
+    loads: usize,
+    /// Requests served from the cache.
+    hits: usize,
+}
+
+impl<T> Default for Cache<T> {
+    fn default() -> Cache<T> {
+        Cache {
+            assets: HashMap::new(),
+            loads: 0,
+            hits: 0,
+        }
+    }
+}
+
+impl<T> Cache<T> {
+    /// Handle to the asset at `path`, calling `load` if it is not loaded yet.
+    fn get<F: FnOnce() -> T>(&mut self, path: &str, load: F) -> Rc<T> {
+        if let Some(asset) = self.assets.get(path).and_then(Weak::upgrade) {
+            self.hits += 1;
+            return asset;
+        }
+        debug!("Loading asset {}", path);
+        // Forget assets dropped since the last load.
+        self.assets.retain(|_, asset| asset.upgrade().is_some());
+        let asset = Rc::new(load());
+        self.assets.insert(path.to_string(), Rc::downgrade(&asset));
+        self.loads += 1;
+        asset
+    }
+
+    /// Handles to all assets still in use.
+    fn loaded(&self) -> Vec<Rc<T>> {
+        self.assets.values().filter_map(Weak::upgrade).collect()
+    }
+}
+
+/// Numbers of loaded assets, e.g. for a debugging overlay.
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct AssetStats {
+    /// Model files, each with one or more meshes.
+    pub models: usize,
+    pub meshes: usize,
+    pub vertices: usize,
+    pub shaders: usize,
+    pub textures: usize,
+    /// Assets loaded from disk so far.
+    pub loads: usize,
+    /// Requests for assets that were already loaded.
+    pub hits: usize,
+}
+
+impl fmt::Display for AssetStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} models ({} meshes, {} vertices), {} shaders, {} textures, {} loads, {} cache hits",
+            self.models, self.meshes, self.vertices, self.shaders, self.textures, self.loads, self.hits
+        )
+    }
+}
+
+/// Render resources shared by all `Model`s.
+///
+/// Meshes, shader programs and textures are loaded once per path and handed out as reference
+/// counted handles, so e.g. two cars of the same kind share one mesh on the GPU. An asset is
+/// freed once the last handle to it is dropped, like the models of a level that is left.
+///
+/// A file that fails to load is replaced by a placeholder cube, the error shader or a
+/// checkerboard texture, and its error is kept until `take_errors()`, e.g. to show it to the
+/// player.
+#[derive(Default)]
+pub struct Assets {
+    meshes: Cache<Meshes>,
+    shaders: Cache<Shader>,
+    textures: Cache<Texture>,
+    errors: Vec<Error>,
+}
+
+impl Assets {
+    pub fn new() -> Assets {
+        Default::default()
+    }
+
+    /// All meshes of an obj, gltf or glb file relative to "res/models".
+    pub fn meshes(&mut self, file: &str) -> Rc<Meshes> {
+        let errors = &mut self.errors;
+        self.meshes.get(file, || {
+            Meshes::new(load_model(file).unwrap_or_else(|e| fallback(errors, e, ModelData::placeholder)))
+        })
+    }
+
+    /// Shader program from "res/shaders/<name>.vs" and ".fs".
+    pub fn shader(&mut self, name: &str) -> Rc<Shader> {
+        let errors = &mut self.errors;
+        self.shaders
+            .get(name, || Shader::new(name).unwrap_or_else(|e| fallback(errors, e, Shader::error)))
+    }
+
+    /// Texture relative to "res/textures".
+    pub fn texture(&mut self, file: &str) -> Rc<Texture> {
+        let errors = &mut self.errors;
+        self.textures.get(file, || {
+            Texture::new(file).unwrap_or_else(|e| fallback(errors, e, Texture::checkerboard))
+        })
+    }
+
+    /// Errors of all files that failed to load since the last call.
+    pub fn take_errors(&mut self) -> Vec<Error> {
+        self.errors.drain(..).collect()
+    }
+
+    pub fn stats(&self) -> AssetStats {
+        let models = self.meshes.loaded();
+        AssetStats {
+            models: models.len(),
+            meshes: models.iter().map(|model| model.meshes.len()).sum(),
+            vertices: models.iter().flat_map(|model| &model.meshes).map(|mesh| mesh.vertices.len()).sum(),
+            shaders: self.shaders.loaded().len(),
+            textures: self.textures.loaded().len() + models.iter().map(|model| model.textures.len()).sum::<usize>(),
+            loads: self.meshes.loads + self.shaders.loads + self.textures.loads,
+            hits: self.meshes.hits + self.shaders.hits + self.textures.hits,
+        }
+    }
+}
+
+/// Remember the `error` of a file and load its `placeholder` instead.
+fn fallback<T, F: FnOnce() -> T>(errors: &mut Vec<Error>, error: Error, placeholder: F) -> T {
+    error!("{}, using a placeholder", error);
+    errors.push(error);
+    placeholder()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Cache;
+    use std::rc::Rc;
+
+    #[test]
+    fn share_and_drop() {
+        let mut cache: Cache<String> = Default::default();
+        let a = cache.get("a", || "first".to_string());
+        let b = cache.get("a", || "second".to_string());
+        assert!(Rc::ptr_eq(&a, &b));
+        assert_eq!(*b, "first");
+        assert_eq!((cache.loads, cache.hits), (1, 1));
+
+        let c = cache.get("c", || "third".to_string());
+        assert_eq!(cache.loaded().len(), 2);
+
+        // Dropped with the last handle, loaded again on the next request.
+        drop(a);
+        drop(b);
+        assert_eq!(cache.loaded(), vec![c]);
+        assert_eq!(*cache.get("a", || "again".to_string()), "again");
+        assert_eq!((cache.loads, cache.hits), (3, 1));
+    }
+}
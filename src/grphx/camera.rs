@@ -14,7 +14,7 @@
 // along with Carambolage.  If not, see <http://www.gnu.org/licenses/>.
 use crate::util::Lerp;
 use log::info;
-use nalgebra::{clamp, Matrix4, Point3, Vector3};
+use nalgebra::{clamp, inf, sup, Matrix4, Point3, Vector3};
 use serde_derive::{Deserialize, Serialize};
 
 /// Camera to calculate the view matrix and follow ingame objects.
@@ -30,6 +30,9 @@ pub struct Camera {
     pub height_min: f32,
     height_max: f32,
     speed: f32,
+    // Area the camera focus stays in.
+    focus_min: Vector3<f32>,
+    focus_max: Vector3<f32>,
 
     // Parameter for camera movement.
     focus_goal: Vector3<f32>,
@@ -50,6 +53,8 @@ impl Camera {
             height_min: 30.,
             height_max: 140.,
             speed: 1.8,
+            focus_min: Vector3::new(-1_000., -1_000., -1_000.),
+            focus_max: Vector3::new(1_000., 1_000., 1_000.),
 
             focus_goal: Vector3::new(0., 0., 0.),
             height_goal: 50.,
@@ -74,9 +79,17 @@ impl Camera {
         self.position = self.focus + Vector3::new(0., 0., self.height);
     }
 
+    /// Limit the camera height and the area the camera focus can move in.
+    pub fn set_bounds(&mut self, height_min: f32, height_max: f32, focus_min: Vector3<f32>, focus_max: Vector3<f32>) {
+        self.height_min = height_min;
+        self.height_max = height_max;
+        self.focus_min = focus_min;
+        self.focus_max = focus_max;
+    }
+
     /// Smooth transition to the focus goal postion.
     pub fn move_to_focus(&mut self, position: Vector3<f32>) {
-        self.focus_goal = position;
+        self.focus_goal = sup(&inf(&position, &self.focus_max), &self.focus_min);
     }
 
     /// Set the focus goal and the camera instantaneously.
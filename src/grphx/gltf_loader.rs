@@ -0,0 +1,267 @@
+// This file is part of Carambolage.
+
+// Carambolage is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Carambolage is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Carambolage.  If not, see <http://www.gnu.org/licenses/>.
+use crate::error::Error;
+use crate::grphx::{flat_normals, MeshData, ModelData, TextureData, Vertex};
+use crate::util::Resource;
+use gltf::image::Format;
+use gltf::mesh::Mode;
+use gltf::Node;
+use log::{debug, warn};
+use nalgebra::{Matrix3, Matrix4, Point3, Rotation3, Vector3, U3};
+use std::f32::consts::FRAC_PI_2;
+use std::path::Path;
+
+/// Load all meshes of the default scene in a gltf or glb file.
+///
+/// Node transforms are baked into the vertices and the y-up axes of glTF are turned to the z-up
+/// axes of the game. Every material gets one texture, its base color texture or a single pixel of
+/// its base color. Primitives without a material use the palette of the `Model`.
+///
+/// Packed files are read from memory, so buffers and images have to be embedded, e.g. in a glb.
+pub fn load_gltf(resource: &Resource) -> Result<ModelData, Error> {
+    debug!("Loading glTF {}", resource);
+    let path = resource.path();
+    let (document, buffers, images) = match resource {
+        Resource::File(file) => gltf::import(file),
+        Resource::Packed(..) => gltf::import_slice(&resource.read().map_err(|e| Error::Io(path.clone(), e))?),
+    }
+    .map_err(|e| Error::Gltf(path.clone(), e))?;
+
+    let textures = document
+        .materials()
+        .map(|material| {
+            let pbr = material.pbr_metallic_roughness();
+            match pbr.base_color_texture() {
+                Some(info) => {
+                    if info.tex_coord() != 0 {
+                        warn!(
+                            "Material {:?} of {} uses uv set {}, only the first is loaded",
+                            material.index(),
+                            path.display(),
+                            info.tex_coord()
+                        );
+                    }
+                    to_texture_data(&path, &images[info.texture().source().index()])
+                }
+                None => Ok(single_color(pbr.base_color_factor())),
+            }
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+
+    let scene = document.default_scene().or_else(|| document.scenes().next());
+    let scene = scene.ok_or_else(|| Error::Invalid(path.clone(), "no scene".to_string()))?;
+    let mut meshes = Vec::new();
+    let y_up_to_z_up = Rotation3::from_axis_angle(&Vector3::x_axis(), FRAC_PI_2).to_homogeneous();
+    for node in scene.nodes() {
+        load_node(&path, &node, &y_up_to_z_up, &buffers, &mut meshes)?;
+    }
+    if meshes.is_empty() {
+        return Err(Error::Invalid(path, "no mesh".to_string()));
+    }
+    debug!("{} meshes, {} materials in {}", meshes.len(), textures.len(), path.display());
+    Ok(ModelData { meshes, textures })
+}
+
+/// Load the meshes of `node` and all its children, `parent` is the transform of its parent node.
+fn load_node(
+    path: &Path,
+    node: &Node,
+    parent: &Matrix4<f32>,
+    buffers: &[gltf::buffer::Data],
+    meshes: &mut Vec<MeshData>,
+) -> Result<(), Error> {
+    let transform = parent * Matrix4::from(node.transform().matrix());
+    if let Some(mesh) = node.mesh() {
+        for primitive in mesh.primitives() {
+            if primitive.mode() != Mode::Triangles {
+                warn!(
+                    "Skipping {:?} primitive of mesh {} in {}",
+                    primitive.mode(),
+                    mesh.index(),
+                    path.display()
+                );
+                continue;
+            }
+            let reader = primitive.reader(|buffer| buffers.get(buffer.index()).map(|data| &data[..]));
+            let positions: Vec<[f32; 3]> = match reader.read_positions() {
+                Some(positions) => positions.collect(),
+                None => continue,
+            };
+            let indices: Vec<u32> = match reader.read_indices() {
+                Some(indices) => indices.into_u32().collect(),
+                None => (0..positions.len() as u32).collect(),
+            };
+            if indices.iter().any(|&i| i as usize >= positions.len()) {
+                return Err(Error::Invalid(
+                    path.to_path_buf(),
+                    format!("index out of range in mesh {}", mesh.index()),
+                ));
+            }
+            let normals: Option<Vec<[f32; 3]>> = reader.read_normals().map(Iterator::collect);
+            let uvs: Vec<[f32; 2]> = reader.read_tex_coords(0).map_or_else(Vec::new, |uvs| uvs.into_f32().collect());
+
+            let vertices: Vec<Vertex> = positions
+                .iter()
+                .enumerate()
+                .map(|(i, position)| Vertex {
+                    position: *position,
+                    normal: normals.as_ref().and_then(|normals| normals.get(i)).cloned().unwrap_or([0.; 3]),
+                    uv: uvs.get(i).cloned().unwrap_or([0.; 2]),
+                })
+                .collect();
+            let (vertices, indices) = if normals.is_some() {
+                (vertices, indices)
+            } else {
+                flat_normals(&vertices, &indices)
+            };
+            meshes.push(MeshData {
+                vertices: vertices.iter().map(|vertex| transformed(vertex, &transform)).collect(),
+                indices,
+                material: primitive.material().index(),
+            });
+        }
+    }
+    for child in node.children() {
+        load_node(path, &child, &transform, buffers, meshes)?;
+    }
+    Ok(())
+}
+
+/// `vertex` moved by `transform`, its normal turned by the inverse transpose.
+fn transformed(vertex: &Vertex, transform: &Matrix4<f32>) -> Vertex {
+    let linear: Matrix3<f32> = transform.fixed_slice::<U3, U3>(0, 0).into_owned();
+    let normal_matrix = linear.try_inverse().map_or_else(|| linear, |inverse| inverse.transpose());
+    let position = transform.transform_point(&Point3::from(vertex.position));
+    let normal = (normal_matrix * Vector3::from(vertex.normal))
+        .try_normalize(0.0)
+        .unwrap_or_else(Vector3::z);
+    Vertex {
+        position: [position.x, position.y, position.z],
+        normal: [normal.x, normal.y, normal.z],
+        uv: vertex.uv,
+    }
+}
+
+/// Convert a decoded glTF image to RGB8 or RGBA8 pixels.
+fn to_texture_data(path: &Path, image: &gltf::image::Data) -> Result<TextureData, Error> {
+    let (channels, has_alpha) = match image.format {
+        Format::R8 => (1, false),
+        Format::R8G8 => (2, true),
+        Format::R8G8B8 => (3, false),
+        Format::R8G8B8A8 => (4, true),
+        format => {
+            return Err(Error::Invalid(
+                path.to_path_buf(),
+                format!("unsupported texture format {:?}", format),
+            ))
+        }
+    };
+    let size = image.width as usize * image.height as usize;
+    if size == 0 || image.pixels.len() != size * channels {
+        return Err(Error::Invalid(
+            path.to_path_buf(),
+            format!("texture of {}x{}px with {} bytes", image.width, image.height, image.pixels.len()),
+        ));
+    }
+    let pixels = match channels {
+        // Grey, with alpha for two channels.
+        1 => image.pixels.iter().flat_map(|&grey| vec![grey, grey, grey]).collect(),
+        2 => image.pixels.chunks(2).flat_map(|p| vec![p[0], p[0], p[0], p[1]]).collect(),
+        _ => image.pixels.clone(),
+    };
+    Ok(TextureData {
+        width: image.width,
+        height: image.height,
+        has_alpha,
+        pixels,
+    })
+}
+
+/// A single pixel of a base color factor for materials without a texture.
+fn single_color(color: [f32; 4]) -> TextureData {
+    TextureData {
+        width: 1,
+        height: 1,
+        has_alpha: true,
+        pixels: color.iter().map(|c| (c.max(0.).min(1.) * 255.).round() as u8).collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{load_gltf, to_texture_data};
+    use crate::util::Resource;
+    use gltf::image::{Data, Format};
+    use nalgebra::Vector3;
+    use std::env;
+    use std::fs;
+    use std::path::Path;
+
+    /// One red triangle without normals, moved along the z axis of glTF by its node.
+    const TRIANGLE: &str = r#"{
+        "asset": { "version": "2.0" },
+        "scene": 0,
+        "scenes": [{ "nodes": [0] }],
+        "nodes": [{ "mesh": 0, "translation": [0.0, 0.0, 2.0] }],
+        "meshes": [{ "primitives": [{ "attributes": { "POSITION": 0 }, "indices": 1, "material": 0 }] }],
+        "materials": [{ "pbrMetallicRoughness": { "baseColorFactor": [1.0, 0.0, 0.0, 1.0] } }],
+        "buffers": [{ "byteLength": 42, "uri": "data:application/octet-stream;base64,AAAAAAAAAAAAAAAAAACAPwAAAAAAAAAAAAAAAAAAgD8AAAAAAAABAAIA" }],
+        "bufferViews": [{ "buffer": 0, "byteLength": 36 }, { "buffer": 0, "byteOffset": 36, "byteLength": 6 }],
+        "accessors": [
+            { "bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3", "min": [0.0, 0.0, 0.0], "max": [1.0, 1.0, 0.0] },
+            { "bufferView": 1, "componentType": 5123, "count": 3, "type": "SCALAR" }
+        ]
+    }"#;
+
+    #[test]
+    fn transformed_triangle() {
+        let dir = env::temp_dir().join("carambolage-gltf");
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("triangle.gltf");
+        fs::write(&path, TRIANGLE).unwrap();
+
+        let model = load_gltf(&Resource::File(path)).unwrap();
+        assert_eq!(model.meshes.len(), 1);
+        let mesh = &model.meshes[0];
+        assert_eq!(mesh.indices, vec![0, 1, 2]);
+        assert_eq!(mesh.material, Some(0));
+        assert_eq!(model.textures[0].pixels, vec![255, 0, 0, 255]);
+
+        // The y-up triangle stands upright in the z-up game, its normal points along -y.
+        let expected = [Vector3::new(0., -2., 0.), Vector3::new(1., -2., 0.), Vector3::new(0., -2., 1.)];
+        for (vertex, expected) in mesh.vertices.iter().zip(&expected) {
+            assert!((Vector3::from(vertex.position) - expected).norm() < 1e-5);
+            assert!((Vector3::from(vertex.normal) - Vector3::new(0., -1., 0.)).norm() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn texture_sizes() {
+        let image = |format: Format, pixels: Vec<u8>| Data {
+            pixels,
+            format,
+            width: 3,
+            height: 1,
+        };
+        let path = Path::new("model.glb");
+        // Grey is expanded to tightly packed RGB, rows of 9 bytes are uploaded without padding.
+        let grey = to_texture_data(path, &image(Format::R8, vec![1, 2, 3])).unwrap();
+        assert_eq!(grey.pixels, vec![1, 1, 1, 2, 2, 2, 3, 3, 3]);
+        assert_eq!(grey.pixels.len(), grey.expected_len());
+        // Pixels that do not match the size are refused instead of read past their end.
+        assert!(to_texture_data(path, &image(Format::R8G8, vec![1, 2, 3])).is_err());
+        assert!(to_texture_data(path, &image(Format::R8G8B8, Vec::new())).is_err());
+    }
+}
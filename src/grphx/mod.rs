@@ -13,10 +13,14 @@
 // You should have received a copy of the GNU General Public License
 // along with Carambolage.  If not, see <http://www.gnu.org/licenses/>.
 
+/// Shared meshes, shaders and textures.
+mod assets;
 /// Camera module to calculate view matrix.
 mod camera;
 /// Frame buffer for background rendering.
 mod framebuffer;
+/// Import of glTF 2.0 models.
+mod gltf_loader;
 /// Material unused.
 mod material;
 /// 3D Mesh for Model
@@ -30,8 +34,10 @@ mod shader;
 /// 2D Texture for Models
 mod texture;
 
+pub(crate) use self::assets::*;
 pub(crate) use self::camera::*;
 pub(crate) use self::framebuffer::*;
+pub(crate) use self::gltf_loader::*;
 pub(crate) use self::mesh::*;
 pub(crate) use self::model::*;
 pub(crate) use self::screen::*;
@@ -12,61 +12,77 @@
 
 // You should have received a copy of the GNU General Public License
 // along with Carambolage.  If not, see <http://www.gnu.org/licenses/>.
-use crate::grphx::{Mesh, Shader, Texture, Vertex};
+use crate::error::Error;
+use crate::grphx::{load_gltf, Assets, Mesh, Shader, Texture, TextureData, Vertex};
+use crate::util::resource;
+use gl;
 use log::{debug, info};
 use nalgebra::{inf, sup, zero, Matrix4, Vector3};
-use std::path::Path;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::rc::Rc;
 use tobj;
 
 /// This is the visual representation of a gameobject.
 ///
 /// Currently and can consist of seperate `Mesh`es which all are drawn with
-/// on `Texture` as a color lookup table and `Shader`program.
+/// on `Texture` as a color lookup table and `Shader`program. All of them are shared with other
+/// models through `Assets`. Meshes with a material of their own, e.g. from a glTF file, are drawn
+/// with its texture instead.
 pub struct Model {
+    pub meshes: Rc<Meshes>,
+    pub shader: Rc<Shader>,
+    pub texture: Rc<Texture>,
+}
+
+/// All meshes of a model file on the GPU, with the textures of their materials.
+pub struct Meshes {
     pub meshes: Vec<Mesh>,
-    pub shader: Shader,
-    pub texture: Texture,
+    /// Material of every mesh as an index into `textures`, `None` uses the texture of the `Model`.
+    pub materials: Vec<Option<usize>>,
+    pub textures: Vec<Texture>,
+}
+
+impl Meshes {
+    /// Upload the meshes and textures of a model file.
+    pub fn new(data: ModelData) -> Meshes {
+        let textures = data.textures.iter().map(Texture::from_data).collect();
+        let (meshes, materials) = data
+            .meshes
+            .into_iter()
+            .map(|mesh| (Mesh::new(mesh.vertices, mesh.indices), mesh.material))
+            .unzip();
+        Meshes {
+            meshes,
+            materials,
+            textures,
+        }
+    }
+
+    /// Every mesh with the texture of its material, if it has one.
+    pub fn iter(&self) -> impl Iterator<Item = (&Mesh, Option<&Texture>)> {
+        let textures = &self.textures;
+        self.meshes
+            .iter()
+            .zip(&self.materials)
+            .map(move |(mesh, material)| (mesh, material.and_then(|material| textures.get(material))))
+    }
 }
 
 impl Model {
-    /// Creates a new Model by passing a path to the obj file and a color palette name.
+    /// Creates a new Model by passing a path to the obj, gltf or glb file and a color palette name.
     ///
-    /// The path to the obj file can be a relative or an absolut path as string.
-    /// The palette file should be placed into "res/models" relative to the project or
-    /// bin dir. The passed string for palette would look like this "car_green.png".
-    pub fn new(file: &str, palette: &str) -> Model {
+    /// The path to the model file is relative to "res/models", the palette file should be placed
+    /// into "res/textures". The passed string for palette would look like this "car_green.png".
+    /// Files already loaded by `assets` are not loaded again.
+    pub fn new(assets: &mut Assets, file: &str, palette: &str) -> Model {
         info!("Model::new - file:{};palette:{}", file, palette);
 
-        let file_str = format!("{}{}", "res/models/", file);
-        let file = Path::new(&file_str);
-        let obj = tobj::load_obj(file);
-
-        let (models, _materials) = obj.unwrap();
+        let meshes = assets.meshes(file);
 
-        let mut meshes = Vec::with_capacity(models.len());
-        for model in models {
-            let mesh = &model.mesh;
-            let num_vertices = mesh.positions.len() / 3;
+        let shader = assets.shader("default");
 
-            // data to fill
-            let mut vertices: Vec<Vertex> = Vec::with_capacity(num_vertices);
-            let indices: Vec<u32> = mesh.indices.clone();
-
-            let (p, n, t) = (&mesh.positions, &mesh.normals, &mesh.texcoords);
-            for i in 0..num_vertices {
-                vertices.push(Vertex {
-                    position: [p[i * 3], p[i * 3 + 1], p[i * 3 + 2]],
-                    normal: [n[i * 3], n[i * 3 + 1], n[i * 3 + 2]],
-                    uv: [t[i * 2], t[i * 2 + 1]],
-                })
-            }
-
-            meshes.push(Mesh::new(vertices, indices));
-        }
-
-        let shader = Shader::new("default");
-
-        let texture = Texture::new(palette);
+        let texture = assets.texture(palette);
 
         Model { meshes, shader, texture }
     }
@@ -76,33 +92,257 @@ impl Model {
     /// Because the basic model has no translation, rotation or scale it needs the model-, view-,
     /// and projection matrix as parameter.
     pub fn draw(&self, model: &Matrix4<f32>, view: &Matrix4<f32>, projection: &Matrix4<f32>) {
+        self.draw_translucent(model, view, projection, 1.0);
+    }
+
+    /// Draw the `Model` with the opacity `alpha`, 1.0 draws it solid like `draw()`.
+    ///
+    /// Translucent models neither hide what is drawn after them nor themselves, so they should be
+    /// drawn last.
+    pub fn draw_translucent(&self, model: &Matrix4<f32>, view: &Matrix4<f32>, projection: &Matrix4<f32>, alpha: f32) {
+        let is_translucent = alpha < 1.0;
         unsafe {
             self.shader.bind();
-            Shader::bind_texture(0, &self.texture);
             Shader::set_uniform_mat4(0, model);
             Shader::set_uniform_mat4(1, view);
             Shader::set_uniform_mat4(2, projection);
-            for mesh in &self.meshes {
+            Shader::set_uniform_float(6, alpha);
+            if is_translucent {
+                gl::DepthMask(gl::FALSE);
+            }
+            for (mesh, texture) in self.meshes.iter() {
+                Shader::bind_texture(0, texture.unwrap_or(&self.texture));
                 mesh.draw();
             }
+            if is_translucent {
+                gl::DepthMask(gl::TRUE);
+            }
         }
     }
+}
 
-    /// Get the minum and maximum x-, y-, and z-coordinates of all vertices in our model.
+/// A mesh of a model file before it is uploaded to the GPU.
+#[derive(Debug, Clone)]
+pub struct MeshData {
+    pub vertices: Vec<Vertex>,
+    pub indices: Vec<u32>,
+    /// Index of the texture of the material in `ModelData::textures`, if the mesh has one.
+    pub material: Option<usize>,
+}
+
+/// Meshes and material textures of a model file, loaded without an OpenGL context.
+#[derive(Default)]
+pub struct ModelData {
+    pub meshes: Vec<MeshData>,
+    pub textures: Vec<TextureData>,
+}
+
+impl ModelData {
+    /// A cube from -1.0 to 1.0 standing in for a model that failed to load.
     ///
-    /// This could be used to generate a bounding box. This is not an efficient function
-    /// and does not manage later transformation of any mesh!
-    pub fn get_min_max(&self) -> (Vector3<f32>, Vector3<f32>) {
-        let mut min = zero();
-        let mut max = zero();
-
-        for mesh in &self.meshes {
-            for vert in &mesh.vertices {
-                min = inf(&min, &vert.position.into());
-                max = sup(&max, &vert.position.into());
+    /// Every face has its own vertices, so it is lit flat like the other models.
+    pub fn placeholder() -> ModelData {
+        let mut vertices = Vec::with_capacity(24);
+        let mut indices = Vec::with_capacity(36);
+        for axis in 0..3 {
+            for &sign in &[-1.0, 1.0] {
+                let mut normal = [0.0; 3];
+                normal[axis] = sign;
+                let first = vertices.len() as u32;
+                for &(u, v) in &[(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)] {
+                    let mut position = [0.0; 3];
+                    position[axis] = sign;
+                    // Counter-clockwise seen from outside the cube.
+                    position[(axis + 1) % 3] = u * sign;
+                    position[(axis + 2) % 3] = v;
+                    vertices.push(Vertex {
+                        position,
+                        normal,
+                        uv: [(u + 1.0) * 0.5, (v + 1.0) * 0.5],
+                    });
+                }
+                indices.extend_from_slice(&[first, first + 1, first + 2, first, first + 2, first + 3]);
             }
         }
-        debug!("(min, max) = ({}, {})", min, max);
-        (min, max)
+        ModelData {
+            meshes: vec![MeshData {
+                vertices,
+                indices,
+                material: None,
+            }],
+            textures: Vec::new(),
+        }
+    }
+
+    /// Vertices of all meshes.
+    pub fn vertices(&self) -> impl Iterator<Item = &Vertex> {
+        self.meshes.iter().flat_map(|mesh| mesh.vertices.iter())
+    }
+}
+
+/// Load all meshes of an obj, gltf or glb file, chosen by the extension.
+///
+/// Nothing is uploaded to the GPU, so this also works without an OpenGL context.
+/// The path is relative to "res/models", mods can override it, see `ResourceLocator`.
+pub fn load_model(file: &str) -> Result<ModelData, Error> {
+    if file.ends_with(".gltf") || file.ends_with(".glb") {
+        return load_gltf(&resource(&format!("models/{}", file)));
+    }
+    let meshes = load_obj(file)?
+        .into_iter()
+        .map(|(vertices, indices)| MeshData {
+            vertices,
+            indices,
+            material: None,
+        })
+        .collect();
+    Ok(ModelData {
+        meshes,
+        textures: Vec::new(),
+    })
+}
+
+/// Load the vertices and indices of all meshes in an obj file.
+///
+/// Meshes without normals get the flat normals of their triangles, meshes without uvs use the
+/// lower left corner of the texture. Empty meshes are skipped.
+/// The path is relative to "res/models", mods can override it, see `ResourceLocator`.
+pub fn load_obj(file: &str) -> Result<Vec<(Vec<Vertex>, Vec<u32>)>, Error> {
+    let (path, models) = read_obj(file)?;
+
+    let mut meshes = Vec::with_capacity(models.len());
+    for model in models.iter().filter(|model| !model.mesh.indices.is_empty()) {
+        let mesh = &model.mesh;
+        let num_vertices = mesh.positions.len() / 3;
+        if mesh.indices.iter().any(|&i| i as usize >= num_vertices) {
+            return Err(Error::Invalid(path, format!("index out of range in mesh '{}'", model.name)));
+        }
+        let has_normals = mesh.normals.len() >= num_vertices * 3;
+        let has_uvs = mesh.texcoords.len() >= num_vertices * 2;
+
+        // data to fill
+        let mut vertices: Vec<Vertex> = Vec::with_capacity(num_vertices);
+        let indices: Vec<u32> = mesh.indices.clone();
+
+        let (p, n, t) = (&mesh.positions, &mesh.normals, &mesh.texcoords);
+        for i in 0..num_vertices {
+            vertices.push(Vertex {
+                position: [p[i * 3], p[i * 3 + 1], p[i * 3 + 2]],
+                normal: if has_normals {
+                    [n[i * 3], n[i * 3 + 1], n[i * 3 + 2]]
+                } else {
+                    [0.; 3]
+                },
+                uv: if has_uvs { [t[i * 2], t[i * 2 + 1]] } else { [0.; 2] },
+            })
+        }
+
+        if has_normals {
+            meshes.push((vertices, indices));
+        } else {
+            debug!("Generating flat normals for mesh '{}' of {}", model.name, path.display());
+            meshes.push(flat_normals(&vertices, &indices));
+        }
+    }
+    if meshes.is_empty() {
+        return Err(Error::Invalid(path, "no mesh".to_string()));
+    }
+    Ok(meshes)
+}
+
+/// Give every triangle its own three vertices with the normal of the triangle.
+///
+/// This is for meshes without normals, the triangles are lit flat like most models of the game.
+/// Indices must be in range, an incomplete last triangle is dropped.
+pub fn flat_normals(vertices: &[Vertex], indices: &[u32]) -> (Vec<Vertex>, Vec<u32>) {
+    let mut flat = Vec::with_capacity(indices.len());
+    for triangle in indices.chunks(3).filter(|triangle| triangle.len() == 3) {
+        let corners: Vec<Vertex> = triangle.iter().map(|&i| vertices[i as usize]).collect();
+        let position = |i: usize| Vector3::from(corners[i].position);
+        let normal = (position(1) - position(0))
+            .cross(&(position(2) - position(0)))
+            .try_normalize(0.0)
+            .unwrap_or_else(Vector3::z);
+        for mut corner in corners {
+            corner.normal = [normal.x, normal.y, normal.z];
+            flat.push(corner);
+        }
+    }
+    let indices = (0..flat.len() as u32).collect();
+    (flat, indices)
+}
+
+/// Parse the obj file relative to "res/models" into the models of tobj, materials are ignored.
+///
+/// Also returns the path of the file for error messages.
+pub fn read_obj(file: &str) -> Result<(PathBuf, Vec<tobj::Model>), Error> {
+    let resource = resource(&format!("models/{}", file));
+    let path = resource.path();
+    let content = resource.read().map_err(|e| Error::Io(path.clone(), e))?;
+    let (models, _materials) =
+        tobj::load_obj_buf(&mut content.as_slice(), |_| Ok((Vec::new(), HashMap::new()))).map_err(|e| Error::Obj(path.clone(), e))?;
+    Ok((path, models))
+}
+
+/// Get the minum and maximum x-, y-, and z-coordinates of `vertices`.
+pub fn get_min_max<'a, I: Iterator<Item = &'a Vertex>>(vertices: I) -> (Vector3<f32>, Vector3<f32>) {
+    let mut min = zero();
+    let mut max = zero();
+
+    for vert in vertices {
+        min = inf(&min, &vert.position.into());
+        max = sup(&max, &vert.position.into());
+    }
+    debug!("(min, max) = ({}, {})", min, max);
+    (min, max)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{flat_normals, load_model, ModelData};
+    use crate::error::Error;
+    use crate::grphx::Vertex;
+    use nalgebra::Vector3;
+
+    #[test]
+    fn missing_model() {
+        match load_model("cars/missing.obj") {
+            Err(Error::Io(path, _)) => assert!(path.ends_with("cars/missing.obj")),
+            _ => panic!("expected an io error"),
+        }
+    }
+
+    #[test]
+    fn placeholder_cube() {
+        let model = ModelData::placeholder();
+        let mesh = &model.meshes[0];
+        assert_eq!((mesh.vertices.len(), mesh.indices.len()), (24, 36));
+        for triangle in mesh.indices.chunks(3) {
+            let position = |i: usize| Vector3::from(mesh.vertices[triangle[i] as usize].position);
+            let (a, b, c) = (position(0), position(1), position(2));
+            let normal = Vector3::from(mesh.vertices[triangle[0] as usize].normal);
+            // Counter-clockwise seen from outside, facing the normal.
+            assert!((b - a).cross(&(c - a)).dot(&normal) > 0.0);
+            assert!(a.dot(&normal) > 0.0);
+        }
+    }
+
+    #[test]
+    fn flat_shaded_triangles() {
+        let vertex = |x: f32, y: f32, z: f32| Vertex {
+            position: [x, y, z],
+            ..Default::default()
+        };
+        // Two triangles sharing an edge, one on the ground and one standing upright.
+        let vertices = vec![vertex(0., 0., 0.), vertex(1., 0., 0.), vertex(0., 1., 0.), vertex(0., 0., 1.)];
+        let (vertices, indices) = flat_normals(&vertices, &[0, 1, 2, 0, 1, 3, 3]);
+        assert_eq!(indices, vec![0, 1, 2, 3, 4, 5]);
+        assert_eq!(vertices[3].position, [0., 0., 0.]);
+        for vertex in &vertices[..3] {
+            assert_eq!(vertex.normal, [0., 0., 1.]);
+        }
+        for vertex in &vertices[3..] {
+            assert_eq!(vertex.normal, [0., -1., 0.]);
+        }
     }
 }
@@ -13,6 +13,7 @@
 // You should have received a copy of the GNU General Public License
 // along with Carambolage.  If not, see <http://www.gnu.org/licenses/>.
 use super::{FrameBuffer, Shader};
+use crate::error::Error;
 
 use std::mem::size_of;
 use std::os::raw::c_void;
@@ -25,11 +26,55 @@ pub(crate) struct Screen {
 
     frame_buffer: FrameBuffer,
     post_proc_shader: Shader,
+
+    width: u32,
+    height: u32,
+}
+
+/// Rectangle of the screen in pixels, the origin is the lower left corner.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub(crate) struct Viewport {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Viewport {
+    /// Width divided by height, for the projection matrix.
+    pub(crate) fn aspect(&self) -> f32 {
+        self.width as f32 / self.height.max(1) as f32
+    }
+}
+
+/// Split a `width` x `height` screen into one viewport per player.
+///
+/// Two players get the left and the right half, three and four players a quarter each.
+/// Viewports are ordered from the upper left to the lower right.
+pub(crate) fn split_viewports(players: usize, width: u32, height: u32) -> Vec<Viewport> {
+    let (columns, rows) = match players {
+        0 | 1 => (1, 1),
+        2 => (2, 1),
+        _ => (2, (players as u32 + 1) / 2),
+    };
+    let (cell_width, cell_height) = (width / columns, height / rows);
+    (0..players.max(1) as u32)
+        .map(|i| Viewport {
+            x: (i % columns) * cell_width,
+            y: height - (i / columns + 1) * cell_height,
+            width: cell_width,
+            height: cell_height,
+        })
+        .collect()
 }
 
 impl Screen {
     /// Create a new `Screen` with `width`and `height`in pixels.
-    pub(crate) fn new(width: u32, height: u32) -> Screen {
+    ///
+    /// Nothing could be seen without the post-processing shader, so there is no fallback for it.
+    pub(crate) fn new(width: u32, height: u32) -> Result<Screen, Error> {
+        let post_proc_shader = Shader::new("post_proc")?;
+
         // Vertex coordinates of two triangles from [-1.0, -1.0] to [1.0, 1.0].
         let vertices: [f32; 24] = [
             -1.0, 1.0, 0.0, 1.0, -1.0, -1.0, 0.0, 0.0, 1.0, -1.0, 1.0, 0.0, -1.0, 1.0, 0.0, 1.0, 1.0, -1.0, 1.0, 0.0, 1.0, 1.0, 1.0, 1.0,
@@ -58,18 +103,22 @@ impl Screen {
         }
 
         let frame_buffer = FrameBuffer::new(width as i32, height as i32);
-        let post_proc_shader = Shader::new("post_proc");
 
-        Screen {
+        Ok(Screen {
             vao,
             vbo,
             frame_buffer,
             post_proc_shader,
-        }
+
+            width,
+            height,
+        })
     }
 
     /// Takes the width and height in pixels for resizing the frame buffer.
     pub(crate) fn resize(&mut self, width: u32, height: u32) {
+        self.width = width;
+        self.height = height;
         unsafe {
             self.frame_buffer.resize(width as i32, height as i32);
         }
@@ -87,6 +136,28 @@ impl Screen {
         }
     }
 
+    /// Viewports of `players` in split-screen, see `split_viewports()`.
+    pub(crate) fn split(&self, players: usize) -> Vec<Viewport> {
+        split_viewports(players, self.width, self.height)
+    }
+
+    /// The whole screen.
+    pub(crate) fn full(&self) -> Viewport {
+        Viewport {
+            x: 0,
+            y: 0,
+            width: self.width,
+            height: self.height,
+        }
+    }
+
+    /// Draw only into `viewport` from now on.
+    pub(crate) fn set_viewport(&self, viewport: &Viewport) {
+        unsafe {
+            gl::Viewport(viewport.x as i32, viewport.y as i32, viewport.width as i32, viewport.height as i32);
+        }
+    }
+
     /// Secent step to render our scene.
     ///
     /// The scene is rendered to our framebuffer which is drawn
@@ -94,6 +165,7 @@ impl Screen {
     pub(crate) fn second_step(&self, gamma: f32) {
         unsafe {
             self.frame_buffer.unbind();
+            gl::Viewport(0, 0, self.width as i32, self.height as i32);
 
             gl::Disable(gl::DEPTH_TEST);
             gl::ClearColor(1.0, 1.0, 1.0, 1.0);
@@ -118,3 +190,59 @@ impl Drop for Screen {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{split_viewports, Viewport};
+
+    #[test]
+    fn split_screen() {
+        let full = Viewport {
+            x: 0,
+            y: 0,
+            width: 800,
+            height: 600,
+        };
+        assert_eq!(split_viewports(1, 800, 600), vec![full]);
+
+        let halves = split_viewports(2, 800, 600);
+        assert_eq!(halves[0], Viewport { width: 400, ..full });
+        assert_eq!(
+            halves[1],
+            Viewport {
+                x: 400,
+                width: 400,
+                ..full
+            }
+        );
+
+        let quarters = split_viewports(3, 800, 600);
+        assert_eq!(quarters.len(), 3);
+        assert_eq!(
+            quarters[0],
+            Viewport {
+                y: 300,
+                width: 400,
+                height: 300,
+                ..full
+            }
+        );
+        assert_eq!(
+            quarters[2],
+            Viewport {
+                width: 400,
+                height: 300,
+                ..full
+            }
+        );
+        assert_eq!(
+            split_viewports(4, 800, 600)[3],
+            Viewport {
+                x: 400,
+                y: 0,
+                width: 400,
+                height: 300
+            }
+        );
+    }
+}
@@ -12,79 +12,103 @@
 
 // You should have received a copy of the GNU General Public License
 // along with Carambolage.  If not, see <http://www.gnu.org/licenses/>.
+use crate::error::Error;
 use crate::grphx::Texture;
+use crate::util::{resource, Resource};
 use gl;
 use log::{debug, error};
 use nalgebra::{Matrix2, Matrix3, Matrix4, Vector2, Vector3, Vector4};
 use std::ffi::CString;
-use std::fs::File;
-use std::io::Read;
 use std::ptr;
 use std::str;
 
+/// Vertex shader of `Shader::error()`, with the inputs and matrices of the default shader.
+const ERROR_VERTEX_SHADER: &str = r#"
+#version 330 core
+#extension GL_ARB_explicit_uniform_location : enable
+
+layout (location = 0) in vec3 aPosition;
+
+layout (location = 0) uniform mat4 uModel;
+layout (location = 1) uniform mat4 uView;
+layout (location = 2) uniform mat4 uProjection;
+
+void main() {
+    gl_Position = uProjection * uView * uModel * vec4(aPosition, 1.);
+}
+"#;
+
+/// Fragment shader of `Shader::error()`, plain magenta.
+const ERROR_FRAGMENT_SHADER: &str = r#"
+#version 330 core
+#extension GL_ARB_explicit_uniform_location : enable
+
+out vec4 FragColor;
+
+layout (location = 6) uniform float uAlpha;
+
+void main() {
+    FragColor = vec4(1., 0., 1., uAlpha);
+}
+"#;
+
 /// Compiled GLSL Shader Program.
 pub struct Shader {
     pub id: u32,
 }
 
 impl Shader {
-    pub fn new(file: &str) -> Shader {
+    /// Compile the shader program from "res/shaders/<file>.vs" and ".fs".
+    pub fn new(file: &str) -> Result<Shader, Error> {
         debug!("New {}", file);
-        let mut shader = Shader { id: 0 };
-
-        // Load vertex shader code from file.
-        let vertex_file_path = format!("res/shaders/{}.vs", file);
-        let mut vertex_file = File::open(vertex_file_path).unwrap_or_else(|_| {
-            error!("Failed to open {}.vs", file);
-            panic!()
-        });
-        let mut vertex_string = String::new();
-        vertex_file.read_to_string(&mut vertex_string).unwrap_or_else(|_| {
-            error!("Failed to read vertex shader");
-            panic!()
-        });
-        let vertex_code = CString::new(vertex_string.as_bytes()).unwrap();
-
-        // Load fragment shader code from file.
-        let fragment_file_path = format!("res/shaders/{}.fs", file);
-        let mut fragment_file = File::open(fragment_file_path).unwrap_or_else(|_| {
-            error!("Failed to open {}.fs", file);
-            panic!()
-        });
-        let mut fragment_string = String::new();
-        fragment_file.read_to_string(&mut fragment_string).unwrap_or_else(|_| {
-            error!("Failed to read fragment shader");
-            panic!()
-        });
-        let fragment_code = CString::new(fragment_string.as_bytes()).unwrap();
-
-        // Try to compile both shaders.
+        let vertex_code = read_source(&resource(&format!("shaders/{}.vs", file)))?;
+        let fragment_code = read_source(&resource(&format!("shaders/{}.fs", file)))?;
+        Self::compile(file, &vertex_code, &fragment_code)
+    }
+
+    /// Magenta shader standing in for a shader program that failed to load.
+    ///
+    /// It is compiled from built-in code, so it works without any file. Should even that fail, the
+    /// returned program draws nothing.
+    pub fn error() -> Shader {
+        let vertex_code = CString::new(ERROR_VERTEX_SHADER).unwrap_or_default();
+        let fragment_code = CString::new(ERROR_FRAGMENT_SHADER).unwrap_or_default();
+        Self::compile("error", &vertex_code, &fragment_code).unwrap_or_else(|e| {
+            error!("{}", e);
+            Shader { id: 0 }
+        })
+    }
+
+    /// Compile and link the program called `name` from the vertex and fragment shader code.
+    fn compile(name: &str, vertex_code: &CString, fragment_code: &CString) -> Result<Shader, Error> {
         unsafe {
             // Compile vertex shader.
             let vertex = gl::CreateShader(gl::VERTEX_SHADER);
             gl::ShaderSource(vertex, 1, &vertex_code.as_ptr(), ptr::null());
             gl::CompileShader(vertex);
-            shader.check_compile_errors(vertex, "VertexShader");
 
             // Compile fragment Shader.
             let fragment = gl::CreateShader(gl::FRAGMENT_SHADER);
             gl::ShaderSource(fragment, 1, &fragment_code.as_ptr(), ptr::null());
             gl::CompileShader(fragment);
-            shader.check_compile_errors(fragment, "FragmentShader");
 
             // Create program from vertex and fragment shader.
             let id = gl::CreateProgram();
             gl::AttachShader(id, vertex);
             gl::AttachShader(id, fragment);
             gl::LinkProgram(id);
-            shader.check_compile_errors(id, "ShaderProgram");
+
+            // The program deletes itself if any step failed.
+            let shader = Shader { id };
+            let result = Self::check_compile_errors(vertex, "VertexShader")
+                .and_then(|_| Self::check_compile_errors(fragment, "FragmentShader"))
+                .and_then(|_| Self::check_compile_errors(id, "ShaderProgram"))
+                .map_err(|log| Error::Shader(name.to_string(), log));
 
             gl::DeleteShader(vertex);
             gl::DeleteShader(fragment);
-            shader.id = id;
+            result.map(|_| shader)
         }
-
-        shader
     }
 
     /// Bind the shader program.
@@ -134,38 +158,55 @@ impl Shader {
         gl::UniformMatrix4fv(id, 1, gl::FALSE, mat.as_slice().as_ptr());
     }
 
+    /// Bind a `f32` to the currently bound shader program at location `id`.
+    pub unsafe fn set_uniform_float(id: i32, value: f32) {
+        gl::Uniform1f(id, value);
+    }
+
     pub unsafe fn _set_uniform_int(id: i32, value: i32) {
         gl::Uniform1i(id, value);
     }
 
-    ///
-    unsafe fn check_compile_errors(&self, shader: u32, shader_type: &str) {
+    /// Check a shader or the whole "ShaderProgram" for errors, returning the info log if any.
+    unsafe fn check_compile_errors(shader: u32, shader_type: &str) -> Result<(), String> {
         debug!("Checking {} shader for compile errors", shader_type);
         let mut success = i32::from(gl::FALSE);
-        let mut info_log = Vec::with_capacity(1024);
-        info_log.set_len(1024 - 1);
+        let mut info_log = vec![0u8; 1024];
 
         if shader_type != "ShaderProgram" {
             gl::GetShaderiv(shader, gl::COMPILE_STATUS, &mut success);
             if success != i32::from(gl::TRUE) {
                 // i8 is a GLchar
                 gl::GetShaderInfoLog(shader, 1024, ptr::null_mut(), info_log.as_mut_ptr() as *mut i8);
-                error!(
-                    "Compilation error of type: {}\nInfo log:\n{}",
-                    shader_type,
-                    str::from_utf8(&info_log).unwrap_or("UNKNOWN")
-                );
+                return Err(format!("Compilation error of type: {}\n{}", shader_type, info_log_text(&info_log)));
             }
         } else {
             gl::GetProgramiv(shader, gl::LINK_STATUS, &mut success);
             if success != i32::from(gl::TRUE) {
                 gl::GetProgramInfoLog(shader, 1024, ptr::null_mut(), info_log.as_mut_ptr() as *mut i8);
-                error!(
-                    "Linking error of type: {}\nInfo log:\n{}",
-                    shader_type,
-                    str::from_utf8(&info_log).unwrap_or("UNKNOWN")
-                );
+                return Err(format!("Linking error of type: {}\n{}", shader_type, info_log_text(&info_log)));
             }
         }
+        Ok(())
+    }
+}
+
+/// Read the code of a shader file.
+fn read_source(resource: &Resource) -> Result<CString, Error> {
+    let code = resource.read().map_err(|e| Error::Io(resource.path(), e))?;
+    CString::new(code).map_err(|_| Error::Invalid(resource.path(), "contains a null byte".to_string()))
+}
+
+/// Text of an info log up to its terminating null byte.
+fn info_log_text(info_log: &[u8]) -> &str {
+    let end = info_log.iter().position(|&c| c == 0).unwrap_or(info_log.len());
+    str::from_utf8(&info_log[..end]).unwrap_or("UNKNOWN")
+}
+
+impl Drop for Shader {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteProgram(self.id);
+        }
     }
 }
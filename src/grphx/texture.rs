@@ -12,6 +12,8 @@
 
 // You should have received a copy of the GNU General Public License
 // along with Carambolage.  If not, see <http://www.gnu.org/licenses/>.
+use crate::error::Error;
+use crate::util::resource;
 use gl;
 use image;
 use image::DynamicImage::*;
@@ -19,7 +21,28 @@ use image::GenericImageView;
 use log::{debug, error};
 
 use std::os::raw::c_void;
-use std::path::Path;
+
+/// Squares of the checkerboard texture along each side.
+const CHECKERBOARD_SIZE: u32 = 8;
+
+/// Pixels of a texture decoded without an OpenGL context, e.g. embedded in a model file.
+///
+/// Rows start at the top of the image, as the uvs of glTF expect.
+pub struct TextureData {
+    pub width: u32,
+    pub height: u32,
+    /// RGBA8 pixels if true, RGB8 otherwise.
+    pub has_alpha: bool,
+    pub pixels: Vec<u8>,
+}
+
+impl TextureData {
+    /// Number of bytes of tightly packed pixels of this size.
+    pub fn expected_len(&self) -> usize {
+        let channels = if self.has_alpha { 4 } else { 3 };
+        self.width as usize * self.height as usize * channels
+    }
+}
 
 /// A 2D Texture for OpenGL
 pub struct Texture {
@@ -27,8 +50,69 @@ pub struct Texture {
 }
 
 impl Texture {
-    pub fn new(path: &str) -> Texture {
-        unsafe { Texture { id: load_texture(path) } }
+    /// Load a texture from an image relative to "res/textures".
+    ///
+    /// Images with a color type other than RGB8 or RGBA8 are converted to RGBA8.
+    pub fn new(path: &str) -> Result<Texture, Error> {
+        let resource = resource(&format!("textures/{}", path));
+        debug!("New from {}", resource);
+
+        let path = resource.path();
+        let content = resource.read().map_err(|e| Error::Io(path.clone(), e))?;
+        let img = image::load_from_memory(&content)
+            .map_err(|e| Error::Image(path.clone(), e))?
+            .flipv();
+        if img.width() == 0 || img.height() == 0 {
+            return Err(Error::Invalid(path, "empty image".to_string()));
+        }
+        let img = match img {
+            ImageRgb8(_) | ImageRgba8(_) => img,
+            _ => {
+                debug!("Converting {:?} to RGBA8", img.color());
+                ImageRgba8(img.to_rgba())
+            }
+        };
+        let format = match img {
+            ImageRgb8(_) => gl::RGB,
+            _ => gl::RGBA,
+        };
+
+        let id = unsafe { upload(img.width(), img.height(), format, &img.raw_pixels()) };
+        debug!("id:{}, width:{}px, height:{}px", id, img.width(), img.height());
+        Ok(Texture { id })
+    }
+
+    /// Upload the pixels of a texture decoded before, e.g. from a glTF file.
+    ///
+    /// Pixels that do not fill the texture exactly get the checkerboard instead.
+    pub fn from_data(data: &TextureData) -> Texture {
+        if data.pixels.is_empty() || data.pixels.len() != data.expected_len() {
+            error!(
+                "Texture of {}x{}px with {} bytes, using the checkerboard",
+                data.width,
+                data.height,
+                data.pixels.len()
+            );
+            return Texture::checkerboard();
+        }
+        let format = if data.has_alpha { gl::RGBA } else { gl::RGB };
+        let id = unsafe { upload(data.width, data.height, format, &data.pixels) };
+        debug!("id:{}, width:{}px, height:{}px", id, data.width, data.height);
+        Texture { id }
+    }
+
+    /// Magenta and black squares standing in for a texture that failed to load.
+    pub fn checkerboard() -> Texture {
+        let size = CHECKERBOARD_SIZE;
+        let mut data = Vec::with_capacity((size * size * 3) as usize);
+        for y in 0..size {
+            for x in 0..size {
+                let is_magenta = (x + y) % 2 == 0;
+                data.extend_from_slice(if is_magenta { &[255, 0, 255] } else { &[0, 0, 0] });
+            }
+        }
+        let id = unsafe { upload(size, size, gl::RGB, &data) };
+        Texture { id }
     }
 }
 
@@ -38,44 +122,26 @@ impl Default for Texture {
     }
 }
 
-unsafe fn load_texture(path: &str) -> u32 {
-    let path_str = format!("{}{}", "res/textures/", path);
-    debug!("New from {}", path_str);
-
-    let path = Path::new(&path_str);
-
+/// Upload the tightly packed pixels in `data` to a new texture and return its id.
+///
+/// `data` must hold all `width * height` pixels of `format`.
+unsafe fn upload(width: u32, height: u32, format: u32, data: &[u8]) -> u32 {
     let mut tex_id = 0;
 
     gl::GenTextures(1, &mut tex_id);
-    let img = image::open(&path).expect("ERROR: Failed to load texture!").flipv();
-    let image_format = match img {
-        ImageRgb8(_) => {
-            debug!("Format: RGB8");
-            gl::RGB
-        }
-        ImageRgba8(_) => {
-            debug!("Format: RGBA8");
-            gl::RGBA
-        }
-        _ => {
-            error!("Format wrong");
-            panic!("ERROR: Wrong image format!")
-        }
-    };
-
-    let data = img.raw_pixels();
-
     gl::BindTexture(gl::TEXTURE_2D, tex_id);
+    // Rows of RGB pixels are not padded to 4 bytes.
+    gl::PixelStorei(gl::UNPACK_ALIGNMENT, 1);
     gl::TexImage2D(
         gl::TEXTURE_2D,
         0,
-        image_format as i32,
-        img.width() as i32,
-        img.height() as i32,
+        format as i32,
+        width as i32,
+        height as i32,
         0,
-        image_format,
+        format,
         gl::UNSIGNED_BYTE,
-        &data[0] as *const u8 as *const c_void,
+        data.as_ptr() as *const c_void,
     );
     gl::GenerateMipmap(gl::TEXTURE_2D);
 
@@ -84,15 +150,13 @@ unsafe fn load_texture(path: &str) -> u32 {
     gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::NEAREST as i32);
     gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::NEAREST as i32);
 
-    debug!("id:{}, width:{}px, height:{}px", tex_id, img.width(), img.height());
-
     tex_id
 }
 
 impl Drop for Texture {
     fn drop(&mut self) {
         unsafe {
-            gl::DeleteTextures(1, self.id as *const u32);
+            gl::DeleteTextures(1, &self.id);
         }
     }
 }
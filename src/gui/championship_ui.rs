@@ -0,0 +1,137 @@
+use super::MenuState;
+use crate::game::championship::{Championship, ChampionshipManifest};
+use crate::game::manifest::LevelManifest;
+use crate::game::scene::Scene;
+use crate::game::GameSettings;
+use imgui::{im_str, Ui};
+use imgui_glfw_rs::imgui;
+
+/// State of the championship screen of the main menu.
+#[derive(Default)]
+pub(super) struct ChampionshipMenu {
+    /// Championship window of the main menu.
+    pub is_open: bool,
+    /// Championship files and levels to choose from, listed when the window opens.
+    championships: Vec<String>,
+    levels: Vec<String>,
+    /// Reason the last championship file failed to load.
+    message: Option<String>,
+}
+
+impl ChampionshipMenu {
+    /// Open or close the window, looking for championships and levels when it opens.
+    pub fn toggle(&mut self) {
+        self.is_open = !self.is_open;
+        if self.is_open {
+            self.championships = ChampionshipManifest::available();
+            self.levels = LevelManifest::available();
+            self.message = None;
+        }
+    }
+}
+
+/// Pick a championship file or the levels of an own championship, then start it.
+pub(super) fn draw_championship_menu(scene: &mut Scene, settings: &mut GameSettings, ui: &Ui, menu: &mut MenuState) {
+    ui.text(im_str!("Load:"));
+    for name in &menu.championship.championships {
+        if ui.button(im_str!("{}", name), (300., 30.)) {
+            match ChampionshipManifest::find(name) {
+                Ok(manifest) => {
+                    settings.championship = manifest;
+                    menu.championship.message = None;
+                }
+                Err(e) => menu.championship.message = Some(e),
+            }
+        }
+    }
+    if let Some(message) = &menu.championship.message {
+        ui.text_colored((1.0, 0.2, 0.1, 1.0), im_str!("{}", message));
+    }
+    ui.separator();
+
+    let championship = &mut settings.championship;
+    ui.text(im_str!("{}", championship.name));
+    if !championship.description.is_empty() {
+        ui.text(im_str!("{}", championship.description));
+    }
+    // Checked levels are played in the order they were checked.
+    ui.text(im_str!("Levels:"));
+    for level in &menu.championship.levels {
+        let mut is_played = championship.levels.contains(level);
+        if ui.checkbox(im_str!("{}", level), &mut is_played) {
+            if is_played {
+                championship.levels.push(level.clone());
+            } else {
+                championship.levels.retain(|played| played != level);
+            }
+            championship.name = "Custom".to_string();
+            championship.description.clear();
+        }
+    }
+    ui.text(im_str!("Order: {}", championship.levels.join(", ")));
+    let points: Vec<String> = championship.points.iter().map(|points| points.to_string()).collect();
+    ui.text(im_str!("Points: {}", points.join(" ")));
+    ui.separator();
+
+    if championship.levels.is_empty() {
+        ui.text(im_str!("Check at least one level"));
+    } else if ui.button(im_str!("Start championship"), (300., 30.)) {
+        menu.is_ingame = true;
+        menu.is_championship = true;
+        menu.championship.is_open = false;
+        scene.reset_cars();
+    }
+}
+
+/// Results of the last level and the standings after it, with the way on to the next level.
+pub(super) fn draw_scoreboard(width: f32, height: f32, ui: &Ui, championship: &Championship, menu: &mut MenuState) {
+    let result = match championship.result() {
+        Some(result) => result,
+        None => return,
+    };
+    ui.window(im_str!("{}", championship.name()))
+        .title_bar(true)
+        .position((width * 0.5 - 150., height * 0.2), imgui::ImGuiCond::Always)
+        .size((300.0, 0.0), imgui::ImGuiCond::Once)
+        .always_use_window_padding(true)
+        .collapsible(false)
+        .resizable(false)
+        .movable(false)
+        .build(|| {
+            ui.text(im_str!(
+                "Level {}/{}: {}",
+                championship.round(),
+                championship.rounds(),
+                championship.level()
+            ));
+            for (place, car) in result.iter().enumerate() {
+                if car.is_finished {
+                    ui.text(im_str!("{}. Player {}  +{}", place + 1, car.car + 1, car.points));
+                } else {
+                    ui.text(im_str!("-  Player {}  did not finish", car.car + 1));
+                }
+            }
+            ui.separator();
+            let standings = championship.standings();
+            for (place, standing) in standings.iter().enumerate() {
+                ui.text(im_str!(
+                    "{}. Player {}  {} points  {} wins",
+                    place + 1,
+                    standing.car + 1,
+                    standing.points,
+                    standing.wins
+                ));
+            }
+            ui.separator();
+            if championship.is_over() {
+                if let Some(champion) = standings.first() {
+                    ui.text_colored((1.0, 0.8, 0.1, 1.0), im_str!("Player {} is the champion!", champion.car + 1));
+                }
+                if ui.button(im_str!("Main menu"), (200., 40.)) {
+                    menu.is_ingame = false;
+                }
+            } else if ui.button(im_str!("Next level"), (200., 40.)) {
+                menu.is_next_round = true;
+            }
+        });
+}
@@ -0,0 +1,84 @@
+use crate::game::config::{config_file, Config};
+use crate::game::controller::{key_name, KeyAction, KEYS};
+use crate::game::GameSettings;
+use glfw::{Action, Key, Window};
+use imgui::{im_str, Ui};
+use imgui_glfw_rs::glfw;
+use imgui_glfw_rs::imgui;
+use log::error;
+
+/// State of the key binding screen.
+#[derive(Default)]
+pub(super) struct ControlsMenu {
+    /// Player and action waiting for a key press.
+    rebinding: Option<(usize, KeyAction)>,
+    /// Reason the last key press was rejected.
+    message: Option<String>,
+    /// Controls window of the main menu.
+    pub is_open: bool,
+}
+
+impl ControlsMenu {
+    pub fn is_rebinding(&self) -> bool {
+        self.rebinding.is_some()
+    }
+}
+
+/// Buttons for all actions of every player on the keyboard, click one and press the new key.
+pub(super) fn draw_controls(window: &Window, settings: &mut GameSettings, ui: &Ui, menu: &mut ControlsMenu, is_key_esc: &mut bool) {
+    if let Some((player, action)) = menu.rebinding {
+        if !*is_key_esc && window.get_key(Key::Escape) == Action::Press {
+            menu.rebinding = None;
+            *is_key_esc = true;
+        } else if let Some(&key) = KEYS.iter().find(|&&key| key != Key::Escape && window.get_key(key) == Action::Press) {
+            match settings.config.conflict(player, action, key) {
+                Some((other, other_action)) => {
+                    menu.message = Some(format!(
+                        "{} is already player {} {}",
+                        key_name::to_name(key),
+                        other + 1,
+                        other_action.name()
+                    ));
+                }
+                None => {
+                    settings.config.keyboard[player].set(action, key);
+                    menu.message = None;
+                    save(&settings.config);
+                }
+            }
+            menu.rebinding = None;
+        }
+    }
+
+    for (player, keys) in settings.config.keyboard.iter().enumerate() {
+        ui.text(im_str!("Player {}:", player + 1));
+        for &action in KeyAction::ALL.iter() {
+            let key = if menu.rebinding == Some((player, action)) {
+                "press a key".to_string()
+            } else {
+                key_name::to_name(keys.get(action))
+            };
+            if ui.button(im_str!("{:<9} {}##{}", action.name(), key, player), (300., 30.)) {
+                menu.rebinding = Some((player, action));
+                menu.message = None;
+            }
+        }
+        ui.spacing();
+    }
+    if let Some(message) = &menu.message {
+        ui.text_colored((1.0, 0.2, 0.1, 1.0), im_str!("{}", message));
+    }
+    if ui.button(im_str!("Reset to defaults"), (300., 30.)) {
+        let defaults = Config::default();
+        settings.config.keyboard = defaults.keyboard;
+        menu.rebinding = None;
+        menu.message = None;
+        save(&settings.config);
+    }
+}
+
+fn save(config: &Config) {
+    if let Err(e) = config.save(&config_file()) {
+        error!("{}", e);
+    }
+}
@@ -0,0 +1,25 @@
+use imgui::{im_str, Ui};
+use imgui_glfw_rs::imgui;
+
+/// List the files that failed to load until the player confirms them.
+pub(super) fn draw_errors(width: f32, height: f32, ui: &Ui, errors: &mut Vec<String>) {
+    ui.window(im_str!("Broken files"))
+        .title_bar(true)
+        .position((width * 0.5 - 300., height * 0.2), imgui::ImGuiCond::Always)
+        .size((600.0, 0.0), imgui::ImGuiCond::Once)
+        .always_use_window_padding(true)
+        .collapsible(false)
+        .resizable(false)
+        .movable(false)
+        .build(|| {
+            for error in errors.iter() {
+                ui.text_colored((1.0, 0.2, 0.1, 1.0), im_str!("{}", error));
+            }
+            ui.separator();
+            ui.text(im_str!("They are shown as magenta placeholders."));
+            ui.text(im_str!("See carambolage.log for details."));
+            if ui.button(im_str!("OK"), (200., 40.)) {
+                errors.clear();
+            }
+        });
+}
@@ -1,7 +1,12 @@
+use super::championship_ui::draw_scoreboard;
+use super::controls_ui::draw_controls;
+use super::{FrameInfo, MenuState};
+use crate::game::car::Side;
 use crate::game::scene::Scene;
 use crate::game::GameSettings;
+use crate::grphx::split_viewports;
 use glfw::Window;
-use imgui::{im_str, ImGuiCol, Ui};
+use imgui::{im_str, ImGuiCol, ImStr, Ui};
 use imgui_glfw_rs::glfw;
 use imgui_glfw_rs::imgui;
 use nalgebra::clamp;
@@ -11,9 +16,8 @@ pub(super) fn draw_game_ui(
     scene: &mut Scene,
     settings: &mut GameSettings,
     ui: &Ui,
-    is_ingame_menu: &mut bool,
-    is_key_esc: &mut bool,
-    is_ingame: &mut bool,
+    menu: &mut MenuState,
+    frame: &FrameInfo,
 ) {
     let (width, height) = window.get_size();
     let width = width as f32;
@@ -30,41 +34,183 @@ pub(super) fn draw_game_ui(
         .movable(false)
         .build(|| {
             ui.text(im_str!("FPS: {:.2}", ui.framerate()));
+            if let Some(championship) = frame.championship {
+                ui.text(im_str!("Level {}/{}", championship.round(), championship.rounds()));
+            }
+            if scene.race.is_active() {
+                ui.text(im_str!("Time: {}", format_time(scene.race.time())));
+            }
+            if scene.derby.is_active() {
+                ui.text(im_str!("Round {}/{}", scene.derby.round(), scene.derby.rounds()));
+            }
         });
 
+    if let Some(ref time_trial) = scene.time_trial {
+        ui.window(im_str!("Time trial"))
+            .title_bar(true)
+            .position((20., 20.), imgui::ImGuiCond::Always)
+            .size((250.0, 0.0), imgui::ImGuiCond::Once)
+            .always_use_window_padding(true)
+            .collapsible(false)
+            .resizable(false)
+            .movable(false)
+            .build(|| {
+                let time = |time: Option<f32>| time.map_or_else(|| "-:--.--".to_string(), format_time);
+                let best = time_trial.best();
+                ui.text(im_str!("Lap   {}", time(time_trial.lap_time())));
+                ui.text(im_str!("Last  {}", time(time_trial.last_lap())));
+                ui.text(im_str!("Best  {}", time(best.map(|best| best.lap))));
+                // All best sectors together, the lap to aim for.
+                ui.text(im_str!("Ideal {}", time(best.map(|best| best.best_sectors.iter().sum()))));
+                ui.separator();
+                for (i, sector) in time_trial.sectors().iter().enumerate() {
+                    ui.text(im_str!("S{}    {}", i + 1, format_time(*sector)));
+                    if let Some(delta) = time_trial.sector_delta(i) {
+                        let color = if delta <= 0.0 { (0.2, 0.9, 0.2, 1.0) } else { (1.0, 0.2, 0.1, 1.0) };
+                        ui.same_line(0.0);
+                        ui.text_colored(color, im_str!("{:+.2}", delta));
+                    }
+                }
+            });
+    }
+
+    let derby = &scene.derby;
+    if derby.is_between_rounds() {
+        ui.window(im_str!("Round over"))
+            .title_bar(false)
+            .position((width * 0.5 - 125., height * 0.3), imgui::ImGuiCond::Always)
+            .size((250.0, 0.0), imgui::ImGuiCond::Once)
+            .always_use_window_padding(true)
+            .collapsible(false)
+            .resizable(false)
+            .movable(false)
+            .build(|| match derby.winner() {
+                Some(winner) => ui.text(im_str!("Player {} wins round {}", winner + 1, derby.round())),
+                None => ui.text(im_str!("Nobody wins round {}", derby.round())),
+            });
+    }
+
+    let standings = scene.race.standings();
     for (id, car) in scene.cars.iter().enumerate() {
+        let progress = scene.race.progress(id);
         ui.window(im_str!("Player {}", id + 1))
             .title_bar(true)
-            .position(player_ui_pos(width, height, id), imgui::ImGuiCond::Always)
+            .position(player_ui_pos(width, height, id, frame.split_screen), imgui::ImGuiCond::Always)
             .size((250.0, 0.0), imgui::ImGuiCond::Once)
             .always_use_window_padding(true)
             .collapsible(false)
             .resizable(false)
             .movable(false)
             .build(|| {
-                ui.with_color_var(ImGuiCol::PlotHistogram, boost_to_rgba(car.boost), || {
-                    ui.progress_bar(car.boost / 100.)
+                ui.with_color_var(ImGuiCol::PlotHistogram, fraction_to_rgba(car.boost_fraction()), || {
+                    ui.progress_bar(car.boost_fraction())
                         .overlay_text(im_str!("BOOST"))
                         .size((-1., 40.))
                         .build();
                 });
+                for (i, &side) in Side::ALL.iter().enumerate() {
+                    if i > 0 {
+                        ui.same_line(0.0);
+                    }
+                    let health = car.health_fraction(side);
+                    ui.with_color_var(ImGuiCol::PlotHistogram, fraction_to_rgba(health), || {
+                        ui.progress_bar(health).overlay_text(side_label(side)).size((54., 16.)).build();
+                    });
+                }
+                let effects = [
+                    ("Shield", car.effects.shield),
+                    ("Heavy", car.effects.heavy),
+                    ("Oil", car.effects.oil),
+                ];
+                let active: Vec<String> = effects
+                    .iter()
+                    .filter(|(_, time)| *time > 0.0)
+                    .map(|(name, time)| format!("{} {:.1}s", name, time))
+                    .collect();
+                if !active.is_empty() {
+                    ui.text_colored((0.4, 0.7, 1.0, 1.0), im_str!("{}", active.join("  ")));
+                }
+                if car.is_wrecked() && !derby.is_out(id) {
+                    ui.text_colored((1.0, 0.2, 0.1, 1.0), im_str!("WRECKED"));
+                }
+                if derby.is_active() {
+                    if derby.is_out(id) {
+                        ui.text_colored((1.0, 0.2, 0.1, 1.0), im_str!("KNOCKED OUT"));
+                    } else {
+                        let progress = derby.progress(id);
+                        ui.text(im_str!("Lives {}  Wins {}", progress.lives, progress.wins));
+                    }
+                }
+                if !scene.race.is_active() {
+                    return;
+                }
+                match progress.finish_time {
+                    Some(time) => {
+                        let place = standings.iter().position(|s| s.car == id).unwrap_or(0) + 1;
+                        ui.text(im_str!("Finished {}. in {}", place, format_time(time)));
+                    }
+                    None => ui.text(im_str!("Lap {}/{}", scene.race.lap(id), scene.race.laps())),
+                }
+                if progress.wrong_way {
+                    ui.text_colored((1.0, 0.2, 0.1, 1.0), im_str!("WRONG WAY"));
+                }
+            });
+    }
+
+    // A championship shows the results on its scoreboard.
+    if let Some(championship) = frame.championship {
+        draw_scoreboard(width, height, ui, championship, menu);
+    } else if scene.race.is_over() {
+        ui.window(im_str!("Results"))
+            .title_bar(true)
+            .position((width * 0.5 - 125., height * 0.3), imgui::ImGuiCond::Always)
+            .size((250.0, 0.0), imgui::ImGuiCond::Once)
+            .always_use_window_padding(true)
+            .collapsible(false)
+            .resizable(false)
+            .movable(false)
+            .build(|| {
+                for (place, standing) in standings.iter().enumerate() {
+                    let result = match standing.finish_time {
+                        Some(time) => format_time(time),
+                        None => "wrecked".to_string(),
+                    };
+                    ui.text(im_str!("{}. Player {}  {}", place + 1, standing.car + 1, result));
+                }
+            });
+    }
+
+    if derby.is_over() && frame.championship.is_none() {
+        ui.window(im_str!("Results"))
+            .title_bar(true)
+            .position((width * 0.5 - 125., height * 0.3), imgui::ImGuiCond::Always)
+            .size((250.0, 0.0), imgui::ImGuiCond::Once)
+            .always_use_window_padding(true)
+            .collapsible(false)
+            .resizable(false)
+            .movable(false)
+            .build(|| {
+                for (place, standing) in derby.standings().iter().enumerate() {
+                    ui.text(im_str!("{}. Player {}  {} wins", place + 1, standing.car + 1, standing.wins));
+                }
             });
     }
 
     let mut close_ingame_menu = false;
-    if !*is_key_esc && window.get_key(glfw::Key::Escape) == glfw::Action::Press {
-        if *is_ingame_menu {
+    // While waiting for a new key binding, Escape only cancels the rebinding.
+    if !menu.controls.is_rebinding() && !menu.is_key_esc && window.get_key(glfw::Key::Escape) == glfw::Action::Press {
+        if menu.is_ingame_menu {
             close_ingame_menu = true;
         }
-        *is_ingame_menu = !*is_ingame_menu;
-        *is_key_esc = true;
-    } else if *is_key_esc && window.get_key(glfw::Key::Escape) == glfw::Action::Release {
-        *is_key_esc = false;
+        menu.is_ingame_menu = !menu.is_ingame_menu;
+        menu.is_key_esc = true;
+    } else if menu.is_key_esc && window.get_key(glfw::Key::Escape) == glfw::Action::Release {
+        menu.is_key_esc = false;
     }
 
     let mut is_smooth_zoom = scene.camera.is_smooth_zoom;
     let mut is_smooth_pan = scene.camera.is_smooth_pan;
-    if *is_ingame_menu {
+    if menu.is_ingame_menu {
         ui.open_popup(im_str!("Menu"));
     }
 
@@ -77,18 +223,40 @@ pub(super) fn draw_game_ui(
         .build(|| {
             if ui.button(im_str!("Continue"), (200., 40.)) || close_ingame_menu {
                 ui.close_current_popup();
-                *is_ingame_menu = false;
+                menu.is_ingame_menu = false;
             }
             ui.separator();
             ui.text(im_str!("Camera settings:"));
             ui.checkbox(im_str!("Smooth zoom"), &mut is_smooth_zoom);
             ui.checkbox(im_str!("Smooth pan"), &mut is_smooth_pan);
+            ui.checkbox(im_str!("Split screen"), &mut settings.split_screen);
             ui.separator();
             ui.input_float(im_str!("Gamma"), &mut settings.gamma).step(0.1).build();
             ui.separator();
+            ui.text(im_str!("Gamepad settings:"));
+            ui.slider_float(im_str!("Stick dead zone"), &mut settings.config.gamepad.dead_zone, 0.0, 0.5)
+                .build();
+            ui.slider_float(
+                im_str!("Trigger dead zone"),
+                &mut settings.config.gamepad.trigger_dead_zone,
+                0.0,
+                0.5,
+            )
+            .build();
+            if ui.collapsing_header(im_str!("Controls")).build() {
+                draw_controls(window, settings, ui, &mut menu.controls, &mut menu.is_key_esc);
+            }
+            if ui.collapsing_header(im_str!("Assets")).build() {
+                ui.text(im_str!("Models:   {} ({} meshes)", frame.assets.models, frame.assets.meshes));
+                ui.text(im_str!("Vertices: {}", frame.assets.vertices));
+                ui.text(im_str!("Shaders:  {}", frame.assets.shaders));
+                ui.text(im_str!("Textures: {}", frame.assets.textures));
+                ui.text(im_str!("Loads:    {}  Cache hits: {}", frame.assets.loads, frame.assets.hits));
+            }
+            ui.separator();
             if ui.button(im_str!("Main menu"), (200., 40.)) {
-                *is_ingame_menu = false;
-                *is_ingame = false;
+                menu.is_ingame_menu = false;
+                menu.is_ingame = false;
                 scene.reset_cars();
             }
             ui.separator();
@@ -104,14 +272,39 @@ pub(super) fn draw_game_ui(
     window.set_should_close(should_close);
 }
 
-fn boost_to_rgba(boost: f32) -> (f32, f32, f32, f32) {
-    let bst = boost * 0.01;
-    (1.0 - bst, clamp(bst, 0.0, 0.77), 0.0, 1.0)
+/// From red when empty to green when full, for the boost and health bars.
+fn fraction_to_rgba(fraction: f32) -> (f32, f32, f32, f32) {
+    (1.0 - fraction, clamp(fraction, 0.0, 0.77), 0.0, 1.0)
+}
+
+fn side_label(side: Side) -> &'static ImStr {
+    match side {
+        Side::Front => im_str!("FRONT"),
+        Side::Rear => im_str!("REAR"),
+        Side::Left => im_str!("LEFT"),
+        Side::Right => im_str!("RIGHT"),
+    }
+}
+
+/// Race time as `m:ss.cc`.
+fn format_time(time: f32) -> String {
+    // Round before splitting off the minutes, so 59.996 shows as 1:00.00 and not 0:60.00.
+    let centiseconds = (time.max(0.0) * 100.0).round() as u32;
+    format!("{}:{:02}.{:02}", centiseconds / 6000, centiseconds % 6000 / 100, centiseconds % 100)
 }
-fn player_ui_pos(width: f32, height: f32, id: usize) -> (f32, f32) {
-    match id {
-        0 => (20.0, height - 100.),
-        1 => (width - 270., height - 100.),
-        _ => unreachable!(),
+
+/// Player windows alternate between the left and the right corner, stacking upwards.
+///
+/// In split-screen every player gets the lower left corner of the own view.
+fn player_ui_pos(width: f32, height: f32, id: usize, split_screen: usize) -> (f32, f32) {
+    if id < split_screen {
+        let viewport = split_viewports(split_screen, width as u32, height as u32)[id];
+        return (viewport.x as f32 + 20., height - viewport.y as f32 - 160.);
+    }
+    let y = height - 160. - (id / 2) as f32 * 150.;
+    if id % 2 == 0 {
+        (20.0, y)
+    } else {
+        (width - 270., y)
     }
 }
@@ -0,0 +1,35 @@
+use crate::net::Lobby;
+use imgui::{im_str, Ui};
+use imgui_glfw_rs::imgui;
+
+/// Address and cars of a network game, shown next to the main menu.
+pub(super) fn draw_lobby(ui: &Ui, lobby: &Lobby) {
+    ui.window(im_str!("Network game"))
+        .title_bar(true)
+        .position((240., 20.), imgui::ImGuiCond::Always)
+        .size((360.0, 0.0), imgui::ImGuiCond::Once)
+        .always_use_window_padding(true)
+        .collapsible(false)
+        .resizable(false)
+        .movable(false)
+        .build(|| {
+            if lobby.is_host {
+                ui.text(im_str!("Hosting on {}", lobby.address));
+            } else {
+                ui.text(im_str!("Joined {}", lobby.address));
+            }
+            ui.separator();
+            for (id, car) in lobby.cars.iter().enumerate() {
+                let place = if lobby.local_cars.contains(&id) {
+                    "this computer"
+                } else {
+                    "network"
+                };
+                ui.text(im_str!("Player {}  {}  ({})", id + 1, car, place));
+            }
+            if let Some(ref error) = lobby.error {
+                ui.separator();
+                ui.text_colored((1.0, 0.2, 0.1, 1.0), im_str!("{}", error));
+            }
+        });
+}
@@ -1,5 +1,10 @@
+use super::championship_ui::draw_championship_menu;
+use super::controls_ui::draw_controls;
+use super::lobby_ui::draw_lobby;
+use super::MenuState;
 use crate::game::scene::Scene;
 use crate::game::GameSettings;
+use crate::net::Lobby;
 use glfw::Window;
 use imgui::{im_str, Ui};
 use imgui_glfw_rs::glfw;
@@ -7,11 +12,11 @@ use imgui_glfw_rs::imgui;
 
 pub fn draw_main_menu(
     window: &mut Window,
-    _scene: &mut Scene,
-    _settings: &mut GameSettings,
+    scene: &mut Scene,
+    settings: &mut GameSettings,
     ui: &Ui,
-    _is_key_esc: &mut bool,
-    is_ingame: &mut bool,
+    lobby: Option<&Lobby>,
+    menu: &mut MenuState,
 ) {
     let (_, height) = window.get_size();
     let height = height as f32;
@@ -25,12 +30,69 @@ pub fn draw_main_menu(
         .resizable(false)
         .movable(false)
         .build(|| {
-            if ui.button(im_str!("Start game"), (200., 40.)) {
-                *is_ingame = true;
+            // Clients of a network game wait for the host to start.
+            if lobby.map_or(true, |lobby| lobby.is_host) {
+                if ui.button(im_str!("Start game"), (200., 40.)) {
+                    menu.is_ingame = true;
+                    menu.is_championship = false;
+                    scene.reset_cars();
+                }
+            } else {
+                ui.text(im_str!("Waiting for the host"));
+            }
+            // Championships change the level, which neither a network game nor a time trial does.
+            if lobby.is_none() && scene.time_trial.is_none() {
+                ui.spacing();
+                if ui.button(im_str!("Championship"), (200., 40.)) {
+                    menu.championship.toggle();
+                    menu.controls.is_open = false;
+                }
+            }
+            ui.spacing();
+            if ui.button(im_str!("Controls"), (200., 40.)) {
+                menu.controls.is_open = !menu.controls.is_open;
+                menu.championship.is_open = false;
             }
             ui.spacing();
             if ui.button(im_str!("Exit"), (200., 40.)) {
                 window.set_should_close(true);
             }
         });
+
+    if let Some(lobby) = lobby {
+        draw_lobby(ui, lobby);
+    }
+
+    if menu.controls.is_open {
+        ui.window(im_str!("Controls"))
+            .title_bar(true)
+            .position((240., height * 0.5 - 100.), imgui::ImGuiCond::Once)
+            .size((0.0, 0.0), imgui::ImGuiCond::Once)
+            .always_use_window_padding(true)
+            .collapsible(false)
+            .resizable(false)
+            .build(|| {
+                draw_controls(window, settings, ui, &mut menu.controls, &mut menu.is_key_esc);
+                ui.separator();
+                if ui.button(im_str!("Close"), (300., 30.)) {
+                    menu.controls.is_open = false;
+                }
+            });
+    }
+
+    if menu.championship.is_open {
+        ui.window(im_str!("Championship"))
+            .title_bar(true)
+            .position((240., height * 0.5 - 100.), imgui::ImGuiCond::Once)
+            .size((0.0, 0.0), imgui::ImGuiCond::Once)
+            .always_use_window_padding(true)
+            .collapsible(false)
+            .resizable(false)
+            .build(|| {
+                draw_championship_menu(scene, settings, ui, menu);
+                if ui.button(im_str!("Close"), (300., 30.)) {
+                    menu.championship.is_open = false;
+                }
+            });
+    }
 }
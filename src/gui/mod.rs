@@ -1,9 +1,18 @@
+mod championship_ui;
+mod controls_ui;
+mod error_ui;
 mod game_ui;
+mod lobby_ui;
 mod main_menu_ui;
 
-use self::{game_ui::*, main_menu_ui::*};
+use self::{championship_ui::*, controls_ui::*, error_ui::*, game_ui::*, main_menu_ui::*};
+use crate::error::Error;
+use crate::game::championship::Championship;
 use crate::game::scene::Scene;
 use crate::game::GameSettings;
+use crate::grphx::AssetStats;
+use crate::net::Lobby;
+use crate::util::find_resource;
 use glfw::{Window, WindowEvent};
 use imgui::{FontGlyphRange, ImFontConfig, ImGui, ImGuiCol, ImVec2, ImVec4};
 use imgui_glfw_rs::glfw;
@@ -11,16 +20,40 @@ use imgui_glfw_rs::imgui;
 use imgui_glfw_rs::ImguiGLFW;
 use imgui_opengl_renderer::Renderer;
 
+/// State of the menus that is kept from one frame to the next.
+#[derive(Default)]
+pub struct MenuState {
+    pub is_ingame: bool,
+    is_ingame_menu: bool,
+    is_key_esc: bool,
+    controls: ControlsMenu,
+    championship: ChampionshipMenu,
+    /// The menu started a championship instead of a single game.
+    pub is_championship: bool,
+    /// The scoreboard asks to go on to the next level of the championship.
+    pub is_next_round: bool,
+}
+
+/// What the menus and the HUD show of the current frame.
+pub struct FrameInfo<'a> {
+    /// Number of split-screen views or 0.
+    pub split_screen: usize,
+    /// Lobby of a network game, shown in the main menu.
+    pub lobby: Option<&'a Lobby>,
+    /// Championship whose scoreboard is shown between its levels.
+    pub championship: Option<&'a Championship>,
+    pub assets: AssetStats,
+}
+
 pub struct AppUI {
     imgui: ImGui,
     imgui_glfw: ImguiGLFW,
     imgui_renderer: Renderer,
 
-    pub is_ingame: bool,
-    is_ingame_menu: bool,
+    pub menu: MenuState,
     pub is_menu_control: bool,
-
-    is_key_esc: bool,
+    /// Files that failed to load and have not been confirmed by the player yet.
+    errors: Vec<String>,
 }
 
 impl AppUI {
@@ -36,11 +69,9 @@ impl AppUI {
             imgui_glfw,
             imgui_renderer,
 
-            is_ingame: false,
-            is_ingame_menu: false,
+            menu: Default::default(),
             is_menu_control: false,
-
-            is_key_esc: false,
+            errors: Vec::new(),
         }
     }
 
@@ -103,8 +134,14 @@ impl AppUI {
             .fonts()
             .add_default_font_with_config(ImFontConfig::new().oversample_h(1).pixel_snap_h(true).size_pixels(font_size));
 
+        // Mods and packs can replace the font, otherwise the copy built into the game is used. Imgui
+        // only borrows the font data, so a loaded font is kept until the game is closed.
+        let font: &'static [u8] = match find_resource("fonts/ProFontWindows.ttf").map(|font| font.read()) {
+            Some(Ok(font)) => Box::leak(font.into_boxed_slice()),
+            _ => include_bytes!("../../res/fonts/ProFontWindows.ttf"),
+        };
         imgui.fonts().add_font_with_config(
-            include_bytes!("../../res/fonts/ProFontWindows.ttf"),
+            font,
             ImFontConfig::new()
                 .merge_mode(true)
                 .oversample_h(1)
@@ -119,25 +156,25 @@ impl AppUI {
         imgui
     }
 
-    pub fn draw(&mut self, window: &mut Window, scene: &mut Scene, settings: &mut GameSettings) {
+    /// Draw the menus and the HUD of the current `frame`.
+    pub fn draw(&mut self, window: &mut Window, scene: &mut Scene, settings: &mut GameSettings, frame: &FrameInfo) {
         let ui = self.imgui_glfw.frame(window, &mut self.imgui);
 
-        if self.is_ingame {
-            draw_game_ui(
-                window,
-                scene,
-                settings,
-                &ui,
-                &mut self.is_ingame_menu,
-                &mut self.is_key_esc,
-                &mut self.is_ingame,
-            );
+        if self.menu.is_ingame {
+            draw_game_ui(window, scene, settings, &ui, &mut self.menu, frame);
         } else {
-            draw_main_menu(window, scene, settings, &ui, &mut self.is_key_esc, &mut self.is_ingame);
+            draw_main_menu(window, scene, settings, &ui, frame.lobby, &mut self.menu);
+        }
+
+        if !self.errors.is_empty() {
+            let (width, height) = window.get_size();
+            draw_errors(width as f32, height as f32, &ui, &mut self.errors);
         }
 
         let is_menu_changed = self.is_menu_control;
-        self.is_menu_control = self.is_ingame_menu || !self.is_ingame;
+        // The scoreboard of a championship and the broken files need the mouse.
+        let is_scoreboard = frame.championship.map_or(false, |championship| championship.is_round_over());
+        self.is_menu_control = self.menu.is_ingame_menu || !self.menu.is_ingame || is_scoreboard || !self.errors.is_empty();
         if self.is_menu_control != is_menu_changed {
             let (win_width, win_height) = window.get_size();
             let curs_x = win_width / 2;
@@ -153,6 +190,11 @@ impl AppUI {
         self.imgui_renderer.render(ui);
     }
 
+    /// Tell the player about files that failed to load.
+    pub fn show_errors(&mut self, errors: Vec<Error>) {
+        self.errors.extend(errors.iter().map(Error::to_string));
+    }
+
     pub fn handle_event(&mut self, event: &WindowEvent) {
         self.imgui_glfw.handle_event(&mut self.imgui, event);
     }
@@ -0,0 +1,29 @@
+// This file is part of Carambolage.
+
+// Carambolage is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Carambolage is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Carambolage.  If not, see <http://www.gnu.org/licenses/>.
+
+/// Errors of loading the files of the game.
+pub mod error;
+/// Core application logic.
+pub mod game;
+/// Graphics module of carambolage.
+pub mod grphx;
+/// User interface
+mod gui;
+/// Networking module of carambolage.
+pub mod net;
+/// Physics module of carambolage.
+pub mod physx;
+/// Utility modules and traits.
+pub mod util;
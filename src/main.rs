@@ -14,20 +14,15 @@
 // along with Carambolage.  If not, see <http://www.gnu.org/licenses/>.
 use simplelog::*;
 
-/// Core application logic.
-mod game;
-/// Graphics module of carambolage.
-mod grphx;
-/// User interface
-mod gui;
-/// Physics module of carambolage.
-mod physx;
-/// Utility modules and traits.
-mod util;
-
-use crate::game::{Game, GameSettings};
+use carambolage::game::ai::Difficulty;
+use carambolage::game::car_definition::CarSelection;
+use carambolage::game::championship::ChampionshipManifest;
+use carambolage::game::config;
+use carambolage::game::{headless, Game, GameSettings};
+use carambolage::net;
+use carambolage::util::RES_ENV;
 use getopts::{Matches, Options};
-use log::info;
+use log::{error, info, warn};
 use std::env;
 use std::fs::File;
 
@@ -42,8 +37,13 @@ fn main() {
         Err(f) => panic!(f.to_string()),
     };
 
+    // Resources are needed to check the settings.
+    if let Some(res) = matches.opt_str("res") {
+        env::set_var(RES_ENV, res);
+    }
+
     // Filter settings
-    let game_settings = match_options(&matches);
+    let mut game_settings = match_options(&matches);
 
     let terminal_log_config = Config {
         time: Some(Level::Error),
@@ -61,9 +61,32 @@ fn main() {
     ])
     .unwrap();
 
+    if game_settings.headless.is_some() || game_settings.replay.is_some() {
+        headless::run(&game_settings);
+        return;
+    }
+
+    // Key bindings of the last session
+    let config_file = config::config_file();
+    if config_file.is_file() {
+        match config::Config::load(&config_file) {
+            Ok(config) => game_settings.config = config,
+            Err(e) => warn!("{}, using the default settings", e),
+        }
+    } else {
+        info!("No {} yet, using the default settings", config_file.display());
+    }
+
     // Start the game
     info!("Starting game");
-    let mut game = Game::new(game_settings);
+    let mut game = match Game::new(game_settings) {
+        Ok(game) => game,
+        Err(e) => {
+            error!("{}", e);
+            eprintln!("{}", e);
+            return;
+        }
+    };
     game.run();
 }
 
@@ -72,8 +95,41 @@ fn get_options() -> Options {
     opts.optflag("f", "fullscreen", "enable fullscreen mode");
     opts.optopt("w", "width", "set window width", "WIDTH");
     opts.optopt("h", "height", "set window height", "HEIGHT");
-    opts.optopt("m", "map", "set the startup map by id", "MAP");
+    opts.optopt("m", "map", "set the startup map by name, e.g. race_track_2", "MAP");
+    opts.optopt("c", "cars", "set the car of every player, e.g. kart,formula:3", "CARS");
+    opts.optopt("p", "players", "set the number of players on the keyboard [1-4]", "PLAYERS");
+    opts.optflag("s", "split-screen", "give every player an own view");
+    opts.optopt(
+        "",
+        "ai",
+        "let the computer drive every car without a player [easy, medium, hard]",
+        "DIFFICULTY",
+    );
     opts.optopt("l", "limit-fps", "set max game fps [0 = unlimited]", "FPS");
+    opts.optopt("t", "tick-rate", "set simulation steps per second", "HZ");
+    opts.optopt(
+        "",
+        "headless",
+        "run TICKS simulation steps without a window and print the result",
+        "TICKS",
+    );
+    opts.optopt("", "script", "read player input for --headless from FILE", "FILE");
+    opts.optopt("", "replay", "play back a recorded match from FILE without a window", "FILE");
+    opts.optflagopt(
+        "",
+        "host",
+        "host a network game on ADDRESS or port [default: all interfaces, port 7878]",
+        "ADDRESS",
+    );
+    opts.optopt("", "join", "join the network game hosted at ADDRESS, e.g. 192.168.0.2", "ADDRESS");
+    opts.optflag("", "time-trial", "drive alone against the ghost of your best lap");
+    opts.optopt(
+        "",
+        "championship",
+        "preselect the championship of the menu by name, e.g. grand_prix",
+        "NAME",
+    );
+    opts.optopt("", "res", "load resources from DIR, e.g. /usr/share/carambolage/res", "DIR");
     opts
 }
 
@@ -88,18 +144,48 @@ fn match_options(matches: &Matches) -> GameSettings {
     if matches.opt_str("h").is_some() {
         game_settings.height = matches.opt_str("h").unwrap().parse().unwrap();
     }
-    if matches.opt_str("m").is_some() {
-        game_settings.map = matches.opt_str("m").unwrap().parse().unwrap();
+    if let Some(map) = matches.opt_str("m") {
+        game_settings.map = map;
+    }
+    if let Some(cars) = matches.opt_str("c") {
+        game_settings.cars = CarSelection::parse_list(&cars).unwrap_or_else(|e| panic!(e));
+    }
+    if matches.opt_str("p").is_some() {
+        game_settings.players = matches.opt_str("p").unwrap().parse().unwrap();
+    }
+    if matches.opt_present("s") {
+        game_settings.split_screen = true;
+    }
+    if matches.opt_present("time-trial") {
+        game_settings.time_trial = true;
+    }
+    if let Some(championship) = matches.opt_str("championship") {
+        game_settings.championship = ChampionshipManifest::find(&championship).unwrap_or_else(|e| panic!(e));
+    }
+    if let Some(difficulty) = matches.opt_str("ai") {
+        game_settings.ai = Some(Difficulty::parse(&difficulty).unwrap_or_else(|e| panic!(e)));
     }
     if matches.opt_str("l").is_some() {
         game_settings.fps = matches.opt_str("l").unwrap().parse().unwrap();
     }
+    if matches.opt_str("t").is_some() {
+        game_settings.tick_rate = matches.opt_str("t").unwrap().parse().unwrap();
+    }
+    if matches.opt_str("headless").is_some() {
+        game_settings.headless = Some(matches.opt_str("headless").unwrap().parse().unwrap());
+    }
+    game_settings.script = matches.opt_str("script");
+    game_settings.replay = matches.opt_str("replay");
+    game_settings.host = matches.opt_default("host", "").map(|address| net::host_address(&address));
+    game_settings.join = matches.opt_str("join").map(|address| net::join_address(&address));
     game_settings
 }
 
 #[cfg(test)]
 mod tests {
     use super::{get_options, match_options};
+    use carambolage::game::ai::Difficulty;
+    use carambolage::game::car_definition::CarSelection;
 
     #[test]
     fn arguments() {
@@ -113,7 +199,17 @@ mod tests {
             String::from("-l"),
             String::from("60"),
             String::from("-m"),
+            String::from("race_track_2"),
+            String::from("-c"),
+            String::from("kart,formula:3"),
+            String::from("-t"),
+            String::from("120"),
+            String::from("-p"),
             String::from("1"),
+            String::from("-s"),
+            String::from("--time-trial"),
+            String::from("--championship"),
+            String::from("grand_prix"),
         ];
         let opts = get_options();
         let matches = match opts.parse(&args[1..]) {
@@ -127,6 +223,63 @@ mod tests {
         assert_eq!(settings.width, 1920);
         assert_eq!(settings.height, 1080);
         assert_eq!(settings.fps, 60);
-        assert_eq!(settings.map, 1);
+        assert_eq!(settings.map, "race_track_2");
+        assert_eq!(settings.cars[1], CarSelection::new("formula", Some(3)));
+        assert_eq!(settings.tick_rate, 120);
+        assert_eq!(settings.players, 1);
+        assert!(settings.split_screen);
+        assert!(settings.time_trial);
+        assert_eq!(settings.championship.name, "Grand Prix");
+        assert_eq!(settings.headless, None);
+        assert_eq!(settings.ai, None);
+    }
+
+    #[test]
+    fn headless_arguments() {
+        let args: Vec<String> = vec![
+            String::from("./carambolage"),
+            String::from("--headless"),
+            String::from("600"),
+            String::from("--script"),
+            String::from("input.txt"),
+            String::from("-p"),
+            String::from("0"),
+            String::from("--ai"),
+            String::from("hard"),
+            String::from("--replay"),
+            String::from("carambolage.replay"),
+        ];
+        let opts = get_options();
+        let matches = match opts.parse(&args[1..]) {
+            Ok(m) => m,
+            Err(f) => panic!(f.to_string()),
+        };
+
+        let settings = match_options(&matches);
+
+        assert_eq!(settings.headless, Some(600));
+        assert_eq!(settings.script, Some(String::from("input.txt")));
+        assert_eq!(settings.players, 0);
+        assert_eq!(settings.ai, Some(Difficulty::Hard));
+        assert_eq!(settings.replay, Some(String::from("carambolage.replay")));
+    }
+
+    #[test]
+    fn network_arguments() {
+        let parse = |args: &[&str]| {
+            let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+            match_options(&get_options().parse(&args).unwrap())
+        };
+
+        let settings = parse(&["--host"]);
+        assert_eq!(settings.host, Some(String::from("0.0.0.0:7878")));
+        assert_eq!(settings.join, None);
+        assert_eq!(parse(&["--host=9000"]).host, Some(String::from("0.0.0.0:9000")));
+        assert_eq!(parse(&["--host=127.0.0.1:9000"]).host, Some(String::from("127.0.0.1:9000")));
+
+        let settings = parse(&["--join", "192.168.0.2"]);
+        assert_eq!(settings.host, None);
+        assert_eq!(settings.join, Some(String::from("192.168.0.2:7878")));
+        assert_eq!(parse(&["--join", "localhost:9000"]).join, Some(String::from("localhost:9000")));
     }
 }
@@ -0,0 +1,457 @@
+// This file is part of Carambolage.
+
+// Carambolage is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Carambolage is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Carambolage.  If not, see <http://www.gnu.org/licenses/>.
+use super::protocol::{CarState, InputState, Message, Snapshot, MAX_PACKET_SIZE, PROTOCOL_VERSION};
+use super::{Lobby, TIMEOUT};
+use crate::game::car_definition::CarSelection;
+use crate::game::controller::Controller;
+use crate::game::scene::Scene;
+use log::info;
+use std::collections::VecDeque;
+use std::io::ErrorKind;
+use std::net::UdpSocket;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Other cars are drawn this many ticks behind the newest snapshot, so there is almost always a
+/// newer snapshot to interpolate to.
+const INTERPOLATION_DELAY: f32 = 5.0;
+/// Snapshots kept for interpolation.
+const SNAPSHOT_BUFFER: usize = 16;
+/// Most unacknowledged inputs resent with every input packet.
+const MAX_SENT_INPUTS: usize = 16;
+/// Unacknowledged inputs beyond this are dropped, e.g. while the host is not responding.
+const MAX_PENDING_INPUTS: usize = 120;
+/// A lost hello is sent again after this long.
+const HELLO_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Accepted by the host as driver of car `car`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Welcome {
+    pub car: usize,
+    pub map: String,
+    pub tick_rate: u32,
+}
+
+/// Player in a network game hosted on another computer.
+///
+/// The host simulates the match, the client only shows it. To hide the round trip to the host,
+/// the client predicts its own car by simulating its inputs right away. Whenever a snapshot
+/// arrives, all cars are put into the state of the host and the inputs the host has not seen
+/// yet are simulated again. All other cars are drawn a few ticks in the past, interpolated
+/// between the two snapshots around that time.
+pub struct Client {
+    socket: UdpSocket,
+    address: String,
+    car: CarSelection,
+    welcome: Option<Welcome>,
+    /// Cars of the match, from the lobby of the host.
+    cars: Vec<CarSelection>,
+    /// The host welcomed the client again with another scene, e.g. on the next level.
+    is_scene_new: bool,
+    is_racing: bool,
+    /// Tick of the newest input.
+    tick: u32,
+    /// Inputs the host has not simulated yet, the oldest first.
+    pending: VecDeque<(u32, InputState)>,
+    /// Received snapshots, the oldest first.
+    snapshots: VecDeque<Snapshot>,
+    /// The newest snapshot still needs to be applied.
+    is_snapshot_new: bool,
+    /// Host tick the other cars are drawn at.
+    render_tick: f32,
+    last_heard: Instant,
+    last_hello: Instant,
+    error: Option<String>,
+}
+
+impl Client {
+    /// Ask the host at `address`, e.g. `192.168.0.2:7878`, to join with `car`.
+    ///
+    /// Returns right away, the host answers with a welcome or a refusal, see `receive()`.
+    pub fn new(address: &str, car: &CarSelection) -> Result<Client, String> {
+        let socket = UdpSocket::bind("0.0.0.0:0")
+            .and_then(|socket| socket.connect(address).map(|_| socket))
+            .and_then(|socket| socket.set_nonblocking(true).map(|_| socket))
+            .map_err(|e| format!("Failed to connect to {}: {}", address, e))?;
+        let client = Client {
+            socket,
+            address: address.to_string(),
+            car: car.clone(),
+            welcome: None,
+            cars: Vec::new(),
+            is_scene_new: false,
+            is_racing: false,
+            tick: 0,
+            pending: VecDeque::new(),
+            snapshots: VecDeque::new(),
+            is_snapshot_new: false,
+            render_tick: 0.0,
+            last_heard: Instant::now(),
+            last_hello: Instant::now(),
+            error: None,
+        };
+        client.send(&Message::Hello {
+            version: PROTOCOL_VERSION,
+            car: car.clone(),
+        });
+        Ok(client)
+    }
+
+    /// Join the host at `address` with `car` and wait for its welcome.
+    pub fn connect(address: &str, car: &CarSelection) -> Result<Client, String> {
+        info!("Joining the network game on {}", address);
+        let mut client = Client::new(address, car)?;
+        while client.welcome.is_none() {
+            client.receive()?;
+            thread::sleep(Duration::from_millis(10));
+        }
+        Ok(client)
+    }
+
+    pub fn welcome(&self) -> Option<&Welcome> {
+        self.welcome.as_ref()
+    }
+
+    /// The scene of the host as it was when the client joined.
+    pub fn scene(&self) -> Result<Scene, String> {
+        match self.welcome {
+            Some(ref welcome) => Scene::new(&welcome.map, &self.cars),
+            None => Err(format!("Not yet welcomed by {}", self.address)),
+        }
+    }
+
+    /// The host started the race.
+    pub fn is_racing(&self) -> bool {
+        self.is_racing
+    }
+
+    /// Why the client is no longer part of the game.
+    pub fn error(&self) -> Option<&str> {
+        self.error.as_ref().map(String::as_str)
+    }
+
+    /// Handle all packets received since the last call.
+    ///
+    /// Fails when the host refused the client or is gone.
+    pub fn receive(&mut self) -> Result<(), String> {
+        if let Some(ref error) = self.error {
+            return Err(error.clone());
+        }
+        let mut buffer = [0; MAX_PACKET_SIZE];
+        loop {
+            let size = match self.socket.recv(&mut buffer) {
+                Ok(size) => size,
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => return Err(self.fail(format!("Lost the connection to {}: {}", self.address, e))),
+            };
+            let message = match Message::decode(&buffer[..size]) {
+                Some(message) => message,
+                None => continue,
+            };
+            self.last_heard = Instant::now();
+            match message {
+                Message::Welcome { car, map, tick_rate, cars } => {
+                    let welcome = Welcome {
+                        car: car as usize,
+                        map,
+                        tick_rate,
+                    };
+                    // The same welcome again only means a hello got answered twice.
+                    if self.welcome.as_ref() != Some(&welcome) || self.cars != cars {
+                        info!(
+                            "Joined the network game on {} with car {} on {}",
+                            self.address,
+                            welcome.car + 1,
+                            welcome.map
+                        );
+                        self.is_scene_new = self.welcome.is_some();
+                        self.welcome = Some(welcome);
+                        self.cars = cars;
+                    }
+                }
+                Message::Reject { reason } => return Err(self.fail(format!("{} refused to let us join: {}", self.address, reason))),
+                Message::Lobby { cars, is_racing } => {
+                    self.cars = cars;
+                    self.is_racing = is_racing;
+                }
+                Message::Snapshot(snapshot) => {
+                    if self.snapshots.back().map_or(true, |newest| snapshot.tick > newest.tick) {
+                        self.snapshots.push_back(snapshot);
+                        self.is_snapshot_new = true;
+                        while self.snapshots.len() > SNAPSHOT_BUFFER {
+                            self.snapshots.pop_front();
+                        }
+                    }
+                }
+                Message::Bye => return Err(self.fail(format!("{} ended the game", self.address))),
+                _ => {}
+            }
+        }
+
+        let now = Instant::now();
+        if now.duration_since(self.last_heard) > TIMEOUT {
+            return Err(self.fail(format!("{} does not answer", self.address)));
+        }
+        if self.welcome.is_none() && now.duration_since(self.last_hello) > HELLO_INTERVAL {
+            self.last_hello = now;
+            self.send(&Message::Hello {
+                version: PROTOCOL_VERSION,
+                car: self.car.clone(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Send the `input` of the player for the next tick and advance the `scene` by `dt`.
+    ///
+    /// Returns true when the host replaced the scene, e.g. with the next level.
+    pub fn update(&mut self, scene: &mut Scene, dt: f32, input: &Controller) -> Result<bool, String> {
+        self.receive()?;
+        let car = match self.welcome {
+            Some(ref welcome) => welcome.car,
+            None => return Ok(false),
+        };
+        let is_scene_new = self.is_scene_new;
+        if is_scene_new {
+            self.is_scene_new = false;
+            *scene = self.scene()?;
+            self.pending.clear();
+            self.snapshots.clear();
+            self.is_snapshot_new = false;
+        }
+        while scene.cars.len() < self.cars.len() {
+            let selection = self.cars[scene.cars.len()].clone();
+            scene.add_car(&selection)?;
+        }
+
+        // Put all cars where the host has them and predict our car from there again.
+        if self.is_snapshot_new {
+            self.is_snapshot_new = false;
+            let snapshot = self.snapshots.back().cloned().unwrap();
+            apply(&snapshot.cars, scene, None);
+            scene.race.set_time(snapshot.race_time);
+            scene.derby.set_round(snapshot.round);
+            scene.pickups.set_state(&snapshot.pickups, &snapshot.slicks);
+            if let Some(ack) = snapshot.ack {
+                while self.pending.front().map_or(false, |&(tick, _)| tick <= ack) {
+                    self.pending.pop_front();
+                }
+            }
+            for &(_, pending) in &self.pending {
+                scene.update(dt, &inputs(scene.cars.len(), car, pending));
+            }
+        }
+
+        self.tick += 1;
+        let state = InputState::from_controller(input);
+        self.pending.push_back((self.tick, state));
+        while self.pending.len() > MAX_PENDING_INPUTS {
+            self.pending.pop_front();
+        }
+        let unsent = self.pending.len().saturating_sub(MAX_SENT_INPUTS);
+        self.send(&Message::Input {
+            first: self.pending[unsent].0,
+            inputs: self.pending.iter().skip(unsent).map(|&(_, input)| input).collect(),
+        });
+        scene.update(dt, &inputs(scene.cars.len(), car, state));
+
+        self.interpolate(scene, car);
+        Ok(is_scene_new)
+    }
+
+    /// Move all cars but our own `car` to their state at `render_tick`.
+    fn interpolate(&mut self, scene: &mut Scene, car: usize) {
+        let newest = match self.snapshots.back() {
+            Some(snapshot) => snapshot.tick as f32,
+            None => return,
+        };
+        // Run at the pace of the host, but stay the delay behind its newest snapshot.
+        let goal = newest - INTERPOLATION_DELAY;
+        self.render_tick += 1.0;
+        if (self.render_tick - goal).abs() > INTERPOLATION_DELAY * 2.0 {
+            self.render_tick = goal;
+        } else {
+            self.render_tick += (goal - self.render_tick) * 0.05;
+        }
+        self.render_tick = self.render_tick.min(newest);
+
+        let after = self.snapshots.iter().position(|s| s.tick as f32 >= self.render_tick);
+        let cars = match after {
+            Some(0) | None => self.snapshots[after.unwrap_or(self.snapshots.len() - 1)].cars.clone(),
+            Some(after) => {
+                let (a, b) = (&self.snapshots[after - 1], &self.snapshots[after]);
+                let t = (self.render_tick - a.tick as f32) / (b.tick - a.tick) as f32;
+                a.cars.iter().zip(&b.cars).map(|(a, b)| a.lerp(b, t)).collect()
+            }
+        };
+        apply(&cars, scene, Some(car));
+    }
+
+    /// Leave the game.
+    pub fn leave(&mut self) {
+        self.send(&Message::Bye);
+    }
+
+    pub fn lobby(&self) -> Lobby {
+        Lobby {
+            address: self.address.clone(),
+            is_host: false,
+            cars: self.cars.clone(),
+            local_cars: self.welcome.iter().map(|welcome| welcome.car).collect(),
+            is_racing: self.is_racing,
+            error: self.error.clone(),
+        }
+    }
+
+    fn fail(&mut self, error: String) -> String {
+        self.error = Some(error.clone());
+        error
+    }
+
+    fn send(&self, message: &Message) {
+        // Lost packets are normal, the next one makes up for it.
+        let _ = self.socket.send(&message.encode());
+    }
+}
+
+/// Put the cars of the `scene` into the `states`, except car `skip`.
+fn apply(states: &[CarState], scene: &mut Scene, skip: Option<usize>) {
+    for (id, state) in states.iter().enumerate().take(scene.cars.len()) {
+        if Some(id) != skip {
+            state.apply(scene, id);
+        }
+    }
+}
+
+/// Input of `cars` cars where only `car` is driven.
+fn inputs(cars: usize, car: usize, input: InputState) -> Vec<Controller> {
+    let mut inputs = vec![Controller::with_state(0., 0., false); cars];
+    if car < cars {
+        inputs[car] = input.controller();
+    }
+    inputs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Client;
+    use crate::game::car_definition::CarSelection;
+    use crate::game::controller::Controller;
+    use crate::game::scene::Scene;
+    use crate::net::Server;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    /// Host and client in one process, talking over localhost.
+    #[test]
+    fn drive_over_localhost() {
+        let mut host = Scene::new("race_track_1", &[CarSelection::new("kart", None)]).unwrap();
+        let mut server = Server::bind("127.0.0.1:0", 60).unwrap();
+        let address = server.local_addr().unwrap().to_string();
+        let mut client = Client::new(&address, &CarSelection::new("formula", Some(2))).unwrap();
+
+        let start = Instant::now();
+        while client.welcome().is_none() {
+            assert!(start.elapsed() < Duration::from_secs(2), "no welcome");
+            assert!(server.receive(&mut host).len() <= 1);
+            client.receive().unwrap();
+            thread::sleep(Duration::from_millis(1));
+        }
+        let car = client.welcome().unwrap().car;
+        assert_eq!(car, 1);
+        assert_eq!(host.selections[car], CarSelection::new("formula", Some(2)));
+        let mut scene = client.scene().unwrap();
+        assert_eq!(scene.cars.len(), 2);
+        server.set_racing(true, &host);
+
+        // Full throttle on the client drives the car on the host, the client sees it move.
+        let dt = 1.0 / 60.0;
+        let forward = Controller::with_state(0., 1., false);
+        for _ in 0..120 {
+            client.update(&mut scene, dt, &forward).unwrap();
+            thread::sleep(Duration::from_millis(1));
+            server.receive(&mut host);
+            let mut input = vec![Controller::with_state(0., 0., false); host.cars.len()];
+            server.apply_inputs(&mut input);
+            host.update(dt, &input);
+            server.send_state(&host);
+        }
+        assert!(client.is_racing());
+        assert!(host.cars[car].body.velocity.norm() > 5.0);
+        assert!(host.cars[0].body.velocity.norm() < 0.1);
+        assert!(scene.cars[car].body.velocity.norm() > 5.0);
+        assert!((scene.cars[car].body.position - host.cars[car].body.position).norm() < 5.0);
+
+        client.leave();
+        thread::sleep(Duration::from_millis(10));
+        server.receive(&mut host);
+        assert_eq!(server.lobby(&host).local_cars, vec![0, 1]);
+    }
+
+    /// Car names from the network are checked against the cars of the host, not resolved as paths.
+    #[test]
+    fn reject_unknown_car() {
+        let mut host = Scene::new("race_track_1", &[CarSelection::new("kart", None)]).unwrap();
+        let mut server = Server::bind("127.0.0.1:0", 60).unwrap();
+        let address = server.local_addr().unwrap().to_string();
+        let mut client = Client::new(&address, &CarSelection::new("../cars/kart", None)).unwrap();
+
+        let start = Instant::now();
+        let error = loop {
+            assert!(start.elapsed() < Duration::from_secs(2), "no answer");
+            assert!(server.receive(&mut host).is_empty());
+            if let Err(e) = client.receive() {
+                break e;
+            }
+            thread::sleep(Duration::from_millis(1));
+        };
+        assert!(error.ends_with("Unknown car '../cars/kart'"));
+        assert_eq!(host.cars.len(), 1);
+    }
+
+    /// Clients keep driving while the host has no input of its own, e.g. with a menu open.
+    #[test]
+    fn drive_without_host_input() {
+        let mut host = Scene::new("race_track_1", &[CarSelection::new("kart", None)]).unwrap();
+        let mut server = Server::bind("127.0.0.1:0", 60).unwrap();
+        let address = server.local_addr().unwrap().to_string();
+        let mut client = Client::new(&address, &CarSelection::new("kart", None)).unwrap();
+
+        let start = Instant::now();
+        while client.welcome().is_none() {
+            assert!(start.elapsed() < Duration::from_secs(2), "no welcome");
+            server.receive(&mut host);
+            client.receive().unwrap();
+            thread::sleep(Duration::from_millis(1));
+        }
+        let car = client.welcome().unwrap().car;
+        let mut scene = client.scene().unwrap();
+        server.set_racing(true, &host);
+
+        let dt = 1.0 / 60.0;
+        let forward = Controller::with_state(0., 1., false);
+        for _ in 0..120 {
+            client.update(&mut scene, dt, &forward).unwrap();
+            thread::sleep(Duration::from_millis(1));
+            server.receive(&mut host);
+            let mut input = Vec::new();
+            server.apply_inputs(&mut input);
+            host.update(dt, &input);
+            server.send_state(&host);
+        }
+        assert!(host.cars[car].body.velocity.norm() > 5.0);
+        assert!(host.cars[0].body.velocity.norm() < 0.1);
+    }
+}
@@ -0,0 +1,310 @@
+// This file is part of Carambolage.
+
+// Carambolage is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Carambolage is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Carambolage.  If not, see <http://www.gnu.org/licenses/>.
+use super::Server;
+use crate::game::controller::Controller;
+use crate::game::scene::Scene;
+use crate::util::FrameLimiter;
+use log::{error, info, warn};
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::thread;
+use std::time::Duration;
+
+/// Settings of a dedicated server, see `carambolage-server --help`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ServerSettings {
+    /// Listen for clients on this address, e.g. `0.0.0.0:7878`.
+    pub address: String,
+    /// Levels played in turn, see `LevelManifest`.
+    pub maps: Vec<String>,
+    /// Simulation steps per second.
+    pub tick_rate: u32,
+    /// A race only starts once this many players joined.
+    pub min_players: usize,
+    /// Time in [s] players get in the lobby before a race starts.
+    pub lobby_time: f32,
+    /// A race ends after this time in [s], even if not every car finished.
+    pub time_limit: f32,
+    /// Stop after this many races, `None` runs until the process is killed.
+    pub races: Option<u32>,
+    /// The standings of every race are appended to this file.
+    pub results: String,
+}
+
+impl Default for ServerSettings {
+    fn default() -> ServerSettings {
+        ServerSettings {
+            address: super::host_address(""),
+            maps: vec!["race_track_1".to_string(), "race_track_2".to_string()],
+            tick_rate: 60,
+            min_players: 1,
+            lobby_time: 10.0,
+            time_limit: 300.0,
+            races: None,
+            results: "carambolage-results.log".to_string(),
+        }
+    }
+}
+
+/// Network game without window and players of its own.
+///
+/// Clients drive around in the lobby until enough of them joined, then the race starts. After
+/// every race the standings are written to the results file and the next level is loaded.
+pub struct DedicatedServer {
+    settings: ServerSettings,
+    server: Server,
+    scene: Scene,
+    /// Index of the current level in `settings.maps`.
+    map: usize,
+    is_racing: bool,
+    /// Time in [s] since the race started.
+    race_time: f32,
+    /// Time in [s] left until the race starts.
+    countdown: f32,
+    /// Races finished so far.
+    races: u32,
+}
+
+impl DedicatedServer {
+    /// Load the first level and listen for clients.
+    pub fn new(settings: ServerSettings) -> Result<DedicatedServer, String> {
+        let map = settings.maps.first().ok_or_else(|| "No map to play".to_string())?;
+        let scene = Scene::new(map, &[])?;
+        let server = Server::bind(&settings.address, settings.tick_rate)?;
+        Ok(DedicatedServer {
+            countdown: settings.lobby_time,
+            settings,
+            server,
+            scene,
+            map: 0,
+            is_racing: false,
+            race_time: 0.0,
+            races: 0,
+        })
+    }
+
+    pub fn server(&self) -> &Server {
+        &self.server
+    }
+
+    pub fn scene(&self) -> &Scene {
+        &self.scene
+    }
+
+    pub fn is_racing(&self) -> bool {
+        self.is_racing
+    }
+
+    /// The server played all races of `ServerSettings::races`.
+    pub fn is_done(&self) -> bool {
+        self.settings.races.map_or(false, |races| self.races >= races)
+    }
+
+    /// Advance the scene by one tick of `dt` and start or end the race.
+    pub fn update(&mut self, dt: f32) {
+        self.server.receive(&mut self.scene);
+        let mut input = vec![Controller::with_state(0., 0., false); self.scene.cars.len()];
+        self.server.apply_inputs(&mut input);
+        self.scene.update(dt, &input);
+        self.server.send_state(&self.scene);
+
+        if self.is_racing {
+            self.race_time += dt;
+            if self.server.players() == 0 {
+                info!("Race on {} aborted, all players left", self.scene.level.name);
+                self.next_race();
+            } else if self.scene.race.is_over() || self.scene.derby.is_over() || self.race_time >= self.settings.time_limit {
+                if let Err(e) = self.write_results() {
+                    error!("{}", e);
+                }
+                self.races += 1;
+                self.next_race();
+            }
+        } else if self.server.players() < self.settings.min_players.max(1) {
+            self.countdown = self.settings.lobby_time;
+        } else {
+            self.countdown -= dt;
+            if self.countdown <= 0.0 {
+                info!("Race on {} starts with {} players", self.scene.level.name, self.scene.cars.len());
+                self.scene.reset_cars();
+                self.is_racing = true;
+                self.race_time = 0.0;
+                self.server.set_racing(true, &self.scene);
+            }
+        }
+    }
+
+    /// Go back to the lobby on the next level that loads.
+    fn next_race(&mut self) {
+        self.is_racing = false;
+        self.countdown = self.settings.lobby_time;
+        for _ in 0..self.settings.maps.len() {
+            self.map = (self.map + 1) % self.settings.maps.len();
+            match self.server.load_map(&self.settings.maps[self.map], &self.scene) {
+                Ok(scene) => {
+                    self.scene = scene;
+                    break;
+                }
+                Err(e) => warn!("Skipping map {}: {}", self.settings.maps[self.map], e),
+            }
+        }
+        self.server.set_racing(false, &self.scene);
+    }
+
+    /// Append the standings of the race to the results file.
+    fn write_results(&self) -> Result<(), String> {
+        let file = &self.settings.results;
+        let text = results(&self.scene, &time::now().rfc3339().to_string());
+        info!("{}", text.trim_end());
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(file)
+            .and_then(|mut f| f.write_all(text.as_bytes()))
+            .map_err(|e| format!("Failed to write {}: {}", file, e))
+    }
+}
+
+/// Standings of the race or derby of the `scene` that ended at `date`, one line per car.
+fn results(scene: &Scene, date: &str) -> String {
+    let race = &scene.race;
+    let derby = &scene.derby;
+    if derby.is_active() {
+        let mut text = format!("{} {}, round {}/{}\n", date, scene.level.name, derby.round(), derby.rounds());
+        for (place, standing) in derby.standings().iter().enumerate() {
+            let car = &scene.selections[standing.car];
+            text += &format!("{:>3}. car {} {}  {} wins\n", place + 1, standing.car + 1, car, standing.wins);
+        }
+        return text;
+    }
+    let mut text = format!("{} {}, {} laps, {:.2} s\n", date, scene.level.name, race.laps(), race.time());
+    for (place, standing) in race.standings().iter().enumerate() {
+        let car = &scene.selections[standing.car];
+        text += &match standing.finish_time {
+            Some(time) => format!("{:>3}. car {} {}  finished in {:.2} s\n", place + 1, standing.car + 1, car, time),
+            None => format!(
+                "{:>3}. car {} {}  {}lap {}/{}\n",
+                place + 1,
+                standing.car + 1,
+                car,
+                if standing.is_wrecked { "wrecked in " } else { "" },
+                standing.lap,
+                race.laps()
+            ),
+        };
+    }
+    text
+}
+
+/// Run a dedicated server until it played `settings.races`.
+pub fn run(settings: ServerSettings) {
+    let mut dedicated = match DedicatedServer::new(settings) {
+        Ok(dedicated) => dedicated,
+        Err(e) => {
+            error!("{}", e);
+            eprintln!("{}", e);
+            return;
+        }
+    };
+    let dt = 1.0 / dedicated.settings.tick_rate.max(1) as f32;
+    let mut frame_limiter = FrameLimiter::new(dedicated.settings.tick_rate);
+    while !dedicated.is_done() {
+        frame_limiter.start();
+        dedicated.update(dt);
+        while frame_limiter.stop() {
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+    dedicated.server.shutdown();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{results, DedicatedServer, ServerSettings};
+    use crate::game::car_definition::CarSelection;
+    use crate::game::controller::Controller;
+    use crate::net::Client;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn rotate_maps() {
+        let settings = ServerSettings {
+            address: "127.0.0.1:0".to_string(),
+            lobby_time: 0.5,
+            time_limit: 1.0,
+            races: Some(1),
+            results: std::env::temp_dir()
+                .join("carambolage-test-results.log")
+                .to_string_lossy()
+                .into_owned(),
+            ..Default::default()
+        };
+        let dt = 1.0 / settings.tick_rate as f32;
+        let mut dedicated = DedicatedServer::new(settings).unwrap();
+        let address = dedicated.server().local_addr().unwrap().to_string();
+
+        // Nobody there, nothing starts.
+        for _ in 0..60 {
+            dedicated.update(dt);
+        }
+        assert!(!dedicated.is_racing());
+        let mut client = Client::new(&address, &CarSelection::new("kart", None)).unwrap();
+
+        let mut scene = None;
+        let mut was_racing = false;
+        for _ in 0..200 {
+            dedicated.update(dt);
+            thread::sleep(Duration::from_millis(1));
+            client.receive().unwrap();
+            if scene.is_none() && client.welcome().is_some() {
+                scene = client.scene().ok();
+            }
+            if let Some(ref mut scene) = scene {
+                client.update(scene, dt, &Controller::with_state(0., 1., false)).unwrap();
+            }
+            was_racing |= dedicated.is_racing();
+            if dedicated.is_done() {
+                break;
+            }
+        }
+        assert!(was_racing);
+        assert!(dedicated.is_done());
+        assert!(!dedicated.is_racing());
+        assert_eq!(dedicated.scene().level.name, "race_track_2");
+        assert_eq!(dedicated.scene().cars.len(), 1);
+
+        // The client follows to the next level.
+        for _ in 0..10 {
+            thread::sleep(Duration::from_millis(1));
+            client
+                .update(scene.as_mut().unwrap(), dt, &Controller::with_state(0., 0., false))
+                .unwrap();
+        }
+        assert_eq!(scene.unwrap().level.name, "race_track_2");
+    }
+
+    #[test]
+    fn list_results() {
+        let settings = ServerSettings::default();
+        let mut scene = crate::game::scene::Scene::new(&settings.maps[0], &[CarSelection::new("kart", None)]).unwrap();
+        scene.race.set_progress(0, 3, Some(61.5));
+        let text = results(&scene, "2019-05-01T12:00:00Z");
+        let mut lines = text.lines();
+        assert!(lines.next().unwrap().starts_with("2019-05-01T12:00:00Z race_track_1, "));
+        assert_eq!(lines.next(), Some("  1. car 1 kart  finished in 61.50 s"));
+        assert_eq!(lines.next(), None);
+    }
+}
@@ -0,0 +1,71 @@
+// This file is part of Carambolage.
+
+// Carambolage is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Carambolage is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Carambolage.  If not, see <http://www.gnu.org/licenses/>.
+
+/// Client side prediction and interpolation.
+mod client;
+/// Network game without window, rotating through levels.
+pub mod dedicated;
+/// Messages and their binary encoding.
+pub mod protocol;
+/// Authoritative host of a network game.
+mod server;
+
+pub use self::client::Client;
+pub use self::server::Server;
+
+use crate::game::car_definition::CarSelection;
+use std::time::Duration;
+
+/// Port a host listens on if none is given.
+pub const DEFAULT_PORT: u16 = 7878;
+/// The other side is considered gone after this long without a packet.
+pub const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// What the lobby screen shows about a network game.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Lobby {
+    /// Address the host listens on, or the client is connected to.
+    pub address: String,
+    pub is_host: bool,
+    /// Car and color of every car in the match.
+    pub cars: Vec<CarSelection>,
+    /// Cars driven by players on this computer.
+    pub local_cars: Vec<usize>,
+    /// The host started the race.
+    pub is_racing: bool,
+    /// Why the connection is lost.
+    pub error: Option<String>,
+}
+
+/// Address to listen on for `--host`, either empty for all interfaces on `DEFAULT_PORT`, a port
+/// or a full address.
+pub fn host_address(text: &str) -> String {
+    if text.is_empty() {
+        format!("0.0.0.0:{}", DEFAULT_PORT)
+    } else if text.parse::<u16>().is_ok() {
+        format!("0.0.0.0:{}", text)
+    } else {
+        text.to_string()
+    }
+}
+
+/// Address of the host for `--join`, `DEFAULT_PORT` is used if it has no port.
+pub fn join_address(text: &str) -> String {
+    if text.contains(':') {
+        text.to_string()
+    } else {
+        format!("{}:{}", text, DEFAULT_PORT)
+    }
+}
@@ -0,0 +1,644 @@
+// This file is part of Carambolage.
+
+// Carambolage is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Carambolage is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Carambolage.  If not, see <http://www.gnu.org/licenses/>.
+use crate::game::car::{Effects, Side};
+use crate::game::car_definition::CarSelection;
+use crate::game::controller::Controller;
+use crate::game::derby::DerbyProgress;
+use crate::game::pickup::Slick;
+use crate::game::scene::Scene;
+use nalgebra::Vector3;
+
+/// Bumped whenever a message changes, hosts reject clients with another version.
+pub const PROTOCOL_VERSION: u16 = 5;
+/// Every packet starts with these bytes, anything else on the port is ignored.
+const MAGIC: [u8; 4] = *b"CRMB";
+/// Largest packet that is sent or received in [byte].
+pub const MAX_PACKET_SIZE: usize = 1400;
+/// Most cars in a network game, so a snapshot always fits into a single packet.
+pub const MAX_CARS: usize = 16;
+
+/// Input of a player for a single tick, the part of a `Controller` that is sent.
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub struct InputState {
+    pub x_axis: f32,
+    pub y_axis: f32,
+    pub boost: bool,
+}
+
+/// Motion, health, power-up, race and derby state of a single car.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct CarState {
+    pub position: Vector3<f32>,
+    /// Yaw in [rad].
+    pub yaw: f32,
+    pub velocity: Vector3<f32>,
+    /// Yaw rate in [rad/s].
+    pub yaw_rate: f32,
+    pub boost: f32,
+    /// Health of every side in 1/255 of a new car, see `Car::health`. Rounded up, so only a
+    /// destroyed side is sent as 0.
+    pub health: [u8; 4],
+    pub effects: Effects,
+    /// Checkpoints passed, see `RaceProgress::passed()`.
+    pub passed: u32,
+    pub finish_time: Option<f32>,
+    /// Derby lives left, see `DerbyProgress`.
+    pub lives: u32,
+    pub wins: u32,
+}
+
+/// State of the whole match on the host after a tick.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    /// Tick of the host.
+    pub tick: u32,
+    /// Newest input tick of the receiving client the host simulated.
+    pub ack: Option<u32>,
+    pub race_time: f32,
+    /// Derby round, see `Derby::round()`.
+    pub round: u32,
+    pub cars: Vec<CarState>,
+    /// Respawn timer of every pickup, see `Pickup::timer`.
+    pub pickups: Vec<f32>,
+    pub slicks: Vec<Slick>,
+}
+
+/// Everything a host and a client tell each other.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Message {
+    /// Client asks to join with a car.
+    Hello {
+        version: u16,
+        car: CarSelection,
+    },
+    /// Client inputs of consecutive ticks starting at `first`, resent until acknowledged.
+    Input {
+        first: u32,
+        inputs: Vec<InputState>,
+    },
+    /// Client leaves the match.
+    Bye,
+    /// Host accepts a client as driver of car `car`.
+    Welcome {
+        car: u32,
+        map: String,
+        tick_rate: u32,
+        cars: Vec<CarSelection>,
+    },
+    /// Host refuses a client.
+    Reject {
+        reason: String,
+    },
+    /// Host tells all clients about the cars and whether the race is running.
+    Lobby {
+        cars: Vec<CarSelection>,
+        is_racing: bool,
+    },
+    Snapshot(Snapshot),
+}
+
+impl InputState {
+    pub fn from_controller(controller: &Controller) -> InputState {
+        InputState {
+            x_axis: controller.get_x_axis(),
+            y_axis: controller.get_y_axis(),
+            boost: controller.get_boost(),
+        }
+    }
+
+    pub fn controller(&self) -> Controller {
+        Controller::with_state(self.x_axis, self.y_axis, self.boost)
+    }
+}
+
+impl CarState {
+    /// State of car `id` of the `scene`.
+    pub fn from_scene(scene: &Scene, id: usize) -> CarState {
+        let car = &scene.cars[id];
+        let progress = scene.race.progress(id);
+        let derby = scene.derby.progress(id);
+        CarState {
+            position: car.body.position,
+            yaw: car.body.rotation[2],
+            velocity: car.body.velocity,
+            yaw_rate: car.body.angular_velocity[2],
+            boost: car.boost,
+            health: [
+                health_to_u8(car.health_fraction(Side::Front)),
+                health_to_u8(car.health_fraction(Side::Rear)),
+                health_to_u8(car.health_fraction(Side::Left)),
+                health_to_u8(car.health_fraction(Side::Right)),
+            ],
+            effects: car.effects,
+            passed: progress.passed() as u32,
+            finish_time: progress.finish_time,
+            lives: derby.lives,
+            wins: derby.wins,
+        }
+    }
+
+    /// Move car `id` of the `scene` into this state.
+    pub fn apply(&self, scene: &mut Scene, id: usize) {
+        let car = &mut scene.cars[id];
+        car.body.position = self.position;
+        car.body.rotation = Vector3::new(0., 0., self.yaw);
+        car.body.velocity = self.velocity;
+        car.body.angular_velocity = Vector3::new(0., 0., self.yaw_rate);
+        car.boost = self.boost;
+        for (health, &sent) in car.health.iter_mut().zip(&self.health) {
+            *health = f32::from(sent) / 255.0 * car.stats.durability;
+        }
+        car.effects = self.effects;
+        scene.race.set_progress(id, self.passed as usize, self.finish_time);
+        scene.derby.set_progress(
+            id,
+            DerbyProgress {
+                lives: self.lives,
+                wins: self.wins,
+            },
+        );
+    }
+
+    /// State between `self` (`t` = 0.0) and `other` (`t` = 1.0), for drawing remote cars smoothly.
+    pub fn lerp(&self, other: &CarState, t: f32) -> CarState {
+        // Turn the short way round.
+        let mut turn = (other.yaw - self.yaw) % (2.0 * std::f32::consts::PI);
+        if turn > std::f32::consts::PI {
+            turn -= 2.0 * std::f32::consts::PI;
+        } else if turn < -std::f32::consts::PI {
+            turn += 2.0 * std::f32::consts::PI;
+        }
+        CarState {
+            position: self.position + (other.position - self.position) * t,
+            yaw: self.yaw + turn * t,
+            velocity: self.velocity + (other.velocity - self.velocity) * t,
+            yaw_rate: self.yaw_rate + (other.yaw_rate - self.yaw_rate) * t,
+            boost: self.boost + (other.boost - self.boost) * t,
+            ..*other
+        }
+    }
+}
+
+/// Health fraction in 1/255, see `CarState::health`.
+fn health_to_u8(fraction: f32) -> u8 {
+    (fraction.max(0.0).min(1.0) * 255.0).ceil() as u8
+}
+
+impl Snapshot {
+    /// Snapshot of the `scene` after `tick` for a client whose input `ack` was simulated last.
+    pub fn from_scene(scene: &Scene, tick: u32, ack: Option<u32>) -> Snapshot {
+        Snapshot {
+            tick,
+            ack,
+            race_time: scene.race.time(),
+            round: scene.derby.round(),
+            cars: (0..scene.cars.len()).map(|id| CarState::from_scene(scene, id)).collect(),
+            pickups: scene.pickups.pickups().iter().map(|pickup| pickup.timer).collect(),
+            slicks: scene.pickups.slicks().to_vec(),
+        }
+    }
+}
+
+impl Message {
+    /// Serialize the message into a packet.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut w = Writer(MAGIC.to_vec());
+        match self {
+            Message::Hello { version, car } => {
+                w.u8(0);
+                w.u16(*version);
+                w.car(car);
+            }
+            Message::Input { first, inputs } => {
+                w.u8(1);
+                w.u32(*first);
+                w.u8(inputs.len() as u8);
+                for input in inputs {
+                    w.f32(input.x_axis);
+                    w.f32(input.y_axis);
+                    w.u8(input.boost as u8);
+                }
+            }
+            Message::Bye => w.u8(2),
+            Message::Welcome { car, map, tick_rate, cars } => {
+                w.u8(3);
+                w.u32(*car);
+                w.string(map);
+                w.u32(*tick_rate);
+                w.cars(cars);
+            }
+            Message::Reject { reason } => {
+                w.u8(4);
+                w.string(reason);
+            }
+            Message::Lobby { cars, is_racing } => {
+                w.u8(5);
+                w.cars(cars);
+                w.u8(*is_racing as u8);
+            }
+            Message::Snapshot(snapshot) => {
+                w.u8(6);
+                w.u32(snapshot.tick);
+                w.option(snapshot.ack, Writer::u32);
+                w.f32(snapshot.race_time);
+                w.u32(snapshot.round);
+                w.u8(snapshot.cars.len() as u8);
+                for car in &snapshot.cars {
+                    w.vector(&car.position);
+                    w.f32(car.yaw);
+                    w.vector(&car.velocity);
+                    w.f32(car.yaw_rate);
+                    w.f32(car.boost);
+                    car.health.iter().for_each(|&health| w.u8(health));
+                    w.f32(car.effects.shield);
+                    w.f32(car.effects.heavy);
+                    w.f32(car.effects.oil);
+                    w.u32(car.passed);
+                    w.option(car.finish_time, Writer::f32);
+                    w.u32(car.lives);
+                    w.u32(car.wins);
+                }
+                w.u8(snapshot.pickups.len() as u8);
+                snapshot.pickups.iter().for_each(|&timer| w.f32(timer));
+                w.u8(snapshot.slicks.len() as u8);
+                for slick in &snapshot.slicks {
+                    w.f32(slick.position[0]);
+                    w.f32(slick.position[1]);
+                    w.f32(slick.time);
+                }
+            }
+        }
+        w.0
+    }
+
+    /// Deserialize a packet, `None` for anything that is not a valid message.
+    pub fn decode(packet: &[u8]) -> Option<Message> {
+        if packet.len() < MAGIC.len() || packet[..MAGIC.len()] != MAGIC {
+            return None;
+        }
+        let mut r = Reader(&packet[MAGIC.len()..]);
+        let message = match r.u8()? {
+            0 => Message::Hello {
+                version: r.u16()?,
+                car: r.car()?,
+            },
+            1 => {
+                let first = r.u32()?;
+                let mut inputs = Vec::new();
+                for _ in 0..r.u8()? {
+                    inputs.push(InputState {
+                        x_axis: r.f32()?,
+                        y_axis: r.f32()?,
+                        boost: r.u8()? != 0,
+                    });
+                }
+                Message::Input { first, inputs }
+            }
+            2 => Message::Bye,
+            3 => Message::Welcome {
+                car: r.u32()?,
+                map: r.string()?,
+                tick_rate: r.u32()?,
+                cars: r.cars()?,
+            },
+            4 => Message::Reject { reason: r.string()? },
+            5 => Message::Lobby {
+                cars: r.cars()?,
+                is_racing: r.u8()? != 0,
+            },
+            6 => {
+                let tick = r.u32()?;
+                let ack = r.option(Reader::u32)?;
+                let race_time = r.f32()?;
+                let round = r.u32()?;
+                let mut cars = Vec::new();
+                for _ in 0..r.u8()? {
+                    cars.push(CarState {
+                        position: r.vector()?,
+                        yaw: r.f32()?,
+                        velocity: r.vector()?,
+                        yaw_rate: r.f32()?,
+                        boost: r.f32()?,
+                        health: [r.u8()?, r.u8()?, r.u8()?, r.u8()?],
+                        effects: Effects {
+                            shield: r.f32()?,
+                            heavy: r.f32()?,
+                            oil: r.f32()?,
+                        },
+                        passed: r.u32()?,
+                        finish_time: r.option(Reader::f32)?,
+                        lives: r.u32()?,
+                        wins: r.u32()?,
+                    });
+                }
+                let mut pickups = Vec::new();
+                for _ in 0..r.u8()? {
+                    pickups.push(r.f32()?);
+                }
+                let mut slicks = Vec::new();
+                for _ in 0..r.u8()? {
+                    slicks.push(Slick {
+                        position: Vector3::new(r.f32()?, r.f32()?, 0.0),
+                        time: r.f32()?,
+                    });
+                }
+                Message::Snapshot(Snapshot {
+                    tick,
+                    ack,
+                    race_time,
+                    round,
+                    cars,
+                    pickups,
+                    slicks,
+                })
+            }
+            _ => return None,
+        };
+        if r.0.is_empty() {
+            Some(message)
+        } else {
+            None
+        }
+    }
+}
+
+/// Little endian serialization.
+struct Writer(Vec<u8>);
+
+impl Writer {
+    fn u8(&mut self, value: u8) {
+        self.0.push(value);
+    }
+
+    fn u16(&mut self, value: u16) {
+        self.0.extend_from_slice(&[value as u8, (value >> 8) as u8]);
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.0
+            .extend_from_slice(&[value as u8, (value >> 8) as u8, (value >> 16) as u8, (value >> 24) as u8]);
+    }
+
+    fn f32(&mut self, value: f32) {
+        self.u32(value.to_bits());
+    }
+
+    fn vector(&mut self, value: &Vector3<f32>) {
+        value.iter().for_each(|&x| self.f32(x));
+    }
+
+    fn option<T>(&mut self, value: Option<T>, write: fn(&mut Writer, T)) {
+        match value {
+            Some(value) => {
+                self.u8(1);
+                write(self, value);
+            }
+            None => self.u8(0),
+        }
+    }
+
+    fn string(&mut self, value: &str) {
+        self.u16(value.len() as u16);
+        self.0.extend_from_slice(value.as_bytes());
+    }
+
+    fn car(&mut self, car: &CarSelection) {
+        self.string(&car.to_string());
+    }
+
+    fn cars(&mut self, cars: &[CarSelection]) {
+        self.u8(cars.len() as u8);
+        cars.iter().for_each(|car| self.car(car));
+    }
+}
+
+/// Little endian deserialization, every read fails at the end of the packet.
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, n: usize) -> Option<&'a [u8]> {
+        if self.0.len() < n {
+            return None;
+        }
+        let (bytes, rest) = self.0.split_at(n);
+        self.0 = rest;
+        Some(bytes)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.bytes(1).map(|b| b[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        self.bytes(2).map(|b| u16::from(b[0]) | u16::from(b[1]) << 8)
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        self.bytes(4)
+            .map(|b| u32::from(b[0]) | u32::from(b[1]) << 8 | u32::from(b[2]) << 16 | u32::from(b[3]) << 24)
+    }
+
+    fn f32(&mut self) -> Option<f32> {
+        self.u32().map(f32::from_bits)
+    }
+
+    fn vector(&mut self) -> Option<Vector3<f32>> {
+        Some(Vector3::new(self.f32()?, self.f32()?, self.f32()?))
+    }
+
+    fn option<T>(&mut self, read: fn(&mut Reader<'a>) -> Option<T>) -> Option<Option<T>> {
+        match self.u8()? {
+            0 => Some(None),
+            _ => read(self).map(Some),
+        }
+    }
+
+    fn string(&mut self) -> Option<String> {
+        let len = self.u16()? as usize;
+        String::from_utf8(self.bytes(len)?.to_vec()).ok()
+    }
+
+    fn car(&mut self) -> Option<CarSelection> {
+        CarSelection::parse_list(&self.string()?).ok()?.pop()
+    }
+
+    fn cars(&mut self) -> Option<Vec<CarSelection>> {
+        (0..self.u8()?).map(|_| self.car()).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{health_to_u8, CarState, InputState, Message, Snapshot, MAX_CARS, MAX_PACKET_SIZE, PROTOCOL_VERSION};
+    use crate::game::car::Effects;
+    use crate::game::car_definition::CarSelection;
+    use crate::game::pickup::{Slick, MAX_PICKUPS, MAX_SLICKS};
+    use nalgebra::Vector3;
+
+    #[test]
+    fn encode_and_decode() {
+        let car = CarState {
+            position: Vector3::new(1.5, -2.0, 0.25),
+            yaw: 3.0,
+            velocity: Vector3::new(0.0, 8.0, -0.0),
+            yaw_rate: -0.5,
+            boost: 42.0,
+            health: [255, 0, 128, 1],
+            effects: Effects {
+                shield: 1.0,
+                heavy: 0.0,
+                oil: 0.5,
+            },
+            passed: 7,
+            finish_time: Some(123.25),
+            lives: 2,
+            wins: 1,
+        };
+        let messages = vec![
+            Message::Hello {
+                version: PROTOCOL_VERSION,
+                car: CarSelection::new("formula", Some(3)),
+            },
+            Message::Input {
+                first: 1000,
+                inputs: vec![
+                    InputState {
+                        x_axis: -1.0,
+                        y_axis: 0.5,
+                        boost: true,
+                    },
+                    Default::default(),
+                ],
+            },
+            Message::Bye,
+            Message::Welcome {
+                car: 2,
+                map: "race_track_2".to_string(),
+                tick_rate: 60,
+                cars: vec![CarSelection::new("kart", None), CarSelection::new("lamba", Some(1))],
+            },
+            Message::Reject {
+                reason: "Full".to_string(),
+            },
+            Message::Lobby {
+                cars: vec![CarSelection::new("kart", None)],
+                is_racing: true,
+            },
+            Message::Snapshot(Snapshot {
+                tick: 5,
+                ack: Some(99),
+                race_time: 1.5,
+                round: 2,
+                cars: vec![car, CarState { finish_time: None, ..car }],
+                pickups: vec![0.0, 3.5],
+                slicks: vec![Slick {
+                    position: Vector3::new(4.0, -2.0, 0.0),
+                    time: 12.0,
+                }],
+            }),
+            Message::Snapshot(Snapshot {
+                tick: 6,
+                ack: None,
+                race_time: 0.0,
+                round: 0,
+                cars: Vec::new(),
+                pickups: Vec::new(),
+                slicks: Vec::new(),
+            }),
+        ];
+        for message in messages {
+            assert_eq!(Message::decode(&message.encode()), Some(message));
+        }
+    }
+
+    #[test]
+    fn full_snapshot_fits() {
+        let car = CarState {
+            position: Vector3::new(0.0, 0.0, 0.0),
+            yaw: 0.0,
+            velocity: Vector3::new(0.0, 0.0, 0.0),
+            yaw_rate: 0.0,
+            boost: 0.0,
+            health: [255; 4],
+            effects: Default::default(),
+            passed: 0,
+            finish_time: Some(0.0),
+            lives: 0,
+            wins: 0,
+        };
+        let snapshot = Message::Snapshot(Snapshot {
+            tick: 0,
+            ack: Some(0),
+            race_time: 0.0,
+            round: 0,
+            cars: vec![car; MAX_CARS],
+            pickups: vec![0.0; MAX_PICKUPS],
+            slicks: vec![
+                Slick {
+                    position: Vector3::new(0.0, 0.0, 0.0),
+                    time: 0.0,
+                };
+                MAX_SLICKS
+            ],
+        });
+        assert!(snapshot.encode().len() <= MAX_PACKET_SIZE);
+
+        // Only a destroyed side is sent as destroyed.
+        assert_eq!(health_to_u8(0.0), 0);
+        assert_eq!(health_to_u8(0.001), 1);
+        assert_eq!(health_to_u8(1.0), 255);
+    }
+
+    #[test]
+    fn decode_garbage() {
+        let packet = Message::Bye.encode();
+        assert_eq!(Message::decode(&packet[1..]), None);
+        assert_eq!(Message::decode(b"CRMB\x63"), None);
+
+        let packet = Message::Reject {
+            reason: "Full".to_string(),
+        }
+        .encode();
+        assert_eq!(Message::decode(&packet[..packet.len() - 1]), None);
+        assert_eq!(Message::decode(&[&packet[..], &[0u8][..]].concat()), None);
+    }
+
+    #[test]
+    fn turn_the_short_way() {
+        let a = CarState {
+            position: Vector3::new(0.0, 0.0, 0.0),
+            yaw: 3.0,
+            velocity: Vector3::new(0.0, 0.0, 0.0),
+            yaw_rate: 0.0,
+            boost: 0.0,
+            health: [255; 4],
+            effects: Default::default(),
+            passed: 0,
+            finish_time: None,
+            lives: 3,
+            wins: 0,
+        };
+        let b = CarState {
+            position: Vector3::new(2.0, 0.0, 0.0),
+            yaw: -3.0,
+            passed: 1,
+            ..a
+        };
+        let half = a.lerp(&b, 0.5);
+        assert!((half.position[0] - 1.0).abs() < 1e-6);
+        assert!((half.yaw - (3.0 + std::f32::consts::PI - 3.0)).abs() < 1e-5);
+        assert_eq!(half.passed, 1);
+    }
+}
@@ -0,0 +1,295 @@
+// This file is part of Carambolage.
+
+// Carambolage is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Carambolage is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Carambolage.  If not, see <http://www.gnu.org/licenses/>.
+use super::protocol::{InputState, Message, Snapshot, MAX_CARS, MAX_PACKET_SIZE, PROTOCOL_VERSION};
+use super::{Lobby, TIMEOUT};
+use crate::game::car_definition::{CarDefinition, CarSelection};
+use crate::game::controller::Controller;
+use crate::game::scene::Scene;
+use log::{info, warn};
+use std::collections::VecDeque;
+use std::io::ErrorKind;
+use std::net::{SocketAddr, UdpSocket};
+use std::time::Instant;
+
+/// A snapshot is sent to every client after this many ticks.
+const SNAPSHOT_INTERVAL: u32 = 2;
+/// The lobby is sent again after this many ticks, in case a packet got lost.
+const LOBBY_INTERVAL: u32 = 60;
+/// Inputs queued beyond this are dropped, so a client that fell behind catches up.
+const MAX_QUEUED_INPUTS: usize = 4;
+
+/// A player on another computer.
+struct RemotePlayer {
+    address: SocketAddr,
+    /// Index of the car in `Scene::cars`.
+    car: usize,
+    /// Received inputs waiting for their tick, the oldest first.
+    queue: VecDeque<(u32, InputState)>,
+    /// Input of the last simulated tick, repeated while the queue is empty.
+    input: Option<(u32, InputState)>,
+    last_heard: Instant,
+}
+
+/// Host of a network game.
+///
+/// The host runs the only authoritative `Scene`. Clients send their inputs every tick and get
+/// snapshots of the whole scene back, see `Client`. Every client that joins gets a new car, the
+/// car of a client that left stays on the track without input.
+pub struct Server {
+    socket: UdpSocket,
+    tick_rate: u32,
+    players: Vec<RemotePlayer>,
+    is_racing: bool,
+    tick: u32,
+    /// Number of cars the clients were told about.
+    announced_cars: usize,
+}
+
+impl Server {
+    /// Listen for clients on `address`, e.g. `0.0.0.0:7878`, for a game simulated with `tick_rate`.
+    pub fn bind(address: &str, tick_rate: u32) -> Result<Server, String> {
+        let socket = UdpSocket::bind(address).map_err(|e| format!("Failed to listen on {}: {}", address, e))?;
+        socket
+            .set_nonblocking(true)
+            .map_err(|e| format!("Failed to listen on {}: {}", address, e))?;
+        info!("Hosting a network game on {}", address);
+        Ok(Server {
+            socket,
+            tick_rate,
+            players: Vec::new(),
+            is_racing: false,
+            tick: 0,
+            announced_cars: 0,
+        })
+    }
+
+    /// Address the host actually listens on, useful after binding port 0.
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.socket.local_addr().ok()
+    }
+
+    /// Handle all packets received since the last call and drop clients that timed out.
+    ///
+    /// Every client that joins gets a new car in the `scene`, the indices of these cars are
+    /// returned.
+    pub fn receive(&mut self, scene: &mut Scene) -> Vec<usize> {
+        let mut joined = Vec::new();
+        let mut buffer = [0; MAX_PACKET_SIZE];
+        loop {
+            let (size, address) = match self.socket.recv_from(&mut buffer) {
+                Ok(packet) => packet,
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => break,
+                // E.g. an ICMP port unreachable of a client that is gone, try again next tick.
+                Err(_) => break,
+            };
+            let message = match Message::decode(&buffer[..size]) {
+                Some(message) => message,
+                None => continue,
+            };
+            let player = self.players.iter().position(|p| p.address == address);
+            if let Some(player) = player {
+                self.players[player].last_heard = Instant::now();
+            }
+            match (message, player) {
+                (Message::Hello { version, car }, None) => {
+                    if let Some(id) = self.join(scene, address, version, &car) {
+                        joined.push(id);
+                    }
+                }
+                // The welcome got lost.
+                (Message::Hello { .. }, Some(player)) => self.welcome(scene, player),
+                (Message::Input { first, inputs }, Some(player)) => {
+                    let player = &mut self.players[player];
+                    let newest = player.queue.back().or_else(|| player.input.as_ref()).map(|&(tick, _)| tick);
+                    for (tick, input) in (first..).zip(inputs) {
+                        if newest.map_or(true, |newest| tick > newest) {
+                            player.queue.push_back((tick, input));
+                        }
+                    }
+                    while player.queue.len() > MAX_QUEUED_INPUTS {
+                        player.queue.pop_front();
+                    }
+                }
+                (Message::Bye, Some(player)) => {
+                    let player = self.players.remove(player);
+                    info!("Network player {} of car {} left", player.address, player.car + 1);
+                }
+                _ => {}
+            }
+        }
+
+        let now = Instant::now();
+        self.players.retain(|player| {
+            let is_alive = now.duration_since(player.last_heard) < TIMEOUT;
+            if !is_alive {
+                warn!("Network player {} of car {} timed out", player.address, player.car + 1);
+            }
+            is_alive
+        });
+        joined
+    }
+
+    /// Add a car for a new client or tell it why not.
+    fn join(&mut self, scene: &mut Scene, address: SocketAddr, version: u16, car: &CarSelection) -> Option<usize> {
+        let refusal = if version != PROTOCOL_VERSION {
+            Some(format!("Protocol version {} expected, not {}", PROTOCOL_VERSION, version))
+        } else if scene.cars.len() >= MAX_CARS {
+            Some(format!("The game is full with {} cars", MAX_CARS))
+        } else if !CarDefinition::available().contains(&car.car) {
+            // The name comes from the network, it must not reach the file system unchecked.
+            Some(format!("Unknown car '{}'", car.car))
+        } else {
+            scene.add_car(car).err().map(|e| {
+                warn!("{}", e);
+                format!("The host failed to load the car '{}'", car.car)
+            })
+        };
+        if let Some(reason) = refusal {
+            warn!("Network player {} refused: {}", address, reason);
+            self.send(&address, &Message::Reject { reason });
+            return None;
+        }
+
+        let id = scene.cars.len() - 1;
+        info!("Network player {} joins with car {} {}", address, id + 1, car);
+        self.players.push(RemotePlayer {
+            address,
+            car: id,
+            queue: VecDeque::new(),
+            input: None,
+            last_heard: Instant::now(),
+        });
+        self.welcome(scene, self.players.len() - 1);
+        Some(id)
+    }
+
+    fn welcome(&self, scene: &Scene, player: usize) {
+        let player = &self.players[player];
+        let welcome = Message::Welcome {
+            car: player.car as u32,
+            map: scene.level.name.clone(),
+            tick_rate: self.tick_rate,
+            cars: scene.selections.clone(),
+        };
+        self.send(&player.address, &welcome);
+        let lobby = self.lobby_message(scene);
+        self.send(&player.address, &lobby);
+    }
+
+    /// Overwrite the `input` of the cars of all clients with their input for the next tick.
+    ///
+    /// `input` grows with neutral controllers up to the cars of the clients, so their input is
+    /// never dropped, e.g. while the host has a menu open.
+    pub fn apply_inputs(&mut self, input: &mut Vec<Controller>) {
+        for player in &mut self.players {
+            if let Some(next) = player.queue.pop_front() {
+                player.input = Some(next);
+            }
+            if let Some((_, state)) = player.input {
+                if input.len() <= player.car {
+                    input.resize(player.car + 1, Controller::with_state(0., 0., false));
+                }
+                input[player.car] = state.controller();
+            }
+        }
+    }
+
+    /// Tell the clients about the `scene` after a tick.
+    pub fn send_state(&mut self, scene: &Scene) {
+        self.tick += 1;
+        if scene.cars.len() != self.announced_cars || self.tick % LOBBY_INTERVAL == 0 {
+            self.broadcast_lobby(scene);
+        }
+        if self.tick % SNAPSHOT_INTERVAL == 0 {
+            for player in &self.players {
+                let snapshot = Snapshot::from_scene(scene, self.tick, player.input.map(|(tick, _)| tick));
+                self.send(&player.address, &Message::Snapshot(snapshot));
+            }
+        }
+    }
+
+    /// Start or stop the race on all clients.
+    pub fn set_racing(&mut self, is_racing: bool, scene: &Scene) {
+        self.is_racing = is_racing;
+        self.broadcast_lobby(scene);
+    }
+
+    /// Number of connected clients.
+    pub fn players(&self) -> usize {
+        self.players.len()
+    }
+
+    /// Start over on `map` with the cars of all connected clients, e.g. to rotate the level.
+    ///
+    /// All other cars are dropped and the remaining ones are numbered in the order their clients
+    /// joined. Every client is welcomed again, so it loads the new scene.
+    pub fn load_map(&mut self, map: &str, scene: &Scene) -> Result<Scene, String> {
+        let cars: Vec<CarSelection> = self.players.iter().map(|player| scene.selections[player.car].clone()).collect();
+        let scene = Scene::new(map, &cars)?;
+        for (id, player) in self.players.iter_mut().enumerate() {
+            player.car = id;
+        }
+        for player in 0..self.players.len() {
+            self.welcome(&scene, player);
+        }
+        self.announced_cars = scene.cars.len();
+        Ok(scene)
+    }
+
+    /// Car `id` is driven by a player on another computer.
+    pub fn is_remote(&self, id: usize) -> bool {
+        self.players.iter().any(|player| player.car == id)
+    }
+
+    /// Tell all clients that the host is gone.
+    pub fn shutdown(&mut self) {
+        for player in &self.players {
+            self.send(&player.address, &Message::Bye);
+        }
+        self.players.clear();
+    }
+
+    pub fn lobby(&self, scene: &Scene) -> Lobby {
+        Lobby {
+            address: self.local_addr().map_or_else(String::new, |address| address.to_string()),
+            is_host: true,
+            cars: scene.selections.clone(),
+            local_cars: (0..scene.cars.len()).filter(|&id| !self.is_remote(id)).collect(),
+            is_racing: self.is_racing,
+            error: None,
+        }
+    }
+
+    fn lobby_message(&self, scene: &Scene) -> Message {
+        Message::Lobby {
+            cars: scene.selections.clone(),
+            is_racing: self.is_racing,
+        }
+    }
+
+    fn broadcast_lobby(&mut self, scene: &Scene) {
+        let lobby = self.lobby_message(scene);
+        for player in &self.players {
+            self.send(&player.address, &lobby);
+        }
+        self.announced_cars = scene.cars.len();
+    }
+
+    fn send(&self, address: &SocketAddr, message: &Message) {
+        if let Err(e) = self.socket.send_to(&message.encode(), address) {
+            warn!("Failed to send to {}: {}", address, e);
+        }
+    }
+}
@@ -0,0 +1,181 @@
+// This file is part of Carambolage.
+
+// Carambolage is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Carambolage is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Carambolage.  If not, see <http://www.gnu.org/licenses/>.
+use nalgebra::{inf, sup, Vector3};
+use std::cmp::Ordering;
+
+/// Axis aligned bounding box in world space.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Aabb {
+    pub mins: Vector3<f32>,
+    pub maxs: Vector3<f32>,
+}
+
+impl Aabb {
+    pub fn new(mins: Vector3<f32>, maxs: Vector3<f32>) -> Aabb {
+        Aabb { mins, maxs }
+    }
+
+    /// Return true if both boxes overlap or touch.
+    pub fn intersects(&self, other: &Aabb) -> bool {
+        (0..3).all(|i| self.mins[i] <= other.maxs[i] && other.mins[i] <= self.maxs[i])
+    }
+
+    /// Smallest box containing both boxes.
+    pub fn merged(&self, other: &Aabb) -> Aabb {
+        Aabb::new(inf(&self.mins, &other.mins), sup(&self.maxs, &other.maxs))
+    }
+
+    /// Box grown by `margin` in every direction.
+    pub fn loosened(&self, margin: f32) -> Aabb {
+        let margin = Vector3::new(margin, margin, margin);
+        Aabb::new(self.mins - margin, self.maxs + margin)
+    }
+
+    pub fn center(&self) -> Vector3<f32> {
+        (self.mins + self.maxs) * 0.5
+    }
+}
+
+/// Sweep and prune broad phase.
+///
+/// All boxes are sorted along the axis in which they are spread the most. Then we sweep along
+/// this axis and only test boxes for overlap whose intervals on the axis overlap. The order
+/// is kept between updates, so sorting is almost free as long as objects move smoothly.
+#[derive(Default)]
+pub struct SweepAndPrune {
+    order: Vec<usize>,
+    pairs: Vec<(usize, usize)>,
+}
+
+impl SweepAndPrune {
+    pub fn new() -> SweepAndPrune {
+        Default::default()
+    }
+
+    /// Find all pairs of overlapping boxes.
+    ///
+    /// The returned pairs are indices into `aabbs` with the smaller index first.
+    pub fn update(&mut self, aabbs: &[Aabb]) -> &[(usize, usize)] {
+        self.pairs.clear();
+        if self.order.len() != aabbs.len() {
+            self.order = (0..aabbs.len()).collect();
+        }
+        if aabbs.len() < 2 {
+            return &self.pairs;
+        }
+
+        let axis = Self::sweep_axis(aabbs);
+        self.order
+            .sort_by(|a, b| aabbs[*a].mins[axis].partial_cmp(&aabbs[*b].mins[axis]).unwrap_or(Ordering::Equal));
+
+        for (n, &i) in self.order.iter().enumerate() {
+            let max = aabbs[i].maxs[axis];
+            for &j in &self.order[n + 1..] {
+                if aabbs[j].mins[axis] > max {
+                    break;
+                }
+                if aabbs[i].intersects(&aabbs[j]) {
+                    self.pairs.push(if i < j { (i, j) } else { (j, i) });
+                }
+            }
+        }
+        &self.pairs
+    }
+
+    /// Axis with the largest variance of the box centers.
+    fn sweep_axis(aabbs: &[Aabb]) -> usize {
+        let n = aabbs.len() as f32;
+        let mut sum = Vector3::new(0., 0., 0.);
+        let mut sum_sq = Vector3::new(0., 0., 0.);
+        for aabb in aabbs {
+            let c = aabb.center();
+            sum += c;
+            sum_sq += c.component_mul(&c);
+        }
+        let variance = sum_sq / n - (sum / n).component_mul(&(sum / n));
+        if variance[0] >= variance[1] && variance[0] >= variance[2] {
+            0
+        } else if variance[1] >= variance[2] {
+            1
+        } else {
+            2
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Aabb, SweepAndPrune};
+    use nalgebra::Vector3;
+
+    /// Tiny deterministic pseudo random number generator in [0; 1).
+    fn random(seed: &mut u32) -> f32 {
+        *seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+        (*seed >> 8) as f32 / (1 << 24) as f32
+    }
+
+    fn random_aabbs(count: usize, seed: &mut u32) -> Vec<Aabb> {
+        (0..count)
+            .map(|_| {
+                let center = Vector3::new(random(seed) * 100.0, random(seed) * 40.0, random(seed));
+                let half = Vector3::new(random(seed) * 3.0, random(seed) * 3.0, 0.5);
+                Aabb::new(center - half, center + half)
+            })
+            .collect()
+    }
+
+    fn brute_force(aabbs: &[Aabb]) -> Vec<(usize, usize)> {
+        let mut pairs = Vec::new();
+        for i in 0..aabbs.len() {
+            for j in i + 1..aabbs.len() {
+                if aabbs[i].intersects(&aabbs[j]) {
+                    pairs.push((i, j));
+                }
+            }
+        }
+        pairs
+    }
+
+    #[test]
+    fn intersects() {
+        let a = Aabb::new(Vector3::new(0., 0., 0.), Vector3::new(1., 1., 1.));
+        let b = Aabb::new(Vector3::new(1., 0.5, 0.5), Vector3::new(2., 2., 2.));
+        let c = Aabb::new(Vector3::new(0., 1.5, 0.), Vector3::new(1., 2., 1.));
+        assert!(a.intersects(&b));
+        assert!(!a.intersects(&c));
+        assert!(a.merged(&c).intersects(&c));
+        assert!(a.loosened(0.5).intersects(&c));
+    }
+
+    #[test]
+    fn same_pairs_as_brute_force() {
+        let mut seed = 42;
+        let mut sap = SweepAndPrune::new();
+        for count in &[0, 1, 2, 8, 32, 128] {
+            let mut aabbs = random_aabbs(*count, &mut seed);
+            for _ in 0..5 {
+                let mut pairs = sap.update(&aabbs).to_vec();
+                pairs.sort();
+                assert_eq!(pairs, brute_force(&aabbs));
+
+                // Move everything a bit, the order from the last update gets reused.
+                for aabb in &mut aabbs {
+                    let offset = Vector3::new(random(&mut seed) - 0.5, random(&mut seed) - 0.5, 0.0);
+                    *aabb = Aabb::new(aabb.mins + offset, aabb.maxs + offset);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,242 @@
+// This file is part of Carambolage.
+
+// Carambolage is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Carambolage is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Carambolage.  If not, see <http://www.gnu.org/licenses/>.
+use super::RigidBody;
+use nalgebra::Vector3;
+
+/// A single contact point between two bodies.
+#[derive(Debug, Copy, Clone)]
+pub struct Contact {
+    /// Contact point in world space.
+    pub point: Vector3<f32>,
+    /// Unit contact normal in world space, pointing from the first to the second body.
+    pub normal: Vector3<f32>,
+    /// Penetration depth along the normal in [m].
+    pub depth: f32,
+}
+
+/// Resolve a contact between two bodies with impulses.
+///
+/// The bodies are pushed apart relative to their inverse mass, and normal and friction
+/// impulses are applied at the contact point, so off-center hits make the cars spin.
+/// Restitution and friction of both bodies are combined. Returns the normal impulse in [Ns].
+pub fn resolve_contact(body1: &mut RigidBody, body2: &mut RigidBody, contact: &Contact) -> f32 {
+    let restitution = body1.restitution.max(body2.restitution);
+    let friction = (body1.friction * body2.friction).sqrt();
+
+    separate(body1, body2, contact);
+    apply_contact_impulses(body1, body2, contact, restitution, friction)
+}
+
+/// Resolve a contact between a body and an immovable collider, e.g. the level.
+///
+/// The normal has to point from `body` into the collider. Returns the normal impulse in [Ns].
+pub fn resolve_static_contact(body: &mut RigidBody, contact: &Contact, restitution: f32, friction: f32) -> f32 {
+    let mut collider = RigidBody::new(0.0, Vector3::new(0., 0., 0.));
+    collider.position = contact.point;
+
+    separate(body, &mut collider, contact);
+    apply_contact_impulses(body, &mut collider, contact, restitution, friction)
+}
+
+/// Move both bodies out of each other, weighted by their inverse mass.
+fn separate(body1: &mut RigidBody, body2: &mut RigidBody, contact: &Contact) {
+    let inv_mass_sum = body1.inv_mass() + body2.inv_mass();
+    if inv_mass_sum <= 0.0 || contact.depth <= 0.0 {
+        return;
+    }
+    let correction = contact.normal * (contact.depth / inv_mass_sum);
+    body1.position -= correction * body1.inv_mass();
+    body2.position += correction * body2.inv_mass();
+}
+
+/// Apply the normal and the friction impulse and return the normal impulse.
+fn apply_contact_impulses(body1: &mut RigidBody, body2: &mut RigidBody, contact: &Contact, restitution: f32, friction: f32) -> f32 {
+    let n = contact.normal;
+    let r1 = contact.point - body1.position;
+    let r2 = contact.point - body2.position;
+
+    // Relative velocity of body 2 seen from body 1. Negative along the normal means approaching.
+    let rel_velocity = body2.velocity_at(&contact.point) - body1.velocity_at(&contact.point);
+    let normal_speed = rel_velocity.dot(&n);
+    if normal_speed >= 0.0 {
+        return 0.0;
+    }
+
+    let normal_mass = effective_mass(body1, body2, &r1, &r2, &n);
+    if normal_mass <= 0.0 {
+        return 0.0;
+    }
+    let j = -(1.0 + restitution) * normal_speed / normal_mass;
+    body1.apply_impulse_at(&(n * -j), &contact.point);
+    body2.apply_impulse_at(&(n * j), &contact.point);
+
+    // Coulomb friction along the remaining sliding direction.
+    let rel_velocity = body2.velocity_at(&contact.point) - body1.velocity_at(&contact.point);
+    let tangent = rel_velocity - n * rel_velocity.dot(&n);
+    let sliding_speed = tangent.norm();
+    if sliding_speed > 1e-6 {
+        let t = tangent / sliding_speed;
+        let tangent_mass = effective_mass(body1, body2, &r1, &r2, &t);
+        if tangent_mass > 0.0 {
+            let jt = (sliding_speed / tangent_mass).min(friction * j);
+            body1.apply_impulse_at(&(t * jt), &contact.point);
+            body2.apply_impulse_at(&(t * -jt), &contact.point);
+        }
+    }
+
+    j
+}
+
+/// Inverse of the mass both bodies show against an impulse along `dir` at the contact.
+fn effective_mass(body1: &RigidBody, body2: &RigidBody, r1: &Vector3<f32>, r2: &Vector3<f32>, dir: &Vector3<f32>) -> f32 {
+    // Only rotation around z is possible, so only the z-part of r x dir matters.
+    let rn1 = r1.cross(dir)[2];
+    let rn2 = r2.cross(dir)[2];
+    body1.inv_mass() + body2.inv_mass() + rn1 * rn1 * body1.inv_inertia() + rn2 * rn2 * body2.inv_inertia()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{resolve_contact, resolve_static_contact, Contact};
+    use crate::physx::RigidBody;
+    use nalgebra::Vector3;
+
+    /// Two cars facing each other on the x-axis, touching at the origin.
+    fn head_on(m1: f32, m2: f32, restitution: f32) -> (RigidBody, RigidBody, Contact) {
+        let half_extents = Vector3::new(1.0, 2.0, 0.5);
+        let mut car1 = RigidBody::new(m1, half_extents);
+        let mut car2 = RigidBody::new(m2, half_extents);
+        car1.position = Vector3::new(-1.0, 0.0, 0.0);
+        car2.position = Vector3::new(1.0, 0.0, 0.0);
+        car1.restitution = restitution;
+        car2.restitution = restitution;
+        car1.friction = 0.0;
+        car2.friction = 0.0;
+        let contact = Contact {
+            point: Vector3::new(0.0, 0.0, 0.0),
+            normal: Vector3::new(1.0, 0.0, 0.0),
+            depth: 0.0,
+        };
+        (car1, car2, contact)
+    }
+
+    fn assert_near(a: f32, b: f32) {
+        assert!((a - b).abs() < 1e-5, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn heavy_car_rams_light_car() {
+        let (mut car1, mut car2, contact) = head_on(3.0, 1.0, 0.5);
+        car1.velocity = Vector3::new(4.0, 0.0, 0.0);
+
+        let j = resolve_contact(&mut car1, &mut car2, &contact);
+
+        // v1' = (m1 v1 + m2 v2 - m2 e (v1 - v2)) / (m1 + m2)
+        // v2' = (m1 v1 + m2 v2 + m1 e (v1 - v2)) / (m1 + m2)
+        assert_near(car1.velocity[0], 2.5);
+        assert_near(car2.velocity[0], 4.5);
+        assert_near(j, 4.5);
+        assert_near(car1.velocity[0] * 3.0 + car2.velocity[0], 12.0);
+        assert_near(car1.angular_velocity[2], 0.0);
+        assert_near(car2.angular_velocity[2], 0.0);
+    }
+
+    #[test]
+    fn light_car_bounces_off_heavy_car() {
+        let (mut car1, mut car2, contact) = head_on(1.0, 3.0, 0.5);
+        car1.velocity = Vector3::new(4.0, 0.0, 0.0);
+
+        resolve_contact(&mut car1, &mut car2, &contact);
+
+        assert_near(car1.velocity[0], -0.5);
+        assert_near(car2.velocity[0], 1.5);
+    }
+
+    #[test]
+    fn inelastic_cars_stick_together() {
+        let (mut car1, mut car2, contact) = head_on(2.0, 1.0, 0.0);
+        car1.velocity = Vector3::new(3.0, 0.0, 0.0);
+        car2.velocity = Vector3::new(-3.0, 0.0, 0.0);
+
+        resolve_contact(&mut car1, &mut car2, &contact);
+
+        assert_near(car1.velocity[0], 1.0);
+        assert_near(car2.velocity[0], 1.0);
+    }
+
+    #[test]
+    fn separating_cars_are_ignored() {
+        let (mut car1, mut car2, contact) = head_on(1.0, 1.0, 1.0);
+        car1.velocity = Vector3::new(-1.0, 0.0, 0.0);
+
+        assert_eq!(resolve_contact(&mut car1, &mut car2, &contact), 0.0);
+        assert_eq!(car1.velocity[0], -1.0);
+        assert_eq!(car2.velocity[0], 0.0);
+    }
+
+    #[test]
+    fn side_hit_spins() {
+        // Car 1 hits the rear of car 2 from the left.
+        let (mut car1, mut car2, mut contact) = head_on(1.0, 1.0, 0.5);
+        car1.velocity = Vector3::new(4.0, 0.0, 0.0);
+        car2.position = Vector3::new(1.0, 1.5, 0.0);
+
+        resolve_contact(&mut car1, &mut car2, &contact);
+        // Pushing behind the center of mass to the right turns car 2 counterclockwise.
+        assert!(car2.angular_velocity[2] > 0.0);
+        // Car 1 is hit at its center line.
+        assert_near(car1.angular_velocity[2], 0.0);
+
+        // Less energy goes into linear motion than in a central hit.
+        assert!(car2.velocity[0] < 3.0);
+
+        contact.depth = 0.5;
+        let p1 = car1.position;
+        let p2 = car2.position;
+        resolve_contact(&mut car1, &mut car2, &contact);
+        assert_near((car2.position - p2)[0] - (car1.position - p1)[0], 0.5);
+    }
+
+    #[test]
+    fn friction_is_limited() {
+        let (mut car1, mut car2, contact) = head_on(1.0, 1.0, 0.0);
+        car1.friction = 0.5;
+        car2.friction = 0.5;
+        car1.velocity = Vector3::new(2.0, 10.0, 0.0);
+
+        let j = resolve_contact(&mut car1, &mut car2, &contact);
+
+        // The tangential impulse is limited by friction * normal impulse.
+        let tangential = 10.0 - car1.velocity[1];
+        assert!(tangential > 0.0);
+        assert!(tangential <= 0.5 * j + 1e-5);
+    }
+
+    #[test]
+    fn bounce_off_wall() {
+        let mut car = RigidBody::new(1.0, Vector3::new(1.0, 2.0, 0.5));
+        car.velocity = Vector3::new(5.0, 0.0, 0.0);
+        let contact = Contact {
+            point: Vector3::new(1.0, 0.0, 0.0),
+            normal: Vector3::new(1.0, 0.0, 0.0),
+            depth: 0.1,
+        };
+
+        resolve_static_contact(&mut car, &contact, 0.2, 0.0);
+
+        assert_near(car.velocity[0], -1.0);
+        assert_near(car.position[0], -0.1);
+    }
+}
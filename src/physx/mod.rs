@@ -1 +1,31 @@
+// This file is part of Carambolage.
 
+// Carambolage is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Carambolage is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Carambolage.  If not, see <http://www.gnu.org/licenses/>.
+
+/// Broad phase collision detection.
+mod broadphase;
+/// Impulse based collision response.
+mod collision;
+/// Broad and narrow phase collision detection and response of a whole scene.
+mod pipeline;
+/// Rigid body state and integration.
+mod rigidbody;
+
+pub use self::broadphase::*;
+pub use self::collision::*;
+pub use self::pipeline::*;
+pub use self::rigidbody::RigidBody;
+
+/// Gravitational acceleration in [m/s²].
+pub const GRAVITY: f32 = 9.81;
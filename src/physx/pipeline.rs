@@ -0,0 +1,289 @@
+// This file is part of Carambolage.
+
+// Carambolage is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Carambolage is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Carambolage.  If not, see <http://www.gnu.org/licenses/>.
+use super::{resolve_contact, resolve_static_contact, Aabb, Contact, RigidBody, SweepAndPrune};
+use nalgebra::{Isometry3, Vector3};
+use ncollide3d::query;
+use ncollide3d::shape::{Cuboid, Shape, ShapeHandle};
+
+/// Bodies are found by the broad phase a bit before they actually touch.
+const AABB_MARGIN: f32 = 0.05;
+
+/// Immovable collision geometry, e.g. the ground or the border of a level.
+pub struct StaticCollider {
+    pub isometry: Isometry3<f32>,
+    pub shape: ShapeHandle<f32>,
+    /// Bounciness of bodies hitting this collider.
+    pub restitution: f32,
+    /// Friction of bodies sliding along this collider.
+    pub friction: f32,
+    aabb: Aabb,
+}
+
+impl StaticCollider {
+    pub fn new<S: Shape<f32>>(isometry: Isometry3<f32>, shape: S, restitution: f32, friction: f32) -> StaticCollider {
+        let shape = ShapeHandle::new(shape);
+        let aabb = shape.aabb(&isometry);
+        let aabb = Aabb::new(aabb.mins().coords, aabb.maxs().coords);
+
+        StaticCollider {
+            isometry,
+            shape,
+            restitution,
+            friction,
+            aabb,
+        }
+    }
+
+    /// World space bounding box of the collider.
+    pub fn aabb(&self) -> &Aabb {
+        &self.aabb
+    }
+}
+
+/// Impulse a body got from a contact, e.g. to damage a car.
+#[derive(Debug, Copy, Clone)]
+pub struct Impact {
+    /// Index of the body in the slice passed to `CollisionPipeline::step()`.
+    pub body: usize,
+    /// Contact point in world space.
+    pub point: Vector3<f32>,
+    /// Impulse applied to the body in [Ns].
+    pub impulse: Vector3<f32>,
+}
+
+/// Collision detection and response for a whole scene.
+///
+/// A sweep and prune broad phase finds all pairs of bodies and static colliders whose bounding
+/// boxes overlap. Only these pairs are tested for an exact contact in the narrow phase, which
+/// is then resolved with impulses.
+#[derive(Default)]
+pub struct CollisionPipeline {
+    broad_phase: SweepAndPrune,
+    aabbs: Vec<Aabb>,
+    impacts: Vec<Impact>,
+}
+
+impl CollisionPipeline {
+    pub fn new() -> CollisionPipeline {
+        Default::default()
+    }
+
+    /// Detect and resolve collisions between all `bodies` and with the static `colliders`.
+    ///
+    /// Returns the impacts of this step, one per body and contact.
+    pub fn step(&mut self, bodies: &mut [&mut RigidBody], colliders: &[StaticCollider]) -> &[Impact] {
+        let num_bodies = bodies.len();
+        self.impacts.clear();
+        self.aabbs.clear();
+        self.aabbs.extend(bodies.iter().map(|body| body.aabb().loosened(AABB_MARGIN)));
+        self.aabbs.extend(colliders.iter().map(|collider| collider.aabb));
+
+        for &(a, b) in self.broad_phase.update(&self.aabbs) {
+            if a >= num_bodies {
+                // Static colliders never collide with each other.
+                continue;
+            }
+            if b < num_bodies {
+                let (left, right) = bodies.split_at_mut(b);
+                if let Some((contact, j)) = Self::collide_bodies(&mut left[a], &mut right[0]) {
+                    push_impact(&mut self.impacts, a, &contact, -j);
+                    push_impact(&mut self.impacts, b, &contact, j);
+                }
+            } else if let Some((contact, j)) = Self::collide_static(&mut bodies[a], &colliders[b - num_bodies]) {
+                push_impact(&mut self.impacts, a, &contact, -j);
+            }
+        }
+        &self.impacts
+    }
+
+    /// Narrow phase and response between two bodies, returns the contact and its normal impulse.
+    fn collide_bodies(body1: &mut RigidBody, body2: &mut RigidBody) -> Option<(Contact, f32)> {
+        let cuboid1 = Cuboid::new(body1.half_extents);
+        let cuboid2 = Cuboid::new(body2.half_extents);
+        let contact = query::contact(&body1.isometry(), &cuboid1, &body2.isometry(), &cuboid2, 0.0);
+        let contact = contact.and_then(|c| to_contact(&c))?;
+        Some((contact, resolve_contact(body1, body2, &contact)))
+    }
+
+    /// Narrow phase and response between a body and a static collider, returns the contact and its
+    /// normal impulse.
+    fn collide_static(body: &mut RigidBody, collider: &StaticCollider) -> Option<(Contact, f32)> {
+        let cuboid = Cuboid::new(body.half_extents);
+        let contact = query::contact(&body.isometry(), &cuboid, &collider.isometry, &*collider.shape, 0.0);
+        let contact = contact.and_then(|c| to_contact(&c))?;
+        Some((
+            contact,
+            resolve_static_contact(body, &contact, collider.restitution, collider.friction),
+        ))
+    }
+}
+
+/// Remember the normal impulse `j` along the contact normal applied to `body`.
+fn push_impact(impacts: &mut Vec<Impact>, body: usize, contact: &Contact, j: f32) {
+    if j != 0.0 {
+        impacts.push(Impact {
+            body,
+            point: contact.point,
+            impulse: contact.normal * j,
+        });
+    }
+}
+
+/// Convert a ncollide contact to a contact for the physics module.
+///
+/// Two perfectly aligned boxes can make the penetration solver fail, returning `NaN`s.
+/// Such a contact is dropped, the next step will find a proper one.
+fn to_contact(contact: &query::Contact<f32>) -> Option<Contact> {
+    if !contact.depth.is_finite() {
+        return None;
+    }
+    Some(Contact {
+        point: (contact.world1.coords + contact.world2.coords) * 0.5,
+        normal: contact.normal.into_inner(),
+        depth: contact.depth,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CollisionPipeline, StaticCollider};
+    use crate::physx::RigidBody;
+    use nalgebra::{zero, Isometry3, Vector3};
+    use ncollide3d::shape::Cuboid;
+    use time::PreciseTime;
+
+    /// Flat ground with four walls around a square of `2 * size` meters.
+    fn arena(size: f32) -> Vec<StaticCollider> {
+        let wall = |x: f32, y: f32, half_extents: Vector3<f32>| {
+            StaticCollider::new(Isometry3::new(Vector3::new(x, y, 0.0), zero()), Cuboid::new(half_extents), 0.3, 0.2)
+        };
+        vec![
+            StaticCollider::new(
+                Isometry3::new(Vector3::new(0., 0., -100.0), zero()),
+                Cuboid::new(Vector3::new(1_000.0, 1_000.0, 100.0)),
+                0.0,
+                0.0,
+            ),
+            wall(-size - 1.0, 0.0, Vector3::new(1.0, size, 2.0)),
+            wall(size + 1.0, 0.0, Vector3::new(1.0, size, 2.0)),
+            wall(0.0, -size - 1.0, Vector3::new(size, 1.0, 2.0)),
+            wall(0.0, size + 1.0, Vector3::new(size, 1.0, 2.0)),
+        ]
+    }
+
+    /// `count` cars on a grid, all racing towards the center of the arena.
+    fn cars(count: usize) -> Vec<RigidBody> {
+        let columns = (count as f32).sqrt().ceil() as usize;
+        (0..count)
+            .map(|i| {
+                let mut car = RigidBody::new(1.0, Vector3::new(0.5, 1.0, 0.4));
+                // Odd coordinates, so no car starts right in the center.
+                let x = (i % columns) as f32 * 4.0 - columns as f32 * 2.0 + 1.0;
+                let y = (i / columns) as f32 * 4.0 - columns as f32 * 2.0 + 3.0;
+                car.position = Vector3::new(x, y, 0.4);
+                car.rotation[2] = i as f32;
+                car.velocity = Vector3::new(-x, -y, 0.0).normalize() * 10.0;
+                car
+            })
+            .collect()
+    }
+
+    fn step(pipeline: &mut CollisionPipeline, cars: &mut [RigidBody], colliders: &[StaticCollider], dt: f32) {
+        for car in cars.iter_mut() {
+            car.integrate(dt);
+        }
+        let mut bodies: Vec<&mut RigidBody> = cars.iter_mut().collect();
+        pipeline.step(&mut bodies, colliders);
+    }
+
+    #[test]
+    fn cars_stay_in_arena() {
+        let colliders = arena(20.0);
+        let mut cars = cars(16);
+        let mut pipeline = CollisionPipeline::new();
+        for _ in 0..600 {
+            step(&mut pipeline, &mut cars, &colliders, 1.0 / 60.0);
+        }
+        for car in &cars {
+            assert!(car.position[0].abs() < 21.0 && car.position[1].abs() < 21.0);
+            assert!(car.position[2] > 0.0 && car.position[2] < 1.0);
+        }
+    }
+
+    #[test]
+    fn cars_push_each_other() {
+        let colliders = arena(20.0);
+        let mut cars = cars(2);
+        cars[0].position = Vector3::new(-1.0, 0.0, 0.4);
+        cars[0].rotation = zero();
+        cars[0].velocity = Vector3::new(5.0, 0.0, 0.0);
+        cars[1].position = Vector3::new(0.0, 0.0, 0.4);
+        cars[1].rotation = zero();
+        cars[1].velocity = zero();
+
+        let mut pipeline = CollisionPipeline::new();
+        step(&mut pipeline, &mut cars, &colliders, 1.0 / 60.0);
+        assert!(cars[1].velocity[0] > 1.0);
+        assert!(cars[0].velocity[0] < 5.0);
+    }
+
+    #[test]
+    fn report_impacts() {
+        let colliders = arena(20.0);
+        let mut cars = cars(2);
+        cars[0].position = Vector3::new(-1.0, 0.0, 0.4);
+        cars[0].rotation = zero();
+        cars[0].velocity = Vector3::new(5.0, 0.0, 0.0);
+        cars[1].position = Vector3::new(0.0, 0.0, 0.4);
+        cars[1].rotation = zero();
+        cars[1].velocity = zero();
+
+        let mut pipeline = CollisionPipeline::new();
+        for car in cars.iter_mut() {
+            car.integrate(1.0 / 60.0);
+        }
+        let mut bodies: Vec<&mut RigidBody> = cars.iter_mut().collect();
+        let impacts = pipeline.step(&mut bodies, &colliders);
+        let pushed = |body: usize| impacts.iter().filter(|i| i.body == body).map(|i| i.impulse[0]).sum::<f32>();
+        // Both cars get the same impulse in opposite directions.
+        assert!(pushed(1) > 1.0);
+        assert!((pushed(0) + pushed(1)).abs() < 1e-4);
+    }
+
+    /// Measure the average step time for 8, 32 and 128 cars.
+    ///
+    /// Run with `cargo test --release benchmark_step -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn benchmark_step() {
+        let ticks = 600;
+        let colliders = arena(60.0);
+        for count in &[8, 32, 128] {
+            let mut cars = cars(*count);
+            let mut pipeline = CollisionPipeline::new();
+
+            let start = PreciseTime::now();
+            for _ in 0..ticks {
+                step(&mut pipeline, &mut cars, &colliders, 1.0 / 60.0);
+            }
+            let duration = start.to(PreciseTime::now());
+            println!(
+                "{:>4} cars: {:>8.1} µs per step",
+                count,
+                duration.num_microseconds().unwrap() as f64 / f64::from(ticks)
+            );
+        }
+    }
+}
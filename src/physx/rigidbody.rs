@@ -0,0 +1,258 @@
+// This file is part of Carambolage.
+
+// Carambolage is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Carambolage is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Carambolage.  If not, see <http://www.gnu.org/licenses/>.
+use super::{Aabb, GRAVITY};
+use nalgebra::{zero, Isometry3, Rotation3, Vector3};
+
+/// A box shaped rigid body with linear and angular motion.
+///
+/// Our cars never roll over, so the body only rotates around the z-axis (yaw). `rotation` and
+/// `angular_velocity` are still stored as `Vector3` to play nicely with `Isometry3`, but their
+/// x and y components always stay 0.
+#[derive(Debug, Copy, Clone)]
+pub struct RigidBody {
+    pub position: Vector3<f32>, // position in world space
+    pub rotation: Vector3<f32>, // rotation in radians per axis
+    pub velocity: Vector3<f32>,
+    pub angular_velocity: Vector3<f32>,
+    /// Half size of the collision box in [m].
+    pub half_extents: Vector3<f32>,
+
+    /// Fraction of the linear velocity lost per second.
+    pub linear_damping: f32,
+    /// Fraction of the angular velocity lost per second.
+    pub angular_damping: f32,
+    /// Bounciness in collisions, 0 is fully inelastic and 1 fully elastic.
+    pub restitution: f32,
+    /// Coulomb friction coefficient in collisions.
+    pub friction: f32,
+
+    // Accumulated for the next `integrate()` call.
+    force: Vector3<f32>,
+    torque: f32,
+
+    mass: f32,
+    inv_mass: f32,
+    inertia: f32,
+    inv_inertia: f32,
+}
+
+impl RigidBody {
+    /// Create a new `RigidBody` resting at the origin.
+    ///
+    /// `mass` is the mass in [kg] and `half_extents` the half size of the body in [m], used to
+    /// calculate the moment of inertia around the z-axis. A `mass` of 0 creates an immovable body.
+    pub fn new(mass: f32, half_extents: Vector3<f32>) -> RigidBody {
+        let mut body = RigidBody {
+            position: zero(),
+            rotation: zero(),
+            velocity: zero(),
+            angular_velocity: zero(),
+            half_extents,
+            linear_damping: 0.0,
+            angular_damping: 0.0,
+            restitution: 0.3,
+            friction: 0.5,
+            force: zero(),
+            torque: 0.0,
+            mass: 0.0,
+            inv_mass: 0.0,
+            inertia: 0.0,
+            inv_inertia: 0.0,
+        };
+        body.set_mass(mass, half_extents);
+        body
+    }
+
+    /// Set the mass in [kg] and recalculate the inertia of a solid box with `half_extents`.
+    pub fn set_mass(&mut self, mass: f32, half_extents: Vector3<f32>) {
+        self.half_extents = half_extents;
+        // Inertia of a solid box around z: m * (w² + l²) / 12 with w = 2 * x, l = 2 * y.
+        let inertia = mass * (half_extents[0] * half_extents[0] + half_extents[1] * half_extents[1]) / 3.0;
+
+        self.mass = mass;
+        self.inv_mass = if mass > 0.0 { 1.0 / mass } else { 0.0 };
+        self.inertia = inertia;
+        self.inv_inertia = if inertia > 0.0 { 1.0 / inertia } else { 0.0 };
+    }
+
+    /// Mass in [kg].
+    pub fn mass(&self) -> f32 {
+        self.mass
+    }
+
+    /// Inverse mass, 0 for immovable bodies.
+    pub fn inv_mass(&self) -> f32 {
+        self.inv_mass
+    }
+
+    /// Moment of inertia around the z-axis.
+    pub fn inertia(&self) -> f32 {
+        self.inertia
+    }
+
+    /// Inverse moment of inertia around the z-axis, 0 for immovable bodies.
+    pub fn inv_inertia(&self) -> f32 {
+        self.inv_inertia
+    }
+
+    /// Unit vector pointing to the front of the body in world space.
+    pub fn forward(&self) -> Vector3<f32> {
+        Rotation3::new(self.rotation) * Vector3::new(0., 1., 0.)
+    }
+
+    /// Unit vector pointing to the right of the body in world space.
+    pub fn right(&self) -> Vector3<f32> {
+        Rotation3::new(self.rotation) * Vector3::new(1., 0., 0.)
+    }
+
+    /// Position and rotation as `Isometry3` for collision queries.
+    pub fn isometry(&self) -> Isometry3<f32> {
+        Isometry3::new(self.position, self.rotation)
+    }
+
+    /// World space bounding box of the rotated collision box.
+    pub fn aabb(&self) -> Aabb {
+        let rotation = Rotation3::new(self.rotation);
+        let rot = rotation.matrix();
+        let mut extents = Vector3::new(0., 0., 0.);
+        for i in 0..3 {
+            for j in 0..3 {
+                extents[i] += rot[(i, j)].abs() * self.half_extents[j];
+            }
+        }
+        Aabb::new(self.position - extents, self.position + extents)
+    }
+
+    /// Velocity of a point given in world space that is attached to this body.
+    pub fn velocity_at(&self, point: &Vector3<f32>) -> Vector3<f32> {
+        self.velocity + self.angular_velocity.cross(&(point - self.position))
+    }
+
+    /// Apply a force in [N] to the center of mass until the next `integrate()`.
+    pub fn apply_force(&mut self, force: &Vector3<f32>) {
+        self.force += force;
+    }
+
+    /// Apply a force in [N] at a point in world space until the next `integrate()`.
+    pub fn apply_force_at(&mut self, force: &Vector3<f32>, point: &Vector3<f32>) {
+        self.force += force;
+        self.torque += (point - self.position).cross(force)[2];
+    }
+
+    /// Apply a torque in [Nm] around the z-axis until the next `integrate()`.
+    pub fn apply_torque(&mut self, torque: f32) {
+        self.torque += torque;
+    }
+
+    /// Instantly change the momentum by an impulse in [Ns] applied to the center of mass.
+    pub fn apply_impulse(&mut self, impulse: &Vector3<f32>) {
+        self.velocity += impulse * self.inv_mass;
+    }
+
+    /// Instantly change the momentum by an impulse in [Ns] applied at a point in world space.
+    pub fn apply_impulse_at(&mut self, impulse: &Vector3<f32>, point: &Vector3<f32>) {
+        self.velocity += impulse * self.inv_mass;
+        self.angular_velocity[2] += (point - self.position).cross(impulse)[2] * self.inv_inertia;
+    }
+
+    /// Advance the body by the time step `dt` in [s].
+    ///
+    /// Uses semi-implicit Euler integration and clears all accumulated forces afterwards.
+    /// Immovable bodies (mass 0) are not affected by gravity or forces.
+    pub fn integrate(&mut self, dt: f32) {
+        if self.inv_mass > 0.0 {
+            self.velocity += (self.force * self.inv_mass + Vector3::new(0., 0., -GRAVITY)) * dt;
+            self.angular_velocity[2] += self.torque * self.inv_inertia * dt;
+        }
+        self.velocity *= (1.0 - self.linear_damping * dt).max(0.0);
+        self.angular_velocity *= (1.0 - self.angular_damping * dt).max(0.0);
+
+        self.position += self.velocity * dt;
+        self.rotation[2] += self.angular_velocity[2] * dt;
+
+        self.force = zero();
+        self.torque = 0.0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RigidBody;
+    use crate::physx::GRAVITY;
+    use nalgebra::Vector3;
+
+    fn body() -> RigidBody {
+        RigidBody::new(2.0, Vector3::new(1.0, 2.0, 0.5))
+    }
+
+    #[test]
+    fn constant_force() {
+        let mut body = body();
+        for _ in 0..100 {
+            body.apply_force(&Vector3::new(4.0, 0.0, 2.0 * GRAVITY));
+            body.integrate(0.01);
+        }
+        // a = F / m = 2 m/s² for one second.
+        assert!((body.velocity[0] - 2.0).abs() < 1e-4);
+        assert!(body.velocity[2].abs() < 1e-4);
+        assert!((body.position[0] - 1.01).abs() < 1e-3);
+    }
+
+    #[test]
+    fn coasting() {
+        let mut body = body();
+        body.velocity = Vector3::new(0.0, 5.0, 0.0);
+        body.apply_force(&Vector3::new(0.0, 0.0, 2.0 * GRAVITY));
+        body.integrate(1.0);
+        assert_eq!(body.velocity[1], 5.0);
+        assert_eq!(body.position[1], 5.0);
+
+        body.linear_damping = 0.5;
+        body.apply_force(&Vector3::new(0.0, 0.0, 2.0 * GRAVITY));
+        body.integrate(1.0);
+        assert_eq!(body.velocity[1], 2.5);
+    }
+
+    #[test]
+    fn force_at_offset_spins() {
+        let mut body = body();
+        // Push the front of the car to the right, it has to turn clockwise.
+        body.apply_force_at(&Vector3::new(1.0, 0.0, 0.0), &Vector3::new(0.0, 2.0, 0.0));
+        body.integrate(1.0);
+        assert!(body.angular_velocity[2] < 0.0);
+        assert!(body.rotation[2] < 0.0);
+        assert!((body.angular_velocity[2] + 2.0 * body.inv_inertia()).abs() < 1e-6);
+    }
+
+    #[test]
+    fn rotated_aabb() {
+        let mut body = body();
+        body.position = Vector3::new(1.0, 1.0, 0.0);
+        body.rotation[2] = std::f32::consts::FRAC_PI_2;
+        let aabb = body.aabb();
+        assert!((aabb.mins - Vector3::new(-1.0, 0.0, -0.5)).norm() < 1e-5);
+        assert!((aabb.maxs - Vector3::new(3.0, 2.0, 0.5)).norm() < 1e-5);
+    }
+
+    #[test]
+    fn immovable() {
+        let mut body = RigidBody::new(0.0, Vector3::new(1.0, 1.0, 1.0));
+        body.apply_force(&Vector3::new(1.0, 1.0, 1.0));
+        body.apply_impulse_at(&Vector3::new(1.0, 0.0, 0.0), &Vector3::new(0.0, 1.0, 0.0));
+        body.integrate(1.0);
+        assert_eq!(body.velocity, Vector3::new(0.0, 0.0, 0.0));
+        assert_eq!(body.angular_velocity, Vector3::new(0.0, 0.0, 0.0));
+    }
+}
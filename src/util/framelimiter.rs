@@ -20,16 +20,17 @@ use time::{Duration, PreciseTime};
 /// externally.
 /// # Example
 /// ```
-/// # fn main() {
-/// let frame_limiter = FrameLimiter::new(60);
+/// use carambolage::util::FrameLimiter;
+///
+/// let mut frame_limiter = FrameLimiter::new(60);
 /// // GameLoop {
-///     // Warning: delta_time will be one millisecond on first start().
+///     // Warning: delta_time will be one microsecond on first start().
 ///     let delta_time = frame_limiter.start();
+///     assert!(delta_time > 0.0);
 ///     // Game stuff
-///     frame_limiter.stop();
-///     assert!(false);
+///     let is_early = frame_limiter.stop();
+///     // Sleep for the rest of the frame if is_early.
 /// // }
-/// # }
 /// ```
 pub struct FrameLimiter {
     time_per_frame: Duration,
@@ -13,10 +13,18 @@
 // You should have received a copy of the GNU General Public License
 // along with Foobar.  If not, see <http://www.gnu.org/licenses/>.
 use nalgebra::{clamp, Vector2, Vector3};
+use std::env;
+use std::path::PathBuf;
 
 mod framelimiter;
+mod pack;
+mod resources;
+mod timestep;
 
 pub use self::framelimiter::FrameLimiter;
+pub use self::pack::Pack;
+pub use self::resources::{find_resource, resource, resource_names, Resource, ResourceLocator, RES_ENV};
+pub use self::timestep::FixedTimestep;
 
 /// Interpolate from a to b with a given factor.
 /// factor = 0.0 returns a
@@ -45,3 +53,23 @@ impl Lerp for f32 {
         a + (b - a) * f
     }
 }
+
+/// Directory for data of the user, e.g. the best laps of the time trial.
+///
+/// This is `%APPDATA%\carambolage` on Windows, `~/Library/Application Support/carambolage` on
+/// macOS and `$XDG_DATA_HOME/carambolage` or `~/.local/share/carambolage` elsewhere. Without any of
+/// these variables the working directory is used.
+pub fn data_dir() -> PathBuf {
+    let home = || env::var_os("HOME").map(PathBuf::from);
+    let base = if cfg!(windows) {
+        env::var_os("APPDATA").map(PathBuf::from)
+    } else if cfg!(target_os = "macos") {
+        home().map(|home| home.join("Library").join("Application Support"))
+    } else {
+        env::var_os("XDG_DATA_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| home().map(|home| home.join(".local").join("share")))
+    };
+    base.map_or_else(|| PathBuf::from("."), |base| base.join("carambolage"))
+}
@@ -0,0 +1,239 @@
+// This file is part of Carambolage.
+
+// Carambolage is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Carambolage is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Carambolage.  If not, see <http://www.gnu.org/licenses/>.
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+/// First bytes of every pack file.
+const MAGIC: &[u8; 8] = b"CRBPACK1";
+/// Longest path in the index, anything longer is a broken pack.
+const MAX_NAME_LENGTH: u32 = 4096;
+
+/// All files of a resource directory in a single file, e.g. `res.pack` for `res`.
+///
+/// The file starts with an index, so a single file is read without reading the whole pack.
+/// All numbers are little endian:
+///
+/// ```text
+/// "CRBPACK1"
+/// u32 number of files
+/// for every file:
+///     u32 length of the path, the path relative to the directory with `/` separators
+///     u64 offset of the content from the start of the pack, u64 length of the content
+/// contents of all files
+/// ```
+#[derive(Debug, PartialEq)]
+pub struct Pack {
+    path: PathBuf,
+    /// Offset and length of every file.
+    files: BTreeMap<String, (u64, u64)>,
+}
+
+impl Pack {
+    /// Read the index of the pack at `path`.
+    ///
+    /// The index is checked against the size of the pack, so a broken pack fails here instead of
+    /// allocating huge buffers later.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Pack> {
+        let path = path.as_ref().to_path_buf();
+        let file = File::open(&path)?;
+        let size = file.metadata()?.len();
+        let mut reader = BufReader::new(file);
+        let mut magic = [0; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid("not a pack file".to_string()));
+        }
+
+        let mut files = BTreeMap::new();
+        for _ in 0..read_u32(&mut reader)? {
+            let name_length = read_u32(&mut reader)?;
+            if name_length > MAX_NAME_LENGTH {
+                return Err(invalid(format!("path of {} bytes in the index", name_length)));
+            }
+            let mut name = vec![0; name_length as usize];
+            reader.read_exact(&mut name)?;
+            let name = String::from_utf8(name).map_err(|e| invalid(format!("path in the index is not UTF-8: {}", e)))?;
+            let offset = read_u64(&mut reader)?;
+            let length = read_u64(&mut reader)?;
+            if offset.checked_add(length).map_or(true, |end| end > size) {
+                return Err(invalid(format!("{} reaches past the end of the pack", name)));
+            }
+            files.insert(name, (offset, length));
+        }
+        Ok(Pack { path, files })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Paths of all files in the pack, sorted alphabetically.
+    pub fn files(&self) -> impl Iterator<Item = &str> {
+        self.files.keys().map(String::as_str)
+    }
+
+    pub fn contains(&self, file: &str) -> bool {
+        self.files.contains_key(file)
+    }
+
+    /// Content of `file`, a path relative to the packed directory like "cars/kart.toml".
+    pub fn read(&self, file: &str) -> io::Result<Vec<u8>> {
+        let &(offset, length) = self
+            .files
+            .get(file)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("{} is not in the pack", file)))?;
+        let mut reader = File::open(&self.path)?;
+        reader.seek(SeekFrom::Start(offset))?;
+        // The pack may have been truncated since it was opened, so don't trust the length here.
+        let mut content = Vec::new();
+        reader.take(length).read_to_end(&mut content)?;
+        if content.len() as u64 != length {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "pack file is truncated"));
+        }
+        Ok(content)
+    }
+
+    /// Pack all files below `dir` into a new pack at `output`, returns the number of files.
+    pub fn create<P: AsRef<Path>, Q: AsRef<Path>>(dir: P, output: Q) -> io::Result<usize> {
+        let dir = dir.as_ref();
+        let mut files = Vec::new();
+        collect_files(dir, dir, &mut files)?;
+        files.sort();
+
+        let names: Vec<String> = files
+            .iter()
+            .map(|file| {
+                let components: Vec<String> = file.components().map(|c| c.as_os_str().to_string_lossy().into_owned()).collect();
+                components.join("/")
+            })
+            .collect();
+        let lengths = files
+            .iter()
+            .map(|file| fs::metadata(dir.join(file)).map(|metadata| metadata.len()))
+            .collect::<io::Result<Vec<u64>>>()?;
+
+        let index_length: usize = names.iter().map(|name| 4 + name.len() + 16).sum();
+        let mut offset = (MAGIC.len() + 4 + index_length) as u64;
+        let mut writer = BufWriter::new(File::create(output)?);
+        writer.write_all(MAGIC)?;
+        writer.write_all(&(files.len() as u32).to_le_bytes())?;
+        for (name, length) in names.iter().zip(&lengths) {
+            writer.write_all(&(name.len() as u32).to_le_bytes())?;
+            writer.write_all(name.as_bytes())?;
+            writer.write_all(&offset.to_le_bytes())?;
+            writer.write_all(&length.to_le_bytes())?;
+            offset += length;
+        }
+        for (file, &length) in files.iter().zip(&lengths) {
+            // A file changing while it is packed would break the index.
+            let copied = io::copy(&mut File::open(dir.join(file))?.take(length), &mut writer)?;
+            if copied != length {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, format!("{} changed", file.display())));
+            }
+        }
+        writer.flush()?;
+        Ok(files.len())
+    }
+}
+
+/// Paths of all files below `dir`, relative to `root`.
+fn collect_files(root: &Path, dir: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_files(root, &path, files)?;
+        } else if let Ok(file) = path.strip_prefix(root) {
+            files.push(file.to_path_buf());
+        }
+    }
+    Ok(())
+}
+
+fn invalid(reason: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, reason)
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_u64<R: Read>(reader: &mut R) -> io::Result<u64> {
+    let mut bytes = [0; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Pack;
+    use std::env;
+    use std::fs;
+
+    #[test]
+    fn pack_and_read() {
+        let dir = env::temp_dir().join("carambolage-pack");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("res/models/maps")).unwrap();
+        fs::write(dir.join("res/models/maps/arena.toml"), "name = \"Arena\"").unwrap();
+        fs::write(dir.join("res/models/c01.obj"), "v 0 0 0").unwrap();
+        fs::write(dir.join("res/empty.txt"), "").unwrap();
+
+        assert_eq!(Pack::create(dir.join("res"), dir.join("res.pack")).unwrap(), 3);
+        let pack = Pack::open(dir.join("res.pack")).unwrap();
+        assert_eq!(
+            pack.files().collect::<Vec<_>>(),
+            vec!["empty.txt", "models/c01.obj", "models/maps/arena.toml"]
+        );
+        assert_eq!(pack.read("models/maps/arena.toml").unwrap(), b"name = \"Arena\"");
+        assert_eq!(pack.read("models/c01.obj").unwrap(), b"v 0 0 0");
+        assert!(pack.read("empty.txt").unwrap().is_empty());
+        assert!(pack.read("models/c02.obj").is_err());
+        assert!(Pack::open(dir.join("res/empty.txt")).is_err());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn broken_index() {
+        let dir = env::temp_dir().join("carambolage-broken-pack");
+        fs::create_dir_all(&dir).unwrap();
+        let entry = |name_length: u32, name: &[u8], offset: u64, length: u64| {
+            let mut pack = b"CRBPACK1".to_vec();
+            pack.extend_from_slice(&1u32.to_le_bytes());
+            pack.extend_from_slice(&name_length.to_le_bytes());
+            pack.extend_from_slice(name);
+            pack.extend_from_slice(&offset.to_le_bytes());
+            pack.extend_from_slice(&length.to_le_bytes());
+            pack.extend_from_slice(b"content");
+            pack
+        };
+        let open = |pack: Vec<u8>| {
+            fs::write(dir.join("broken.pack"), pack).unwrap();
+            Pack::open(dir.join("broken.pack"))
+        };
+
+        assert!(open(entry(1, b"a", 33, 7)).is_ok());
+        // Huge path or content lengths are refused before anything is allocated.
+        assert!(open(entry(u32::max_value(), b"a", 33, 7)).is_err());
+        assert!(open(entry(1, b"a", 33, u64::max_value())).is_err());
+        assert!(open(entry(1, b"a", 33, 8)).is_err());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
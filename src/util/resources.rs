@@ -0,0 +1,256 @@
+// This file is part of Carambolage.
+
+// Carambolage is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Carambolage is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Carambolage.  If not, see <http://www.gnu.org/licenses/>.
+use super::{data_dir, Pack};
+use log::warn;
+use std::collections::BTreeSet;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Environment variable with the directory of the shipped resources, `--res` sets it too.
+pub const RES_ENV: &str = "CARAMBOLAGE_RES";
+
+thread_local! {
+    /// Locator of this installation, built on first use, so `--res` has to be applied before.
+    static LOCATOR: ResourceLocator = ResourceLocator::from_env();
+}
+
+/// A file found by the `ResourceLocator`, either a loose file or a file in a `Pack`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Resource {
+    File(PathBuf),
+    /// The pack and the path of the file in the pack.
+    Packed(Arc<Pack>, String),
+}
+
+impl Resource {
+    pub fn read(&self) -> io::Result<Vec<u8>> {
+        match self {
+            Resource::File(path) => fs::read(path),
+            Resource::Packed(pack, file) => pack.read(file),
+        }
+    }
+
+    pub fn read_to_string(&self) -> io::Result<String> {
+        String::from_utf8(self.read()?).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    /// Path for messages, a packed file is shown inside its pack like `res.pack/cars/kart.toml`.
+    pub fn path(&self) -> PathBuf {
+        match self {
+            Resource::File(path) => path.clone(),
+            Resource::Packed(pack, file) => pack.path().join(file),
+        }
+    }
+}
+
+impl fmt::Display for Resource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.path().display())
+    }
+}
+
+/// Finds resources like models, textures and manifests in several directories.
+///
+/// Every directory has the layout of `res`, e.g. `models/maps/race_track_1.toml`, and can be
+/// packed into a `Pack` next to it, e.g. `res.pack`. A file in an earlier directory overrides the
+/// file with the same path in all later ones, so a mod only needs to contain the files it changes.
+/// Loose files override all packed files, so shipped packs can be tried out with single changed
+/// files.
+///
+/// The indices of the packs are read once, when the locator is created.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResourceLocator {
+    dirs: Vec<PathBuf>,
+    /// Packs of all directories, in the order of the directories.
+    packs: Vec<Arc<Pack>>,
+}
+
+impl ResourceLocator {
+    /// Search `dirs` in order.
+    pub fn new(dirs: Vec<PathBuf>) -> ResourceLocator {
+        let packs = dirs
+            .iter()
+            .map(|dir| dir.with_extension("pack"))
+            .filter(|path| path.is_file())
+            .filter_map(|path| match Pack::open(&path) {
+                Ok(pack) => Some(Arc::new(pack)),
+                Err(e) => {
+                    warn!("Failed to open {}: {}", path.display(), e);
+                    None
+                }
+            })
+            .collect();
+        ResourceLocator { dirs, packs }
+    }
+
+    /// Search the directories of this installation, from the highest to the lowest priority:
+    ///
+    /// 1. `mods` in the data dir of the user, e.g. `~/.local/share/carambolage/mods`
+    /// 2. `$CARAMBOLAGE_RES`, set by `--res`
+    /// 3. `res` next to the executable
+    /// 4. `res` in the working directory
+    /// 5. `carambolage/res` in every `$XDG_DATA_DIRS`, e.g. `/usr/share/carambolage/res`
+    pub fn from_env() -> ResourceLocator {
+        let mut dirs = vec![data_dir().join("mods")];
+        if let Some(root) = env::var_os(RES_ENV).filter(|root| !root.is_empty()) {
+            dirs.push(PathBuf::from(root));
+        }
+        if let Some(exe_dir) = env::current_exe().ok().as_ref().and_then(|exe| exe.parent()) {
+            dirs.push(exe_dir.join("res"));
+        }
+        dirs.push(PathBuf::from("res"));
+        if !cfg!(windows) {
+            let data_dirs = env::var_os("XDG_DATA_DIRS")
+                .filter(|dirs| !dirs.is_empty())
+                .unwrap_or_else(|| "/usr/local/share:/usr/share".into());
+            dirs.extend(env::split_paths(&data_dirs).map(|dir| dir.join("carambolage").join("res")));
+        }
+        ResourceLocator::new(dirs)
+    }
+
+    /// All searched directories, including ones that do not exist.
+    pub fn dirs(&self) -> &[PathBuf] {
+        &self.dirs
+    }
+
+    /// The resource `file` with `/` separators, e.g. "textures/car-blue.png", from the first
+    /// directory that contains it or else from the first pack.
+    pub fn find(&self, file: &str) -> Option<Resource> {
+        if let Some(path) = self.dirs.iter().map(|dir| dir.join(file)).find(|path| path.is_file()) {
+            return Some(Resource::File(path));
+        }
+        self.packs
+            .iter()
+            .find(|pack| pack.contains(file))
+            .map(|pack| Resource::Packed(pack.clone(), file.to_string()))
+    }
+
+    /// Names of the files in `dir` of all directories and packs with `extension`, without the
+    /// extension.
+    ///
+    /// Names are sorted alphabetically and overridden files are only listed once.
+    pub fn names(&self, dir: &str, extension: &str) -> Vec<String> {
+        let mut files: BTreeSet<PathBuf> = BTreeSet::new();
+        for entries in self.dirs.iter().filter_map(|root| fs::read_dir(root.join(dir)).ok()) {
+            files.extend(entries.filter_map(|entry| entry.ok()).map(|entry| entry.path()));
+        }
+        for pack in &self.packs {
+            files.extend(
+                pack.files()
+                    .map(Path::new)
+                    .filter(|file| file.parent() == Some(Path::new(dir)))
+                    .map(Path::to_path_buf),
+            );
+        }
+        let names: BTreeSet<String> = files
+            .iter()
+            .filter(|path| path.extension().map_or(false, |ext| ext == extension))
+            .filter_map(|path| path.file_stem().map(|stem| stem.to_string_lossy().into_owned()))
+            .collect();
+        names.into_iter().collect()
+    }
+}
+
+/// The resource `file` relative to `res` with `/` separators, e.g. "textures/car-blue.png".
+///
+/// Searches the directories of `ResourceLocator::from_env()`, which is built once per thread. A
+/// missing file is looked for in `res` in the working directory, so errors name the usual place of
+/// it.
+pub fn resource(file: &str) -> Resource {
+    find_resource(file).unwrap_or_else(|| Resource::File(Path::new("res").join(file)))
+}
+
+/// The resource `file` if it exists, see `resource()`.
+pub fn find_resource(file: &str) -> Option<Resource> {
+    LOCATOR.with(|locator| locator.find(file))
+}
+
+/// Names of the resources in `dir` with `extension`, see `ResourceLocator::names()`.
+pub fn resource_names(dir: &str, extension: &str) -> Vec<String> {
+    LOCATOR.with(|locator| locator.names(dir, extension))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Resource, ResourceLocator};
+    use crate::util::Pack;
+    use std::env;
+    use std::fs;
+    use std::path::PathBuf;
+    use std::sync::Arc;
+
+    /// Empty directory for a test, removed on creation.
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("carambolage-resources-{}", name));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn write(dir: &PathBuf, file: &str) {
+        let path = dir.join(file);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, file).unwrap();
+    }
+
+    #[test]
+    fn mods_override_shipped_files() {
+        let root = test_dir("mods");
+        let (mods, shipped) = (root.join("mods"), root.join("res"));
+        write(&shipped, "cars/kart.toml");
+        write(&shipped, "cars/formula.toml");
+        write(&mods, "cars/kart.toml");
+        write(&mods, "cars/monster.toml");
+
+        let locator = ResourceLocator::new(vec![mods.clone(), root.join("missing"), shipped.clone()]);
+        assert_eq!(locator.find("cars/kart.toml"), Some(Resource::File(mods.join("cars/kart.toml"))));
+        assert_eq!(
+            locator.find("cars/formula.toml"),
+            Some(Resource::File(shipped.join("cars/formula.toml")))
+        );
+        assert_eq!(locator.find("cars/truck.toml"), None);
+        assert_eq!(locator.names("cars", "toml"), vec!["formula", "kart", "monster"]);
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn loose_files_override_packed_files() {
+        let root = test_dir("packed");
+        let (mods, shipped) = (root.join("mods"), root.join("res"));
+        write(&shipped, "cars/kart.toml");
+        write(&shipped, "cars/formula.toml");
+        write(&shipped, "models/c01.obj");
+        Pack::create(&shipped, root.join("res.pack")).unwrap();
+        fs::remove_file(shipped.join("cars/formula.toml")).unwrap();
+        fs::remove_file(shipped.join("models/c01.obj")).unwrap();
+        write(&mods, "cars/monster.toml");
+
+        let locator = ResourceLocator::new(vec![mods.clone(), shipped.clone()]);
+        assert_eq!(locator.find("cars/kart.toml"), Some(Resource::File(shipped.join("cars/kart.toml"))));
+        let formula = locator.find("cars/formula.toml").unwrap();
+        let pack = Arc::new(Pack::open(root.join("res.pack")).unwrap());
+        assert_eq!(formula, Resource::Packed(pack, "cars/formula.toml".to_string()));
+        assert_eq!(formula.read_to_string().unwrap(), "cars/formula.toml");
+        assert_eq!(formula.path(), root.join("res.pack/cars/formula.toml"));
+        assert_eq!(locator.names("cars", "toml"), vec!["formula", "kart", "monster"]);
+        assert!(locator.names("models", "toml").is_empty());
+
+        fs::remove_dir_all(root).unwrap();
+    }
+}
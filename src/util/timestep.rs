@@ -0,0 +1,108 @@
+// This file is part of Carambolage.
+
+// Carambolage is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Carambolage is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Carambolage.  If not, see <http://www.gnu.org/licenses/>.
+
+/// Never simulate more than this many seconds in a single frame.
+///
+/// If the game freezes for a moment (window dragging, loading) we drop the time instead of
+/// trying to catch up with hundreds of simulation steps.
+const MAX_FRAME_TIME: f32 = 0.25;
+
+/// Accumulates the variable frame time and splits it into fixed simulation steps.
+///
+/// # Example
+/// ```
+/// use carambolage::util::FixedTimestep;
+///
+/// let mut timestep = FixedTimestep::new(60);
+/// let mut ticks = 0;
+/// // Once per rendered frame, here a frame that took 60ms.
+/// for _ in 0..timestep.advance(0.06) {
+///     // Simulate one step of timestep.dt() seconds.
+///     ticks += 1;
+/// }
+/// // Draw the state interpolated by timestep.alpha().
+/// assert_eq!(ticks, 3);
+/// assert!(timestep.alpha() > 0.0 && timestep.alpha() < 1.0);
+/// ```
+pub struct FixedTimestep {
+    dt: f32,
+    accumulator: f32,
+}
+
+impl FixedTimestep {
+    /// Create a new `FixedTimestep` running the simulation with `tick_rate` steps per second.
+    pub fn new(tick_rate: u32) -> FixedTimestep {
+        FixedTimestep {
+            dt: 1.0 / tick_rate.max(1) as f32,
+            accumulator: 0.0,
+        }
+    }
+
+    /// Add the elapsed `frame_time` in seconds and return how many steps have to be simulated.
+    pub fn advance(&mut self, frame_time: f32) -> u32 {
+        self.accumulator += frame_time.max(0.0).min(MAX_FRAME_TIME);
+
+        let mut steps = 0;
+        while self.accumulator >= self.dt {
+            self.accumulator -= self.dt;
+            steps += 1;
+        }
+        steps
+    }
+
+    /// Duration of a single simulation step in seconds.
+    pub fn dt(&self) -> f32 {
+        self.dt
+    }
+
+    /// How far we are between the last and the next simulation step in [0.0; 1.0).
+    ///
+    /// Use it to interpolate between the previous and the current simulation state for rendering.
+    pub fn alpha(&self) -> f32 {
+        self.accumulator / self.dt
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FixedTimestep;
+
+    #[test]
+    fn steps_do_not_depend_on_frame_rate() {
+        for fps in &[30, 60, 144, 1000] {
+            let mut timestep = FixedTimestep::new(60);
+            let frame_time = 1.0 / *fps as f32;
+            let frames = *fps * 10;
+            let steps: u32 = (0..frames).map(|_| timestep.advance(frame_time)).sum();
+            assert!((steps as i32 - 600).abs() <= 1, "{} fps: {} steps", fps, steps);
+            assert!(timestep.alpha() >= 0.0 && timestep.alpha() < 1.0);
+        }
+    }
+
+    #[test]
+    fn alpha() {
+        let mut timestep = FixedTimestep::new(10);
+        assert_eq!(timestep.advance(0.05), 0);
+        assert!((timestep.alpha() - 0.5).abs() < 1e-5);
+        assert_eq!(timestep.advance(0.075), 1);
+        assert!((timestep.alpha() - 0.25).abs() < 1e-4);
+    }
+
+    #[test]
+    fn long_frames_are_clamped() {
+        let mut timestep = FixedTimestep::new(60);
+        assert_eq!(timestep.advance(10.0), 15);
+    }
+}